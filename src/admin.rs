@@ -0,0 +1,497 @@
+use crate::app::DeviceConfiguration;
+use crate::ipc::IPCMessage;
+use crate::tabs::nodes::requests::request_packet;
+use crate::util::{self, get_secs};
+use crate::{ADMIN_TARGET, DEVICE_CONFIG, REMOTE_SESSIONS};
+use anyhow::{bail, Result};
+use itertools::Itertools;
+use meshtastic::protobufs::admin_message::{ConfigType, ModuleConfigType, PayloadVariant};
+use meshtastic::protobufs::to_radio::PayloadVariant::Packet;
+use meshtastic::protobufs::{
+    config, module_config, AdminMessage, Config, ModuleConfig, PortNum, ToRadio,
+};
+use meshtastic::Message;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// The firmware forgets a session passkey 300 seconds after handing it out; we stop trusting
+/// ours a little earlier so that a change doesn't race the expiry.
+const PASSKEY_LIFETIME_SECS: u64 = 280;
+/// How long we wait on a config section before asking the remote node for it again.
+const SECTION_RETRY_SECS: u64 = 60;
+const CHANNEL_SLOTS: u32 = 8;
+const REBOOT_DELAY_SECS: i32 = 5;
+
+/// Which node the config tabs are showing and editing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AdminTarget {
+    #[default]
+    Local,
+    Remote(u32),
+}
+
+impl fmt::Display for AdminTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdminTarget::Local => write!(f, "local radio"),
+            AdminTarget::Remote(id) => write!(f, "REMOTE !{id:08x}"),
+        }
+    }
+}
+
+/// A piece of a node's configuration that can be asked for with a single admin message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConfigSection {
+    Config(ConfigType),
+    Module(ModuleConfigType),
+    Channel(u32),
+    /// The canned message list, which the firmware keeps apart from the module config.
+    CannedMessages,
+}
+
+impl ConfigSection {
+    fn request(self) -> PayloadVariant {
+        match self {
+            ConfigSection::Config(c) => PayloadVariant::GetConfigRequest(c as i32),
+            ConfigSection::Module(m) => PayloadVariant::GetModuleConfigRequest(m as i32),
+            // channel requests are one-based so that index zero never looks like an unset field.
+            ConfigSection::Channel(index) => PayloadVariant::GetChannelRequest(index + 1),
+            ConfigSection::CannedMessages => {
+                PayloadVariant::GetCannedMessageModuleMessagesRequest(true)
+            }
+        }
+    }
+
+    fn of_config(variant: &config::PayloadVariant) -> Self {
+        use config::PayloadVariant as cpv;
+        ConfigSection::Config(match variant {
+            cpv::Device(_) => ConfigType::DeviceConfig,
+            cpv::Position(_) => ConfigType::PositionConfig,
+            cpv::Power(_) => ConfigType::PowerConfig,
+            cpv::Network(_) => ConfigType::NetworkConfig,
+            cpv::Display(_) => ConfigType::DisplayConfig,
+            cpv::Lora(_) => ConfigType::LoraConfig,
+            cpv::Bluetooth(_) => ConfigType::BluetoothConfig,
+            cpv::Security(_) => ConfigType::SecurityConfig,
+            cpv::Sessionkey(_) => ConfigType::SessionkeyConfig,
+            cpv::DeviceUi(_) => ConfigType::DeviceuiConfig,
+        })
+    }
+
+    fn of_module(variant: &module_config::PayloadVariant) -> Self {
+        use module_config::PayloadVariant as mpv;
+        ConfigSection::Module(match variant {
+            mpv::Mqtt(_) => ModuleConfigType::MqttConfig,
+            mpv::Serial(_) => ModuleConfigType::SerialConfig,
+            mpv::ExternalNotification(_) => ModuleConfigType::ExtnotifConfig,
+            mpv::StoreForward(_) => ModuleConfigType::StoreforwardConfig,
+            mpv::RangeTest(_) => ModuleConfigType::RangetestConfig,
+            mpv::Telemetry(_) => ModuleConfigType::TelemetryConfig,
+            mpv::CannedMessage(_) => ModuleConfigType::CannedmsgConfig,
+            mpv::Audio(_) => ModuleConfigType::AudioConfig,
+            mpv::RemoteHardware(_) => ModuleConfigType::RemotehardwareConfig,
+            mpv::NeighborInfo(_) => ModuleConfigType::NeighborinfoConfig,
+            mpv::AmbientLighting(_) => ModuleConfigType::AmbientlightingConfig,
+            mpv::DetectionSensor(_) => ModuleConfigType::DetectionsensorConfig,
+            mpv::Paxcounter(_) => ModuleConfigType::PaxcounterConfig,
+        })
+    }
+}
+
+/// A config change bound for whichever node is the current admin target.
+#[derive(Debug, Clone)]
+pub enum ConfigChange {
+    Config(config::PayloadVariant),
+    Module(module_config::PayloadVariant),
+}
+
+/// What we know of a remote node we've opened an admin session with.
+#[derive(Debug, Clone, Default)]
+pub struct RemoteSession {
+    pub config: DeviceConfiguration,
+    session_passkey: Vec<u8>,
+    passkey_received: u64,
+    received: HashSet<ConfigSection>,
+    requested: HashMap<ConfigSection, u64>,
+    /// Every change we've sent to this node, oldest first.
+    pub changes: Vec<String>,
+}
+
+impl RemoteSession {
+    pub fn has(&self, section: ConfigSection) -> bool {
+        self.received.contains(&section)
+    }
+
+    fn passkey_valid(&self) -> bool {
+        !self.session_passkey.is_empty()
+            && get_secs().saturating_sub(self.passkey_received) < PASSKEY_LIFETIME_SECS
+    }
+
+    /// How many more seconds we'll use the session passkey for, if we hold a current one.
+    pub fn passkey_expires_in(&self) -> Option<u64> {
+        self.passkey_valid().then(|| {
+            PASSKEY_LIFETIME_SECS.saturating_sub(get_secs().saturating_sub(self.passkey_received))
+        })
+    }
+}
+
+pub async fn target() -> AdminTarget {
+    *ADMIN_TARGET.read().await
+}
+
+pub async fn set_target(target: AdminTarget) {
+    *ADMIN_TARGET.write().await = target;
+    info!("Config tabs now target the {target}");
+}
+
+pub async fn session(node_id: u32) -> Option<RemoteSession> {
+    REMOTE_SESSIONS.read().await.get(&node_id).cloned()
+}
+
+/// Starts (or resumes) an admin session with a remote node and makes it the config target.
+/// Asking for the device config first also gets us the session passkey.
+pub async fn open_session(node_id: u32) {
+    REMOTE_SESSIONS.write().await.entry(node_id).or_default();
+    set_target(AdminTarget::Remote(node_id)).await;
+    ensure_section(node_id, ConfigSection::Config(ConfigType::DeviceConfig)).await;
+}
+
+/// Steps the config target from the local radio through each open session and back.
+pub async fn cycle_target() -> AdminTarget {
+    let sessions: Vec<u32> = REMOTE_SESSIONS
+        .read()
+        .await
+        .keys()
+        .copied()
+        .sorted()
+        .collect();
+    let next = match target().await {
+        AdminTarget::Local => sessions
+            .first()
+            .map_or(AdminTarget::Local, |id| AdminTarget::Remote(*id)),
+        AdminTarget::Remote(current) => sessions
+            .iter()
+            .find(|id| **id > current)
+            .map_or(AdminTarget::Local, |id| AdminTarget::Remote(*id)),
+    };
+    set_target(next).await;
+    next
+}
+
+/// Asks a remote node for a config section, unless we already have it or asked recently.
+pub async fn ensure_section(node_id: u32, section: ConfigSection) {
+    let due = match REMOTE_SESSIONS.read().await.get(&node_id) {
+        Some(session) => {
+            !session.has(section)
+                && session
+                    .requested
+                    .get(&section)
+                    .is_none_or(|at| get_secs().saturating_sub(*at) > SECTION_RETRY_SECS)
+        }
+        None => false,
+    };
+    if due {
+        request_section(node_id, section).await;
+    }
+}
+
+pub async fn ensure_channels(node_id: u32) {
+    for index in 0..CHANNEL_SLOTS {
+        ensure_section(node_id, ConfigSection::Channel(index)).await;
+    }
+}
+
+pub async fn request_section(node_id: u32, section: ConfigSection) {
+    if let Some(session) = REMOTE_SESSIONS.write().await.get_mut(&node_id) {
+        session.requested.insert(section, get_secs());
+    }
+    match send_admin(node_id, true, section.request()).await {
+        Ok(()) => info!("Asked !{node_id:08x} for {section:?}"),
+        Err(e) => error!("Unable to ask !{node_id:08x} for {section:?}: {e}"),
+    }
+}
+
+/// Sends a config change to the target and updates our copy of its config to match.  Changes
+/// to remote nodes are logged and remembered against the session so they're easy to spot.
+pub async fn apply_change(
+    target: AdminTarget,
+    local_node: u32,
+    change: ConfigChange,
+    description: String,
+) -> Result<()> {
+    let variant = match change.clone() {
+        ConfigChange::Config(c) => PayloadVariant::SetConfig(Config {
+            payload_variant: Some(c),
+        }),
+        ConfigChange::Module(m) => PayloadVariant::SetModuleConfig(ModuleConfig {
+            payload_variant: Some(m),
+        }),
+    };
+    let apply = |dc: &mut DeviceConfiguration| match change {
+        ConfigChange::Config(c) => dc.apply_config(c),
+        ConfigChange::Module(m) => dc.apply_module_config(m),
+    };
+    match target {
+        AdminTarget::Local => {
+            send_admin(local_node, false, variant).await?;
+            let mut f = DEVICE_CONFIG.write().await;
+            apply(f.get_or_insert_with(DeviceConfiguration::default));
+            info!("[local radio] set {description}");
+        }
+        AdminTarget::Remote(node_id) => {
+            require_passkey(node_id).await?;
+            send_admin(node_id, true, variant).await?;
+            if let Some(session) = REMOTE_SESSIONS.write().await.get_mut(&node_id) {
+                apply(&mut session.config);
+                session.changes.push(description.clone());
+            }
+            warn!("[{target}] set {description}");
+        }
+    }
+    Ok(())
+}
+
+/// Asks our own radio for a config section again, for when it will have changed by itself.
+pub async fn request_local_section(local_node: u32, section: ConfigSection) {
+    if let Err(e) = send_admin(local_node, false, section.request()).await {
+        error!("Unable to ask the local radio for {section:?}: {e}");
+    }
+}
+
+/// Asks our own radio for its canned message list.
+pub async fn request_canned_messages(local_node: u32) {
+    let variant = ConfigSection::CannedMessages.request();
+    if let Err(e) = send_admin(local_node, false, variant).await {
+        error!("Unable to ask the local radio for its canned messages: {e}");
+    }
+}
+
+/// Replaces the target's canned message list.  The firmware stores it as one `|`-separated
+/// string, so the messages themselves can't contain a `|`.
+pub async fn set_canned_messages(
+    target: AdminTarget,
+    local_node: u32,
+    messages: Vec<String>,
+) -> Result<()> {
+    if messages.iter().any(|m| m.contains('|')) {
+        bail!("canned messages can't contain '|'");
+    }
+    let variant = PayloadVariant::SetCannedMessageModuleMessages(messages.join("|"));
+    let description = format!("canned messages = {}", messages.join(" | "));
+    match target {
+        AdminTarget::Local => {
+            send_admin(local_node, false, variant).await?;
+            let mut f = DEVICE_CONFIG.write().await;
+            f.get_or_insert_with(DeviceConfiguration::default)
+                .canned_messages = messages;
+            info!("[local radio] set {description}");
+        }
+        AdminTarget::Remote(node_id) => {
+            require_passkey(node_id).await?;
+            send_admin(node_id, true, variant).await?;
+            if let Some(session) = REMOTE_SESSIONS.write().await.get_mut(&node_id) {
+                session.config.canned_messages = messages;
+                session.changes.push(description.clone());
+            }
+            warn!("[{target}] set {description}");
+        }
+    }
+    Ok(())
+}
+
+/// Splits the firmware's `|`-separated canned message string, dropping empty slots.
+pub fn parse_canned_messages(messages: &str) -> Vec<String> {
+    messages
+        .split('|')
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+pub async fn reboot(target: AdminTarget, local_node: u32) -> Result<()> {
+    let variant = PayloadVariant::RebootSeconds(REBOOT_DELAY_SECS);
+    match target {
+        AdminTarget::Local => send_admin(local_node, false, variant).await?,
+        AdminTarget::Remote(node_id) => {
+            require_passkey(node_id).await?;
+            send_admin(node_id, true, variant).await?;
+            if let Some(session) = REMOTE_SESSIONS.write().await.get_mut(&node_id) {
+                session.changes.push("reboot".to_string());
+            }
+        }
+    }
+    warn!("[{target}] rebooting in {REBOOT_DELAY_SECS} seconds");
+    Ok(())
+}
+
+/// Remote nodes refuse changes that don't carry a current session passkey.  If ours is missing
+/// or stale, ask for a fresh one and have the user retry once it arrives.
+async fn require_passkey(node_id: u32) -> Result<()> {
+    let valid = REMOTE_SESSIONS
+        .read()
+        .await
+        .get(&node_id)
+        .is_some_and(RemoteSession::passkey_valid);
+    if !valid {
+        request_section(node_id, ConfigSection::Config(ConfigType::DeviceConfig)).await;
+        bail!("no current session key for !{node_id:08x}; requested a new one, try again shortly");
+    }
+    Ok(())
+}
+
+/// Nodes that still use a legacy "admin" channel are reached over it; everyone else gets the
+/// admin message wrapped with their public key by our radio.
+async fn admin_channel() -> Option<u32> {
+    let dc = DEVICE_CONFIG.read().await;
+    dc.as_ref()?
+        .channels
+        .values()
+        .find(|c| c.settings.as_ref().is_some_and(|s| s.name == "admin"))
+        .map(|c| c.index as u32)
+}
+
+async fn send_admin(to: u32, remote: bool, variant: PayloadVariant) -> Result<()> {
+    let session_passkey = match remote {
+        true => REMOTE_SESSIONS
+            .read()
+            .await
+            .get(&to)
+            .map(|s| s.session_passkey.clone())
+            .unwrap_or_default(),
+        false => vec![],
+    };
+    let message = AdminMessage {
+        session_passkey,
+        payload_variant: Some(variant),
+    };
+    let (channel, pki_encrypted) = match admin_channel().await {
+        Some(index) if remote => (index, false),
+        _ => (0, remote),
+    };
+    let mut packet = request_packet(
+        to,
+        channel,
+        meshtastic::utils::generate_rand_id(),
+        PortNum::AdminApp,
+        message.encode_to_vec(),
+    );
+    packet.pki_encrypted = pki_encrypted;
+    let payload_variant = Some(Packet(packet));
+    util::send_to_radio(IPCMessage::ToRadio(ToRadio { payload_variant })).await
+}
+
+/// Files responses from nodes we hold an admin session with.  Our own radio answers with the
+/// canned message list, which isn't in its config dump, and with sections we asked for again.
+/// Anything else is dropped, so no other node can put words in our radio's mouth.
+pub async fn handle_admin_message(from: u32, my_node_id: u32, message: AdminMessage) {
+    let mut sessions = REMOTE_SESSIONS.write().await;
+    let Some(session) = sessions.get_mut(&from) else {
+        if from != my_node_id || my_node_id == 0 {
+            debug!("Ignoring admin message from !{from:08x}, we have no session with it");
+            return;
+        }
+        match message.payload_variant {
+            Some(PayloadVariant::GetCannedMessageModuleMessagesResponse(m)) => {
+                let mut f = DEVICE_CONFIG.write().await;
+                let dc = f.get_or_insert_with(DeviceConfiguration::default);
+                dc.canned_messages = parse_canned_messages(&m);
+                info!(
+                    "Local radio has {} canned message(s)",
+                    dc.canned_messages.len()
+                );
+            }
+            Some(PayloadVariant::GetConfigResponse(Config {
+                payload_variant: Some(c),
+            })) => {
+                info!(
+                    "Received {:?} from the local radio",
+                    ConfigSection::of_config(&c)
+                );
+                let mut f = DEVICE_CONFIG.write().await;
+                f.get_or_insert_with(DeviceConfiguration::default)
+                    .apply_config(c);
+            }
+            _ => debug!("Ignoring admin message from our radio: {message:?}"),
+        }
+        return;
+    };
+    if !message.session_passkey.is_empty() {
+        session.session_passkey = message.session_passkey;
+        session.passkey_received = get_secs();
+    }
+    match message.payload_variant {
+        Some(PayloadVariant::GetConfigResponse(Config {
+            payload_variant: Some(c),
+        })) => {
+            let section = ConfigSection::of_config(&c);
+            info!("Received {section:?} from !{from:08x}");
+            session.received.insert(section);
+            session.config.apply_config(c);
+        }
+        Some(PayloadVariant::GetModuleConfigResponse(ModuleConfig {
+            payload_variant: Some(m),
+        })) => {
+            let section = ConfigSection::of_module(&m);
+            info!("Received {section:?} from !{from:08x}");
+            session.received.insert(section);
+            session.config.apply_module_config(m);
+        }
+        Some(PayloadVariant::GetChannelResponse(channel)) => {
+            session
+                .received
+                .insert(ConfigSection::Channel(channel.index as u32));
+            session.config.apply_channel(channel);
+        }
+        Some(PayloadVariant::GetCannedMessageModuleMessagesResponse(m)) => {
+            info!("Received canned messages from !{from:08x}");
+            session.received.insert(ConfigSection::CannedMessages);
+            session.config.canned_messages = parse_canned_messages(&m);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use meshtastic::protobufs::config::SecurityConfig;
+
+    fn security_response(admin_key: &[u8]) -> AdminMessage {
+        AdminMessage {
+            payload_variant: Some(PayloadVariant::GetConfigResponse(Config {
+                payload_variant: Some(config::PayloadVariant::Security(SecurityConfig {
+                    admin_key: vec![admin_key.to_vec()],
+                    ..Default::default()
+                })),
+            })),
+            ..Default::default()
+        }
+    }
+
+    fn local_admin_keys() -> Option<Vec<Vec<u8>>> {
+        DEVICE_CONFIG
+            .try_read()
+            .ok()?
+            .as_ref()
+            .map(|dc| dc.security.admin_key.clone())
+    }
+
+    #[tokio::test]
+    async fn only_our_radio_updates_the_local_config() {
+        let me = 0x1111_1111;
+        *DEVICE_CONFIG.write().await = None;
+
+        handle_admin_message(0x2222_2222, me, security_response(b"spoofed")).await;
+        assert_eq!(local_admin_keys(), None);
+
+        // before our radio has told us its number, nobody is our radio
+        handle_admin_message(0, 0, security_response(b"unknown")).await;
+        assert_eq!(local_admin_keys(), None);
+
+        handle_admin_message(me, me, security_response(b"ours")).await;
+        assert_eq!(local_admin_keys(), Some(vec![b"ours".to_vec()]));
+
+        *DEVICE_CONFIG.write().await = None;
+    }
+}
@@ -1,27 +1,41 @@
+use crate::admin::{self, AdminTarget};
+use crate::composer::{self, Composer};
 use crate::consts;
+use crate::export;
 use crate::ipc::IPCMessage;
+use crate::keymap::{Action, Keymap, Resolved};
 use crate::meshtastic_interaction::meshtastic_loop;
-use crate::packet_handler::{MessageEnvelope, PacketResponse, process_packet};
+use crate::metrics;
+use crate::mouse::{self, Target};
+use crate::palette::{self, Command, Entry, Palette, PaletteKey};
+use crate::packet_handler::{
+    MessageEnvelope, MessageKind, PacketResponse, RequestReply, observe_link, process_packet,
+    request_reply,
+};
 use crate::tabs::nodes::ComprehensiveNode;
 use crate::tabs::*;
-use crate::theme::THEME;
+use crate::theme::{self, THEME};
+use crate::topology::TopologyFilter;
 use crate::tui::Event;
-use crate::{tui, util};
+use crate::pki::{self, KeyCheck};
+use crate::{tui, util, KEY_PINS, PREFERENCES};
 use anyhow::Result;
 use color_eyre::eyre::WrapErr;
-use crossterm::event::KeyCode;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
 use crossterm::terminal::{LeaveAlternateScreen, disable_raw_mode};
 use itertools::Itertools;
 use meshtastic::packet::PacketDestination;
-use meshtastic::protobufs::{Channel, DeviceUiConfig};
+use meshtastic::protobufs::{Channel, DeviceUiConfig, Position};
 use meshtastic::types::MeshChannel;
-use ratatui::widgets::{Clear, Paragraph};
+use ratatui::widgets::{Clear, Paragraph, Wrap};
 use ratatui::{
     prelude::*,
     widgets::{Block, Borders, Tabs},
 };
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 use meshtastic::protobufs::config::*;
 use meshtastic::protobufs::module_config::*;
@@ -33,6 +47,10 @@ use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
 use tui_logger::TuiLoggerWidget;
 
+/// What we call the unnamed primary channel.  It's longer than a real channel name can be, so
+/// it can't be mistaken for one.
+pub const DEFAULT_CHANNEL_LABEL: &str = "LongFast (Default)";
+
 #[derive(Debug, Default, Clone)]
 pub struct App {
     pub mode: Mode,
@@ -42,36 +60,86 @@ pub struct App {
     pub device_config_tab: ConfigTab,
     pub modules_config_tab: ModulesConfigTab,
     pub messages_tab: MessagesTab,
+    pub waypoints_tab: WaypointsTab,
+    pub range_test_tab: RangeTestTab,
     pub about_tab: AboutTab,
     pub input_mode: InputMode,
-    pub cursor_position: usize,
-    pub input: String,
+    pub composer: Composer,
     pub connection: Connection,
     pub user_prefs: Preferences,
+    /// Nodes presenting a public key other than the one we pinned, with the key they presented.
+    pub key_changes: BTreeMap<u32, Vec<u8>>,
+    pub keymap: Keymap,
+    pub palette: Option<Palette>,
+    /// Whether to capture the mouse.  Off, the terminal keeps its own text selection.
+    pub mouse: bool,
+    /// The last row clicked, to spot double-clicks.
+    last_click: Option<(Instant, MenuTabs, usize)>,
 }
 
 impl App {
     pub(crate) fn render_send_message_popup(&self, area: Rect, buf: &mut Buffer) {
-        let popup_block = Block::default()
-            .title("Enter message")
+        let title = match self.tab {
+            MenuTabs::DeviceConfig => self.device_config_tab.edit_title(),
+            MenuTabs::ModulesConfig => self.modules_config_tab.edit_title(),
+            MenuTabs::Waypoints => self.waypoints_tab.edit_title(),
+            MenuTabs::Messages => self.messages_tab.prompt_title(),
+            _ => "Enter message".to_string(),
+        };
+        let mut popup_block = Block::default()
+            .title(title)
             .borders(Borders::ALL)
             .title_alignment(Alignment::Center)
             .border_set(symbols::border::DOUBLE)
             .style(THEME.middle);
-        let popup_area = centered_rect(area, 60, 25);
-        let _popup_layout = Layout::default()
-            .direction(Direction::Horizontal)
-            .margin(1)
-            .constraints([Constraint::Percentage(50)])
-            .split(popup_area);
+        if self.tab == MenuTabs::Messages && self.messages_tab.composing_text() {
+            popup_block = popup_block.title_bottom(self.composer.counter().right_aligned());
+        }
+        let popup_area = centered_rect(area, 60, 40);
+        let mut text_area = popup_block.inner(popup_area).inner(Margin::new(1, 1));
+        let mut options_area = None;
+        let mut canned_area = None;
+        let canned = util::canned_messages();
+        if self.sending_new_message() {
+            let hint = match self.composer.options_open {
+                true => " Tab picks an option, PgUp/PgDn changes it, F3 hides ",
+                false if canned.is_empty() => " F3 send options ",
+                false => " F3 send options, Alt-1..9 canned message ",
+            };
+            popup_block = popup_block.title_bottom(Line::raw(hint).left_aligned());
+            if !canned.is_empty() {
+                let [text, picks] = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Min(1), Constraint::Length(1)])
+                    .areas(text_area);
+                text_area = text;
+                canned_area = Some(picks);
+            }
+            if self.composer.options_open {
+                let [text, options] = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Min(1), Constraint::Length(1)])
+                    .areas(text_area);
+                text_area = text;
+                options_area = Some(options);
+            }
+        }
 
         Widget::render(Clear, area, buf);
         Widget::render(popup_block, popup_area, buf);
         Widget::render(
-            Paragraph::new(self.input.clone()).style(THEME.message_selected),
-            centered_rect(popup_area, 75, 25),
+            Paragraph::new(self.composer.lines())
+                .style(THEME.message_selected)
+                .wrap(Wrap { trim: false }),
+            text_area,
             buf,
         );
+        if let Some(picks) = canned_area {
+            Widget::render(Paragraph::new(Composer::canned_line(&canned)), picks, buf);
+        }
+        if let Some(options) = options_area {
+            Widget::render(Paragraph::new(self.composer.options_line()), options, buf);
+        }
     }
 }
 
@@ -79,16 +147,38 @@ impl App {
 pub struct Preferences {
     pub(crate) initialized: String,
     pub(crate) show_mqtt: bool,
+    pub(crate) export_dir: PathBuf,
+    pub(crate) export_on_exit: bool,
+    /// Whether the metrics exporter is running, and so wants node snapshots.
+    pub(crate) metrics: bool,
+    /// Which links the topology export leaves out.
+    pub(crate) topology: TopologyFilter,
 }
 
 #[derive(Debug, Clone, Default)]
 pub enum Connection {
     TCP(String, u16),
     Serial(String),
+    Mqtt(MqttSettings),
     #[default]
     None,
 }
 
+/// Where to watch a mesh from over MQTT, with no radio attached.
+#[derive(Debug, Clone, Default)]
+pub struct MqttSettings {
+    pub host: String,
+    pub port: u16,
+    /// e.g. `msh/US`; we read everything under `<root>/2/e/`.
+    pub root_topic: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// The channel our outgoing packets are published on; None leaves us listening only.
+    pub publish_channel: Option<String>,
+    /// The node number we send as, since there's no radio to lend us one.
+    pub node_id: u32,
+}
+
 impl App {
     fn chain_hook(&mut self) {
         let original_hook = std::panic::take_hook();
@@ -103,6 +193,8 @@ impl App {
     fn escape(&mut self) {
         self.mode = match self.tab {
             MenuTabs::Nodes => self.nodes_tab.escape(),
+            MenuTabs::Waypoints => self.waypoints_tab.escape(),
+            MenuTabs::RangeTest => self.range_test_tab.escape(),
             MenuTabs::Messages => self.messages_tab.escape(),
             MenuTabs::Channels => self.channels_tab.escape(),
             MenuTabs::DeviceConfig => self.device_config_tab.escape(),
@@ -111,25 +203,201 @@ impl App {
         }
     }
     async fn function_key(&mut self, num: u8) {
-        if num == 12 {
-            self.mode = Mode::RestartComms;
-        }
         match self.tab {
             MenuTabs::Nodes => self.nodes_tab.function_key(num).await,
-            MenuTabs::Messages => self.messages_tab.function_key(num),
+            MenuTabs::Waypoints => self.waypoints_tab.function_key(num).await,
+            MenuTabs::RangeTest => self.range_test_tab.function_key(num).await,
+            MenuTabs::Messages if num == 2 => {
+                if let Some(minutes) = self.messages_tab.begin_history_request() {
+                    self.composer.set(minutes);
+                    self.input_mode = InputMode::Editing;
+                }
+            }
+            MenuTabs::Messages => self.messages_tab.function_key(num).await,
+            MenuTabs::DeviceConfig if num == 5 && self.device_config_tab.begin_confirmation() => {
+                self.composer.clear();
+                self.input_mode = InputMode::Editing;
+            }
             MenuTabs::Channels => self.channels_tab.function_key(num).await,
-            MenuTabs::DeviceConfig => self.device_config_tab.function_key(num),
-            MenuTabs::ModulesConfig => self.modules_config_tab.function_key(num),
+            MenuTabs::DeviceConfig => self.device_config_tab.function_key(num).await,
+            MenuTabs::ModulesConfig => self.modules_config_tab.function_key(num).await,
             _ => {}
         }
     }
 
+    async fn export(&self) {
+        match self.export_all().await {
+            Ok(dir) => info!("Exported node and telemetry data to {}", dir.display()),
+            Err(e) => error!("Unable to export node and telemetry data: {e:#}"),
+        }
+    }
+
+    async fn export_all(&self) -> Result<PathBuf> {
+        let prefs = PREFERENCES.read().await.clone();
+        export::export_all(
+            &prefs.export_dir,
+            &self.nodes_tab.node_list,
+            &self.waypoints_tab.waypoints,
+            self.nodes_tab.my_node_id,
+            &prefs.topology,
+        )
+    }
+
+    /// Checks a public key a node presented against the one pinned for it.
+    async fn check_key(&mut self, node: u32, key: &[u8]) {
+        if key.is_empty() {
+            return;
+        }
+        let check = KEY_PINS.write().await.check(node, key);
+        match check {
+            KeyCheck::First => debug!(
+                "Pinned public key {} for {}",
+                pki::fingerprint(key),
+                self.nodes_tab.node_label(node)
+            ),
+            KeyCheck::Known => {
+                self.key_changes.remove(&node);
+            }
+            KeyCheck::Changed { pinned } => {
+                if self.key_changes.get(&node).map(Vec::as_slice) != Some(key) {
+                    error!(
+                        "Public key for {} changed from {} to {}; someone may be impersonating it",
+                        self.nodes_tab.node_label(node),
+                        pki::fingerprint(&pinned),
+                        pki::fingerprint(key)
+                    );
+                    self.key_changes.insert(node, key.to_vec());
+                }
+            }
+        }
+    }
+
+    /// Pins the key a node is presenting now, once the user has decided to trust it.
+    async fn accept_key(&mut self, node: u32) {
+        let Some(key) = self.key_changes.remove(&node) else {
+            return;
+        };
+        KEY_PINS.write().await.accept(node, &key);
+        info!(
+            "Now trusting public key {} for {}",
+            pki::fingerprint(&key),
+            self.nodes_tab.node_label(node)
+        );
+    }
+
+    fn render_key_warning(&self, area: Rect, buf: &mut Buffer) {
+        let names = self
+            .key_changes
+            .keys()
+            .map(|id| self.nodes_tab.node_label(*id))
+            .join(", ");
+        Line::from(format!(
+            " ⚠ Public key changed for {names}; this can mean impersonation.  Check the node's \
+             detail view, and press K there to trust the new key. "
+        ))
+        .style(THEME.key_warning)
+        .render(area, buf);
+    }
+
+    async fn char_key(&mut self, c: char) {
+        if self.tab == MenuTabs::Messages {
+            if let Some(canned) = canned_message(c) {
+                self.composer.set(canned);
+                self.input_mode = InputMode::Editing;
+                return;
+            }
+            if let Some(prefill) = self.messages_tab.char_key(c) {
+                self.composer.set(prefill);
+                self.input_mode = InputMode::Editing;
+            }
+            return;
+        }
+        if self.tab != MenuTabs::Nodes {
+            return;
+        }
+        match (c, self.nodes_tab.detail_node()) {
+            ('a', Some(node_id)) if node_id == self.nodes_tab.my_node_id => {
+                admin::set_target(AdminTarget::Local).await;
+                self.tab = MenuTabs::DeviceConfig;
+            }
+            ('a', Some(node_id)) => {
+                admin::open_session(node_id).await;
+                self.tab = MenuTabs::DeviceConfig;
+            }
+            ('K', Some(node_id)) => self.accept_key(node_id).await,
+            _ => self.nodes_tab.char_key(c).await,
+        }
+    }
+
+    /// Takes in one packet from the radio, whether or not there's a screen to show it on.
+    async fn packet(&mut self, packet: IPCMessage) {
+        if let Some((request_id, reply)) = request_reply(&packet) {
+            if let RequestReply::Failed(reason) = reply {
+                metrics::count_ack_failure(reason).await;
+            }
+            self.nodes_tab.resolve_request(request_id, reply);
+        }
+        if let Some(link) = observe_link(&packet) {
+            self.nodes_tab.observe_link(link);
+        }
+        if let Some((node, key)) = pki::packet_key(&packet) {
+            self.check_key(node, &key).await;
+        }
+        let update =
+            process_packet(packet, &self.nodes_tab.node_list, self.nodes_tab.my_node_id).await;
+        if update.is_some() {
+            // we received an update on a node
+            match update.unwrap() {
+                PacketResponse::NodeUpdate(id, cn) => {
+                    if let Some(user) = &cn.node_info.user {
+                        self.check_key(id, &user.public_key).await;
+                    }
+                    self.nodes_tab.node_list.insert(id, *cn);
+                }
+                PacketResponse::InboundMessage(envelope) => {
+                    self.messages_tab.push(envelope);
+                }
+                PacketResponse::Waypoint(entry) => self.waypoints_tab.upsert(entry),
+                PacketResponse::StoreForward(from, name, sf) => {
+                    self.messages_tab.observe_store_forward(from, name, &sf);
+                }
+                PacketResponse::RangeTest(hit) => {
+                    self.range_test_tab.my_position = self.my_position();
+                    self.range_test_tab.record(hit);
+                }
+                PacketResponse::UserUpdate(id, user) => {
+                    self.check_key(id, &user.public_key).await;
+                    if let Some(cn) = self.nodes_tab.node_list.get(&id) {
+                        let mut ncn = cn.clone();
+                        ncn.node_info.user = Some(user);
+                        ncn.last_seen = util::get_secs();
+                        self.nodes_tab.node_list.insert(id, ncn);
+                    } else {
+                        let mut cn = ComprehensiveNode::with_id(id);
+                        cn.node_info.user = Some(user);
+                        cn.last_seen = util::get_secs();
+                        self.nodes_tab.node_list.insert(id, cn);
+                    }
+                }
+                PacketResponse::OurAddress(id) => {
+                    self.nodes_tab.my_node_id = id;
+                    self.device_config_tab.my_node_id = id;
+                    self.modules_config_tab.my_node_id = id;
+                    self.waypoints_tab.my_node_id = id;
+                    admin::request_canned_messages(id).await;
+                }
+            }
+        }
+    }
+
     pub async fn run(&mut self) -> Result<()> {
         self.chain_hook();
         let mut tui = tui::Tui::new()
             .unwrap()
             .tick_rate(consts::TICK_RATE)
-            .frame_rate(consts::FRAME_RATE);
+            .frame_rate(consts::FRAME_RATE)
+            .paste(true)
+            .mouse(self.mouse);
 
         let _ = tui.enter(); // Starts event handler, enters raw mode, enters alternate screen
 
@@ -148,6 +416,8 @@ impl App {
         let mut join_handle: JoinHandle<Result<()>> = tokio::task::spawn(async move {
             meshtastic_loop(conn, fromradio_tx, toradio_thread_rx).await
         });
+        let metrics_enabled = PREFERENCES.read().await.metrics;
+        let mut last_snapshot = 0;
 
         while self.is_running() {
             // check if we requested a comm restart
@@ -159,6 +429,10 @@ impl App {
             // execute runs, if needed
             match self.tab {
                 MenuTabs::Nodes => self.nodes_tab.run().await,
+                MenuTabs::Waypoints => {
+                    self.waypoints_tab.my_position = self.my_position();
+                    self.waypoints_tab.run().await
+                }
                 MenuTabs::Messages => self.messages_tab.run().await,
                 MenuTabs::Channels => self.channels_tab.run().await,
                 MenuTabs::DeviceConfig => self.device_config_tab.run().await,
@@ -170,86 +444,25 @@ impl App {
             let _ = self.draw(&mut tui.terminal);
 
             // process input
-            if let Some(Event::Key(press)) = tui.next().await {
-                use KeyCode::*;
-                match self.input_mode {
-                    InputMode::Normal => match press.code {
-                        Char('q') | Esc => self.escape(),
-                        Char('h') | Left => self.left(),
-                        Char('l') | Right => self.right(),
-                        Char('k') | Up => self.prev(),
-                        Char('j') | Down => self.next(),
-                        PageUp => self.prev_page(),
-                        PageDown => self.next_page(),
-                        KeyCode::Enter => self.enter_key().await,
-                        KeyCode::BackTab => self.prev_tab(),
-                        KeyCode::Tab => self.next_tab(),
-                        KeyCode::F(n) => self.function_key(n).await,
-                        _ => {}
-                    },
-                    InputMode::Editing => match press.code {
-                        KeyCode::Enter => self.enter_key().await,
-                        KeyCode::Char(to_insert) => self.enter_char(to_insert),
-                        KeyCode::Backspace => {
-                            self.delete_char();
-                        }
-                        KeyCode::Left => {
-                            self.move_cursor_left();
-                        }
-                        KeyCode::Right => {
-                            self.move_cursor_right();
-                        }
-                        KeyCode::Esc => {
-                            self.input_mode = InputMode::Normal;
-                        }
-                        _ => {}
-                    },
+            match tui.next().await {
+                Some(Event::Key(press)) => self.key(press).await,
+                Some(Event::Mouse(event)) => self.mouse(event).await,
+                Some(Event::Paste(text)) if self.input_mode == InputMode::Editing => {
+                    self.composer.insert_str(&text)
                 }
-            };
+                _ => {}
+            }
 
             // execute action logic
             if let Ok(packet) = fromradio_thread_rx.try_recv() {
-                let update = process_packet(packet, self.nodes_tab.node_list.clone()).await;
-                if update.is_some() {
-                    // we received an update on a node
-                    match update.unwrap() {
-                        PacketResponse::NodeUpdate(id, cn) => {
-                            self.nodes_tab.node_list.insert(id, *cn);
-                        }
-                        PacketResponse::InboundMessage(envelope) => {
-                            if let Some(cn) = self
-                                .nodes_tab
-                                .node_list
-                                .get(&envelope.clone().source.unwrap().num)
-                            {
-                                let mut ncn = cn.clone();
-                                ncn.last_rssi = envelope.rx_rssi;
-                                ncn.last_snr = envelope.rx_snr;
-                                self.nodes_tab
-                                    .node_list
-                                    .insert(envelope.clone().source.unwrap().num, ncn);
-                            }
-                            self.messages_tab.messages.push_back(envelope);
-                        }
-                        PacketResponse::UserUpdate(id, user) => {
-                            if let Some(cn) = self.nodes_tab.node_list.get(&id) {
-                                let mut ncn = cn.clone();
-                                ncn.node_info.user = Some(user);
-                                ncn.last_seen = util::get_secs();
-                                self.nodes_tab.node_list.insert(id, ncn);
-                            } else {
-                                let mut cn = ComprehensiveNode::with_id(id);
-                                cn.node_info.user = Some(user);
-                                cn.last_seen = util::get_secs();
-                                self.nodes_tab.node_list.insert(id, cn);
-                            }
-                        }
-                        PacketResponse::OurAddress(id) => {
-                            self.nodes_tab.my_node_id = id;
-                            self.nodes_tab.my_node_id = id;
-                        }
-                    }
-                }
+                self.packet(packet).await;
+            }
+
+            if metrics_enabled
+                && util::get_secs().saturating_sub(last_snapshot) >= metrics::SNAPSHOT_SECS
+            {
+                metrics::snapshot(&self.nodes_tab.node_list, self.nodes_tab.my_node_id).await;
+                last_snapshot = util::get_secs();
             }
 
             // tend to our threads
@@ -272,19 +485,285 @@ impl App {
 
         let _ = tui.exit(); // stops event handler, exits raw mode, exits alternate screen
         join_handle.abort();
+        if PREFERENCES.read().await.export_on_exit {
+            // the tui is gone by now, so report straight to the terminal.
+            match self.export_all().await {
+                Ok(dir) => println!("Exported node and telemetry data to {}", dir.display()),
+                Err(e) => eprintln!("Unable to export node and telemetry data: {e:#}"),
+            }
+        }
         Ok(())
     }
 
+    /// Listens to the radio for `secs` seconds without drawing anything, then writes an export
+    /// and returns the directory it went to.  The radio sends us its node database when we
+    /// connect, so the node table fills up even when nothing else is heard in that time.
+    pub async fn run_headless(&mut self, secs: u64) -> Result<PathBuf> {
+        let (fromradio_tx, mut fromradio_rx) =
+            mpsc::channel::<IPCMessage>(consts::MPSC_BUFFER_SIZE);
+        let (toradio_tx, toradio_rx) = mpsc::channel::<IPCMessage>(consts::MPSC_BUFFER_SIZE);
+        *crate::TO_RADIO_MPSC.write().await = Some(toradio_tx);
+        let conn = self.connection.clone();
+        let join_handle: JoinHandle<Result<()>> =
+            tokio::task::spawn(
+                async move { meshtastic_loop(conn, fromradio_tx, toradio_rx).await },
+            );
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(secs);
+        loop {
+            tokio::select! {
+                packet = fromradio_rx.recv() => match packet {
+                    Some(packet) => self.packet(packet).await,
+                    // the connection ended, and took its sender with it
+                    None => break,
+                },
+                _ = tokio::time::sleep_until(deadline) => break,
+            }
+        }
+        if join_handle.is_finished() {
+            if let Ok(Err(e)) = join_handle.await {
+                return Err(e.context("the connection to the radio ended"));
+            }
+        } else {
+            join_handle.abort();
+        }
+        self.export_all().await
+    }
+
     fn draw(&self, terminal: &mut Terminal<impl Backend>) -> Result<()> {
         terminal
             .draw(|frame| {
                 frame.render_widget(self, frame.size());
+                theme::adapt(frame.buffer_mut());
             })
             .wrap_err("terminal.draw")
             .unwrap();
         Ok(())
     }
 
+    async fn key(&mut self, press: KeyEvent) {
+        use KeyCode::*;
+        if let Some(palette) = &mut self.palette {
+            match palette.key(press) {
+                PaletteKey::Open => {}
+                PaletteKey::Close => self.palette = None,
+                PaletteKey::Run(command) => {
+                    self.palette = None;
+                    self.run_command(command).await;
+                }
+            }
+            return;
+        }
+        // the number keys pick canned messages there, so they can't also be counts
+        self.keymap.pause_counts(self.tab == MenuTabs::Messages);
+        match self.input_mode {
+            InputMode::Normal => match self.keymap.press(press) {
+                Resolved::Action(action, count) => self.action(action, count).await,
+                Resolved::Pending => {}
+                Resolved::Unbound => match press.code {
+                    KeyCode::F(n) => self.function_key(n).await,
+                    Char(c) => self.char_key(c).await,
+                    _ => {}
+                },
+            },
+            InputMode::Editing => match press.code {
+                KeyCode::Enter if !press.modifiers.contains(KeyModifiers::ALT) => {
+                    self.enter_key().await
+                }
+                KeyCode::F(2) => self.composer.split = !self.composer.split,
+                Char(c @ '1'..='9')
+                    if press.modifiers.contains(KeyModifiers::ALT)
+                        && self.sending_new_message() =>
+                {
+                    if let Some(canned) = canned_message(c) {
+                        self.composer.insert_str(&canned);
+                    }
+                }
+                KeyCode::F(3) if self.sending_new_message() => {
+                    self.composer.options_open = !self.composer.options_open
+                }
+                _ if self.sending_new_message()
+                    && self.composer.options_key(
+                        press,
+                        &self.destinations(),
+                        &util::active_channels(),
+                    ) => {}
+                KeyCode::Esc => {
+                    self.messages_tab.cancel_prompt();
+                    self.device_config_tab.cancel_confirmation();
+                    self.composer.clear();
+                    self.input_mode = InputMode::Normal;
+                }
+                _ => self.composer.key(press),
+            },
+        }
+    }
+
+    /// Clicks pick tabs, sub-tabs and rows, a double-click acts like Enter on the row, and the
+    /// wheel moves through the current list.  Popups keep the keyboard to themselves.
+    async fn mouse(&mut self, event: MouseEvent) {
+        if self.palette.is_some() || self.input_mode == InputMode::Editing {
+            return;
+        }
+        match event.kind {
+            MouseEventKind::ScrollUp => self.scroll(false),
+            MouseEventKind::ScrollDown => self.scroll(true),
+            MouseEventKind::Down(MouseButton::Left) => {
+                match mouse::target_at(event.column, event.row) {
+                    Some(Target::Tab(tab)) => self.tab = tab,
+                    Some(Target::SubTab(index)) => match self.tab {
+                        MenuTabs::DeviceConfig => self.device_config_tab.select_tab(index),
+                        MenuTabs::ModulesConfig => self.modules_config_tab.select_tab(index),
+                        _ => {}
+                    },
+                    Some(Target::Row(index)) => {
+                        let now = Instant::now();
+                        let double = self.last_click.is_some_and(|(at, tab, row)| {
+                            tab == self.tab
+                                && row == index
+                                && now.duration_since(at).as_millis() <= mouse::DOUBLE_CLICK_MS
+                        });
+                        self.select_row(index);
+                        match double {
+                            true => {
+                                self.last_click = None;
+                                self.enter_key().await;
+                            }
+                            false => self.last_click = Some((now, self.tab, index)),
+                        }
+                    }
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn scroll(&mut self, down: bool) {
+        match self.tab {
+            MenuTabs::Nodes => self.nodes_tab.scroll(down),
+            MenuTabs::Messages => self.messages_tab.scroll(down),
+            _ if down => self.next(),
+            _ => self.prev(),
+        }
+    }
+
+    async fn action(&mut self, action: Action, count: Option<usize>) {
+        let times = count.unwrap_or(1);
+        match action {
+            Action::Back => self.escape(),
+            Action::Left => (0..times).for_each(|_| self.left()),
+            Action::Right => (0..times).for_each(|_| self.right()),
+            Action::Up => (0..times).for_each(|_| self.prev()),
+            Action::Down => (0..times).for_each(|_| self.next()),
+            Action::PageUp => (0..times).for_each(|_| self.prev_page()),
+            Action::PageDown => (0..times).for_each(|_| self.next_page()),
+            Action::Top => self.select_row(count.map_or(0, |n| n.saturating_sub(1))),
+            Action::Bottom => self.select_row(count.map_or(usize::MAX, |n| n.saturating_sub(1))),
+            Action::Enter => self.enter_key().await,
+            Action::NextTab => (0..times).for_each(|_| self.next_tab()),
+            Action::PrevTab => (0..times).for_each(|_| self.prev_tab()),
+            Action::Palette => self.palette = Some(Palette::new(self.palette_entries())),
+            Action::Export => self.export().await,
+            Action::NextTheme => info!("Switched to the {} theme", theme::next_theme()),
+            Action::Reconnect => self.mode = Mode::RestartComms,
+            Action::FunctionKey(n) => self.function_key(n).await,
+        }
+    }
+
+    /// Everything the palette offers: the actions, the current tab's function keys, the other
+    /// tabs, and per-node commands.
+    fn palette_entries(&self) -> Vec<Entry> {
+        let mut entries = vec![];
+        for (n, description) in palette::function_keys(self.tab) {
+            entries.push(Entry::new(
+                format!("{description} (F{n})"),
+                Command::Action(Action::FunctionKey(*n)),
+            ));
+        }
+        for (action, description) in Action::describe_all() {
+            let label = match self.keymap.keys_for(action) {
+                Some(keys) => format!("{description} ({keys})"),
+                None => description.to_string(),
+            };
+            entries.push(Entry::new(label, Command::Action(action)));
+        }
+        for tab in MenuTabs::iter().filter(|t| *t != self.tab) {
+            entries.push(Entry::new(format!("Go to {tab}"), Command::GoTo(tab)));
+        }
+        entries.push(Entry::new("Show or hide MQTT nodes", Command::ToggleMqtt));
+        for text in util::canned_messages() {
+            entries.push(Entry::new(
+                format!("Send canned message: {text}"),
+                Command::CannedMessage(text),
+            ));
+        }
+        for (id, name) in self.destinations() {
+            entries.push(Entry::new(
+                format!("Send DM to {name}"),
+                Command::DirectMessage(id, name.clone()),
+            ));
+            entries.push(Entry::new(
+                format!("Traceroute {name}"),
+                Command::Traceroute(id),
+            ));
+        }
+        entries
+    }
+
+    async fn run_command(&mut self, command: Command) {
+        match command {
+            Command::Action(action) => self.action(action, None).await,
+            Command::GoTo(tab) => self.tab = tab,
+            Command::DirectMessage(id, name) => {
+                self.tab = MenuTabs::Messages;
+                self.composer.clear();
+                self.composer.options.destination = Some((id, name));
+                self.input_mode = InputMode::Editing;
+            }
+            Command::Traceroute(id) => self.nodes_tab.traceroute(id).await,
+            Command::CannedMessage(text) => {
+                self.tab = MenuTabs::Messages;
+                self.composer.set(text);
+                self.input_mode = InputMode::Editing;
+            }
+            Command::ToggleMqtt => {
+                let mut prefs = PREFERENCES.write().await;
+                prefs.show_mqtt = !prefs.show_mqtt;
+                match prefs.show_mqtt {
+                    true => info!("Showing nodes heard over MQTT"),
+                    false => info!("Hiding nodes heard over MQTT"),
+                }
+            }
+            Command::Connect(connection) => {
+                info!("Switching connection to {connection:?}");
+                self.connection = connection;
+                self.mode = Mode::RestartComms;
+            }
+        }
+    }
+
+    /// Whether the popup is composing a new message, which is when the send options apply.
+    fn sending_new_message(&self) -> bool {
+        self.tab == MenuTabs::Messages && !self.messages_tab.has_prompt()
+    }
+
+    /// The nodes a message can be sent to directly, by name.
+    fn destinations(&self) -> Vec<(u32, String)> {
+        self.nodes_tab
+            .node_list
+            .values()
+            .filter(|cn| cn.id != self.nodes_tab.my_node_id)
+            .map(|cn| {
+                let name = match &cn.node_info.user {
+                    Some(user) if !user.short_name.is_empty() => user.short_name.clone(),
+                    _ => format!("!{:x}", cn.id),
+                };
+                (cn.id, name)
+            })
+            .sorted_by_key(|(_, name)| name.to_lowercase())
+            .collect()
+    }
+
     fn is_running(&self) -> bool {
         self.mode != Mode::Exiting
     }
@@ -322,6 +801,8 @@ impl App {
     fn prev(&mut self) {
         match self.tab {
             MenuTabs::Nodes => self.nodes_tab.prev_row(),
+            MenuTabs::Waypoints => self.waypoints_tab.prev_row(),
+            MenuTabs::RangeTest => self.range_test_tab.prev_row(),
             MenuTabs::Messages => self.messages_tab.prev_row(),
             MenuTabs::Channels => self.channels_tab.prev_row(),
             MenuTabs::DeviceConfig => self.device_config_tab.prev_row(),
@@ -330,6 +811,19 @@ impl App {
         }
     }
 
+    fn select_row(&mut self, index: usize) {
+        match self.tab {
+            MenuTabs::Nodes => self.nodes_tab.select_row(index),
+            MenuTabs::Waypoints => self.waypoints_tab.select_row(index),
+            MenuTabs::RangeTest => self.range_test_tab.select_row(index),
+            MenuTabs::Messages => self.messages_tab.select_row(index),
+            MenuTabs::Channels => self.channels_tab.select_row(index),
+            MenuTabs::DeviceConfig => self.device_config_tab.select_row(index),
+            MenuTabs::ModulesConfig => self.modules_config_tab.select_row(index),
+            MenuTabs::About => {}
+        }
+    }
+
     fn prev_page(&mut self) {
         match self.tab {
             MenuTabs::Nodes => self.nodes_tab.prev_page(),
@@ -344,6 +838,8 @@ impl App {
     fn next(&mut self) {
         match self.tab {
             MenuTabs::Nodes => self.nodes_tab.next_row(),
+            MenuTabs::Waypoints => self.waypoints_tab.next_row(),
+            MenuTabs::RangeTest => self.range_test_tab.next_row(),
             MenuTabs::Messages => self.messages_tab.next_row(),
             MenuTabs::Channels => self.channels_tab.next_row(),
             MenuTabs::DeviceConfig => self.device_config_tab.next_row(),
@@ -369,82 +865,140 @@ impl App {
                 self.input_mode = InputMode::Editing;
             }
             InputMode::Editing => {
-                if !self.input.is_empty() {
-                    info!("Sending message {} to LongFast", self.input.clone());
-                    let message = MessageEnvelope {
-                        timestamp: 0,
-                        source: None,
-                        destination: PacketDestination::Broadcast,
-                        channel: MeshChannel::new(0).unwrap(),
-                        message: self.input.clone(),
-                        rx_rssi: 0,
-                        rx_snr: 0.0,
+                let parts = match self.messages_tab.composing_text() {
+                    true => match self.composer.parts() {
+                        Some(parts) => parts,
+                        None => {
+                            warn!(
+                                "That's {} bytes, over the {} byte limit; F2 splits it into parts",
+                                self.composer.byte_len(),
+                                composer::MAX_MESSAGE_BYTES
+                            );
+                            return;
+                        }
+                    },
+                    false => vec![],
+                };
+                if self.messages_tab.composing_text() {
+                    let text = self.composer.text().to_string();
+                    self.composer.remember(&text);
+                }
+                if self.messages_tab.has_prompt() {
+                    let me = self
+                        .nodes_tab
+                        .node_list
+                        .get(&self.nodes_tab.my_node_id)
+                        .map(|cn| cn.node_info.clone());
+                    self.messages_tab
+                        .finish_prompt(self.composer.text(), parts, &self.composer.options, me)
+                        .await;
+                } else {
+                    let options = self.composer.options.clone();
+                    let (destination, to) = match &options.destination {
+                        Some((id, name)) => (PacketDestination::Node((*id).into()), name.clone()),
+                        None => (PacketDestination::Broadcast, "everyone".to_string()),
                     };
-                    if let Err(e) = util::send_to_radio(IPCMessage::SendMessage(message)).await {
-                        error!("Unable to send message to node: {e}");
+                    for part in parts.into_iter().filter(|p| !p.is_empty()) {
+                        info!(
+                            "Sending message {} to {to} on channel {}",
+                            part, options.channel
+                        );
+                        let message = MessageEnvelope {
+                            timestamp: 0,
+                            source: None,
+                            destination,
+                            channel: MeshChannel::from(options.channel),
+                            message: part,
+                            rx_rssi: 0,
+                            rx_snr: 0.0,
+                            id: 0,
+                            via_store_forward: false,
+                            reply_id: 0,
+                            emoji: false,
+                            kind: MessageKind::Text,
+                            decrypted_with: None,
+                            pki_encrypted: false,
+                        };
+                        if let Err(e) =
+                            util::send_to_radio(IPCMessage::SendMessage(message, options.clone()))
+                                .await
+                        {
+                            error!("Unable to send message to node: {e}");
+                        }
                     }
                 }
-                self.input = "".to_string();
-                self.cursor_position = 0;
+                self.composer.clear();
                 self.input_mode = InputMode::Normal;
             }
         }
     }
 
-    async fn enter_key(&mut self) {
-        match self.tab {
-            MenuTabs::Nodes => self.nodes_tab.enter_key(),
-            MenuTabs::Messages => self.enter_key_messages().await,
-            MenuTabs::Channels => self.channels_tab.enter_key(),
-            _ => {}
+    async fn enter_key_config(&mut self) {
+        match self.input_mode {
+            InputMode::Normal => {
+                let field = match self.tab {
+                    MenuTabs::DeviceConfig => self.device_config_tab.selected_field(),
+                    MenuTabs::ModulesConfig => self.modules_config_tab.selected_field(),
+                    _ => None,
+                };
+                if let Some(field) = field.filter(|f| f.editable()) {
+                    self.composer.set(field.initial_input());
+                    self.input_mode = InputMode::Editing;
+                }
+            }
+            InputMode::Editing => {
+                match self.tab {
+                    MenuTabs::DeviceConfig => {
+                        self.device_config_tab
+                            .apply_edit(self.composer.text())
+                            .await
+                    }
+                    MenuTabs::ModulesConfig => {
+                        self.modules_config_tab
+                            .apply_edit(self.composer.text())
+                            .await
+                    }
+                    _ => {}
+                }
+                self.composer.clear();
+                self.input_mode = InputMode::Normal;
+            }
         }
     }
 
-    fn move_cursor_left(&mut self) {
-        let cursor_moved_left = self.cursor_position.saturating_sub(1);
-        self.cursor_position = self.clamp_cursor(cursor_moved_left);
-    }
-
-    fn move_cursor_right(&mut self) {
-        let cursor_moved_right = self.cursor_position.saturating_add(1);
-        self.cursor_position = self.clamp_cursor(cursor_moved_right);
+    /// Our node's last reported position, if it has one.
+    fn my_position(&self) -> Option<Position> {
+        self.nodes_tab
+            .node_list
+            .get(&self.nodes_tab.my_node_id)
+            .and_then(|cn| cn.node_info.position)
     }
 
-    fn enter_char(&mut self, new_char: char) {
-        let byte_index = self
-            .input
-            .char_indices()
-            .nth(self.cursor_position)
-            .map(|(i, _)| i)
-            .unwrap_or(self.input.len());
-        self.input.insert(byte_index, new_char);
-        self.move_cursor_right();
-    }
-
-    fn delete_char(&mut self) {
-        let is_not_cursor_leftmost = self.cursor_position != 0;
-        if is_not_cursor_leftmost {
-            // Method "remove" is not used on the saved text for deleting the selected char.
-            // Reason: Using remove on String works on bytes instead of the chars.
-            // Using remove would require special care because of char boundaries.
-
-            let current_index = self.cursor_position;
-            let from_left_to_current_index = current_index - 1;
-
-            // Getting all characters before the selected character.
-            let before_char_to_delete = self.input.chars().take(from_left_to_current_index);
-            // Getting all characters after selected character.
-            let after_char_to_delete = self.input.chars().skip(current_index);
-
-            // Put all characters together except the selected one.
-            // By leaving the selected one out, it is forgotten and therefore deleted.
-            self.input = before_char_to_delete.chain(after_char_to_delete).collect();
-            self.move_cursor_left();
+    fn enter_key_waypoints(&mut self) {
+        match self.input_mode {
+            InputMode::Normal => {
+                if let Some(value) = self.waypoints_tab.enter_key() {
+                    self.composer.set(value);
+                    self.input_mode = InputMode::Editing;
+                }
+            }
+            InputMode::Editing => {
+                self.waypoints_tab.apply_edit(self.composer.text());
+                self.composer.clear();
+                self.input_mode = InputMode::Normal;
+            }
         }
     }
 
-    fn clamp_cursor(&self, new_cursor_pos: usize) -> usize {
-        new_cursor_pos.clamp(0, self.input.chars().count())
+    async fn enter_key(&mut self) {
+        match self.tab {
+            MenuTabs::Nodes => self.nodes_tab.enter_key(),
+            MenuTabs::Messages => self.enter_key_messages().await,
+            MenuTabs::Channels => self.channels_tab.enter_key(),
+            MenuTabs::DeviceConfig | MenuTabs::ModulesConfig => self.enter_key_config().await,
+            MenuTabs::Waypoints => self.enter_key_waypoints(),
+            _ => {}
+        }
     }
 
     fn render_event_log(&self, area: Rect, buf: &mut Buffer) {
@@ -458,36 +1012,45 @@ impl App {
         TuiLoggerWidget::default().block(block).render(area, buf)
     }
 
-    fn render_bottom_bar(area: Rect, buf: &mut Buffer) {
+    fn render_bottom_bar(&self, area: Rect, buf: &mut Buffer) {
         let keys = [
-            ("H/←", "Left"),
-            ("L/→", "Right"),
-            ("K/↑", "Up"),
-            ("J/↓", "Down"),
-            ("Enter", "Interact/Send"),
-            ("Q/Esc", "Quit"),
+            (Action::Left, "Left"),
+            (Action::Right, "Right"),
+            (Action::Up, "Up"),
+            (Action::Down, "Down"),
+            (Action::Enter, "Interact/Send"),
+            (Action::Palette, "Commands"),
+            (Action::Export, "Export"),
+            (Action::Back, "Quit"),
         ];
         let dt: OffsetDateTime = OffsetDateTime::now_utc();
 
         let mut spans = keys
             .iter()
+            .filter_map(|(action, desc)| Some((self.keymap.keys_for(*action)?, desc)))
             .flat_map(|(key, desc)| {
                 let key = Span::styled(format!(" {key} "), THEME.key_binding.key);
                 let desc = Span::styled(format!(" {desc} "), THEME.key_binding.description);
                 [key, desc]
             })
             .collect_vec();
+        if let Some(pending) = self.keymap.pending() {
+            spans.push(Span::styled(format!(" {pending} "), THEME.key_binding.key));
+        }
         spans.push(Span::styled(
             format!("| {}", dt.format(consts::DATE_FORMAT).unwrap()),
             THEME.date_display,
         ));
         Line::from(spans)
             .centered()
-            .style((Color::Indexed(236), Color::Indexed(232)))
+            .style(THEME.footer)
             .render(area, buf);
     }
 
     pub fn render_tabs(&self, area: Rect, buf: &mut Buffer) {
+        mouse::tabs(area, MenuTabs::iter().map(MenuTabs::title), |i| {
+            Target::Tab(MenuTabs::from_repr(i).unwrap_or_default())
+        });
         let titles = MenuTabs::iter().map(MenuTabs::title);
         Tabs::new(titles)
             .style(THEME.tabs)
@@ -501,6 +1064,8 @@ impl App {
     pub fn render_selected_tab(&self, area: Rect, buf: &mut Buffer) {
         match self.tab {
             MenuTabs::Nodes => self.nodes_tab.clone().render(area, buf),
+            MenuTabs::Waypoints => self.waypoints_tab.clone().render(area, buf),
+            MenuTabs::RangeTest => self.range_test_tab.clone().render(area, buf),
             MenuTabs::Messages => self.messages_tab.clone().render(area, buf),
             MenuTabs::Channels => self.channels_tab.clone().render(area, buf),
             MenuTabs::DeviceConfig => self.device_config_tab.clone().render(area, buf),
@@ -516,19 +1081,27 @@ impl Widget for &App {
             .direction(Direction::Vertical)
             .constraints(vec![
                 Constraint::Length(1),
+                Constraint::Length(!self.key_changes.is_empty() as u16),
                 Constraint::Min(0),
                 Constraint::Length(12),
                 Constraint::Length(1),
             ]);
-        let [tabs, middle, event_log, bottom_bar] = layout.areas(area);
+        let [tabs, key_warning, middle, event_log, bottom_bar] = layout.areas(area);
+        mouse::clear();
         Block::new().style(THEME.root).render(area, buf);
         self.render_tabs(tabs, buf);
+        if !self.key_changes.is_empty() {
+            self.render_key_warning(key_warning, buf);
+        }
         match self.input_mode {
             InputMode::Editing => self.render_send_message_popup(middle, buf),
             InputMode::Normal => self.render_selected_tab(middle, buf),
         }
+        if let Some(palette) = &self.palette {
+            palette.render(middle, buf);
+        }
         self.render_event_log(event_log, buf);
-        App::render_bottom_bar(bottom_bar, buf);
+        self.render_bottom_bar(bottom_bar, buf);
     }
 }
 
@@ -561,6 +1134,8 @@ pub enum MenuTabs {
     #[default]
     Messages,
     Nodes,
+    Waypoints,
+    RangeTest,
     Channels,
     DeviceConfig,
     ModulesConfig,
@@ -574,6 +1149,12 @@ pub enum InputMode {
     Editing,
 }
 
+/// The canned message a number key picks, counting from one.
+fn canned_message(key: char) -> Option<String> {
+    let n = key.to_digit(10).filter(|n| *n > 0)? as usize;
+    util::canned_messages().get(n - 1).cloned()
+}
+
 pub(crate) fn centered_rect(r: Rect, percent_x: u16, percent_y: u16) -> Rect {
     let popup_layout = Layout::default()
         .direction(Direction::Vertical)
@@ -620,5 +1201,64 @@ pub struct DeviceConfiguration {
     pub detection_sensor: DetectionSensorConfig,
     pub paxcounter: PaxcounterConfig,
     pub channels: HashMap<i32, Channel>,
+    /// Kept apart from `canned_message`, which only holds the module's settings.
+    pub canned_messages: Vec<String>,
     pub last_update: u64,
 }
+
+impl DeviceConfiguration {
+    pub fn apply_config(&mut self, variant: meshtastic::protobufs::config::PayloadVariant) {
+        use meshtastic::protobufs::config::PayloadVariant;
+        match variant {
+            PayloadVariant::Device(d) => self.device = d,
+            PayloadVariant::Position(p) => self.position = p,
+            PayloadVariant::Power(p) => self.power = p,
+            PayloadVariant::Network(n) => self.network = n,
+            PayloadVariant::Display(d) => self.display = d,
+            PayloadVariant::Lora(l) => self.lora = l,
+            PayloadVariant::Bluetooth(b) => self.bluetooth = b,
+            PayloadVariant::Security(s) => self.security = s,
+            PayloadVariant::Sessionkey(sk) => self.session_key = sk,
+            PayloadVariant::DeviceUi(d) => self.device_ui = d,
+        }
+        self.last_update = util::get_secs();
+    }
+
+    pub fn apply_module_config(
+        &mut self,
+        variant: meshtastic::protobufs::module_config::PayloadVariant,
+    ) {
+        use meshtastic::protobufs::module_config::PayloadVariant as mpv;
+        match variant {
+            mpv::Mqtt(o) => self.mqtt = o,
+            mpv::Serial(o) => self.serial = o,
+            mpv::ExternalNotification(o) => self.external_notification = o,
+            mpv::StoreForward(o) => self.store_forward = o,
+            mpv::RangeTest(o) => self.range_test = o,
+            mpv::Telemetry(o) => self.telemetry = o,
+            mpv::CannedMessage(o) => self.canned_message = o,
+            mpv::Audio(o) => self.audio = o,
+            mpv::RemoteHardware(o) => self.remote_hardware = o,
+            mpv::NeighborInfo(o) => self.neighbor_info = o,
+            mpv::AmbientLighting(o) => self.ambient_lighting = o,
+            mpv::DetectionSensor(o) => self.detection_sensor = o,
+            mpv::Paxcounter(o) => self.paxcounter = o,
+        }
+        self.last_update = util::get_secs();
+    }
+
+    /// Stores a channel, giving the unnamed default primary channel a readable name.
+    pub fn apply_channel(&mut self, mut channel: Channel) {
+        if let Some(settings) = channel.settings.as_mut() {
+            if channel.index == 0 && settings.name.is_empty() && settings.psk == [1] {
+                settings.name = DEFAULT_CHANNEL_LABEL.to_string();
+            };
+            info!(
+                "Storing channel config for {} (Ch: {})",
+                settings.name, channel.index
+            );
+            self.channels.insert(channel.index, channel);
+            self.last_update = util::get_secs();
+        }
+    }
+}
@@ -1,4 +1,7 @@
+use crate::theme::ColorDepth;
 use clap::Parser;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 #[derive(Parser)]
 #[command(version, about, long_about=None)]
 pub struct CliArgs {
@@ -15,4 +18,109 @@ pub struct CliArgs {
     pub tcp_port: u16,
     #[arg(short = 'm', long, help = "Show MQTT nodes in output?", action)]
     pub show_mqtt: bool,
+    #[arg(
+        long,
+        help = "Directory that node and telemetry exports (F9) are written to",
+        default_value = "."
+    )]
+    pub export_dir: PathBuf,
+    #[arg(
+        long,
+        help = "Write an export of node and telemetry data on exit",
+        action
+    )]
+    pub export_on_exit: bool,
+    #[arg(
+        long,
+        value_name = "SECS",
+        help = "Listen for this many seconds without the TUI, write an export, and exit"
+    )]
+    pub export_after: Option<u64>,
+    #[arg(
+        long,
+        help = "Leave links that went through MQTT out of the topology export",
+        action
+    )]
+    pub topology_skip_mqtt: bool,
+    #[arg(
+        long,
+        value_name = "SECS",
+        help = "Leave links not heard of for this many seconds out of the topology export"
+    )]
+    pub topology_max_age: Option<u64>,
+    #[arg(
+        long,
+        value_name = "NAME:PSK",
+        help = "An extra channel key (base64 PSK) to decrypt packets with; may be repeated"
+    )]
+    pub channel_key: Vec<String>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Keep nodes' pinned public keys in this file across sessions"
+    )]
+    pub known_keys: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "ADDR",
+        help = "Serve Prometheus metrics on this address, e.g. 127.0.0.1:9464"
+    )]
+    pub metrics_listen: Option<SocketAddr>,
+    #[arg(
+        long,
+        value_name = "HOST[:PORT]",
+        help = "Watch a mesh through an MQTT broker instead of a radio"
+    )]
+    pub mqtt: Option<String>,
+    #[arg(
+        long,
+        help = "The MQTT root topic to read, e.g. msh/EU_868",
+        default_value = "msh/US"
+    )]
+    pub mqtt_topic: String,
+    #[arg(long, help = "MQTT username")]
+    pub mqtt_user: Option<String>,
+    #[arg(long, env = "MESHTUI_MQTT_PASSWORD", help = "MQTT password")]
+    pub mqtt_password: Option<String>,
+    #[arg(
+        long,
+        value_name = "CHANNEL",
+        help = "Publish what we send over MQTT, encrypted for this channel"
+    )]
+    pub mqtt_publish: Option<String>,
+    #[arg(
+        long,
+        help = "The node id to send as over MQTT, e.g. !1234abcd (random if not given)"
+    )]
+    pub mqtt_node_id: Option<String>,
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "The theme to start with: dark, light, high-contrast, solarized, monochrome, or one from --themes-dir"
+    )]
+    pub theme: Option<String>,
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "A directory of .toml theme files to offer alongside the presets"
+    )]
+    pub themes_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        value_enum,
+        help = "How many colours the terminal shows (detected from NO_COLOR, COLORTERM and TERM if not given)"
+    )]
+    pub colors: Option<ColorDepth>,
+    #[arg(
+        long,
+        value_name = "PRESET|FILE",
+        help = "Key bindings: default, vim, emacs, or a TOML keymap file",
+        default_value = "default"
+    )]
+    pub keymap: String,
+    #[arg(
+        long,
+        help = "Leave the mouse to the terminal, so text can be selected and copied as usual"
+    )]
+    pub no_mouse: bool,
 }
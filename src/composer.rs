@@ -0,0 +1,530 @@
+use crate::theme::THEME;
+use crate::util::get_channel_from_id;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use meshtastic::protobufs::mesh_packet::Priority;
+use ratatui::prelude::*;
+use strum::{Display, EnumIter, FromRepr, IntoEnumIterator};
+
+/// The most text a single Meshtastic message can carry, in bytes.
+pub const MAX_MESSAGE_BYTES: usize = 200;
+/// How many sent messages Up/Down can recall.
+const HISTORY_LENGTH: usize = 50;
+/// The most hops the firmware will relay a packet.
+const MAX_HOP_LIMIT: u32 = 7;
+/// The priorities worth choosing between; the firmware reserves the others for itself.
+const PRIORITIES: [Priority; 5] = [
+    Priority::Unset,
+    Priority::Background,
+    Priority::Default,
+    Priority::Reliable,
+    Priority::High,
+];
+
+/// How a new message goes out.  The defaults match what sending used to do: broadcast on the
+/// primary channel, acked, with the radio's own hop limit and priority.
+#[derive(Debug, Clone)]
+pub struct SendOptions {
+    /// None broadcasts; otherwise the node id and a name to show for it.
+    pub destination: Option<(u32, String)>,
+    pub channel: u32,
+    /// None uses the hop limit configured on our radio.
+    pub hop_limit: Option<u32>,
+    pub want_ack: bool,
+    pub priority: Priority,
+    /// Send Unishox2-compressed text when that comes out smaller.
+    pub compress: bool,
+}
+
+impl Default for SendOptions {
+    fn default() -> Self {
+        SendOptions {
+            destination: None,
+            channel: 0,
+            hop_limit: None,
+            want_ack: true,
+            priority: Priority::Unset,
+            compress: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Display, EnumIter, FromRepr, PartialEq, Eq)]
+enum SendOption {
+    #[default]
+    #[strum(to_string = "To")]
+    Destination,
+    Channel,
+    #[strum(to_string = "Hops")]
+    HopLimit,
+    #[strum(to_string = "Ack")]
+    WantAck,
+    Priority,
+    Compress,
+}
+
+/// Steps through `choices` from `current`, wrapping at either end.
+fn step<T: PartialEq + Clone>(choices: &[T], current: &T, delta: i32) -> Option<T> {
+    if choices.is_empty() {
+        return None;
+    }
+    let i = choices.iter().position(|c| c == current).unwrap_or(0) as i32;
+    let n = choices.len() as i32;
+    Some(choices[(i + delta).rem_euclid(n) as usize].clone())
+}
+
+impl SendOptions {
+    fn value(&self, option: SendOption) -> String {
+        match option {
+            SendOption::Destination => match &self.destination {
+                None => "everyone".to_string(),
+                Some((_, name)) => name.clone(),
+            },
+            SendOption::Channel => {
+                let name = get_channel_from_id(self.channel)
+                    .and_then(|c| c.settings)
+                    .map(|s| s.name)
+                    .unwrap_or_default();
+                format!("{} {name}", self.channel)
+            }
+            SendOption::HopLimit => match self.hop_limit {
+                None => "default".to_string(),
+                Some(h) => h.to_string(),
+            },
+            SendOption::WantAck => match self.want_ack {
+                true => "on".to_string(),
+                false => "off".to_string(),
+            },
+            SendOption::Priority => format!("{:?}", self.priority),
+            SendOption::Compress => match self.compress {
+                true => "when smaller".to_string(),
+                false => "off".to_string(),
+            },
+        }
+    }
+
+    /// Moves `option` to its next (`delta` 1) or previous (-1) value.  Destinations are picked
+    /// from `nodes` and channels from `channels`.
+    fn adjust(
+        &mut self,
+        option: SendOption,
+        delta: i32,
+        nodes: &[(u32, String)],
+        channels: &[u32],
+    ) {
+        match option {
+            SendOption::Destination => {
+                let choices: Vec<Option<(u32, String)>> = std::iter::once(None)
+                    .chain(nodes.iter().cloned().map(Some))
+                    .collect();
+                if let Some(d) = step(&choices, &self.destination, delta) {
+                    self.destination = d;
+                }
+            }
+            SendOption::Channel => {
+                if let Some(c) = step(channels, &self.channel, delta) {
+                    self.channel = c;
+                }
+            }
+            SendOption::HopLimit => {
+                let choices: Vec<Option<u32>> = std::iter::once(None)
+                    .chain((0..=MAX_HOP_LIMIT).map(Some))
+                    .collect();
+                if let Some(h) = step(&choices, &self.hop_limit, delta) {
+                    self.hop_limit = h;
+                }
+            }
+            SendOption::WantAck => self.want_ack = !self.want_ack,
+            SendOption::Priority => {
+                if let Some(p) = step(&PRIORITIES, &self.priority, delta) {
+                    self.priority = p;
+                }
+            }
+            SendOption::Compress => self.compress = !self.compress,
+        }
+    }
+}
+
+/// The text being typed into the input popup.  The cursor is a char index, not a byte index, so
+/// multi-byte characters (emoji, accents) move and delete as one.
+#[derive(Debug, Clone, Default)]
+pub struct Composer {
+    text: String,
+    cursor: usize,
+    history: Vec<String>,
+    history_index: Option<usize>,
+    draft: String,
+    /// Split messages that are too long into numbered parts instead of refusing to send them.
+    pub split: bool,
+    pub options: SendOptions,
+    pub options_open: bool,
+    option_focus: SendOption,
+}
+
+impl Composer {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replaces the text, with the cursor at the end.
+    pub fn set(&mut self, text: impl Into<String>) {
+        self.text = text.into();
+        self.cursor = self.text.chars().count();
+        self.history_index = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.set("");
+    }
+
+    pub fn byte_len(&self) -> usize {
+        self.text.len()
+    }
+
+    fn byte_index(&self, cursor: usize) -> usize {
+        self.text
+            .char_indices()
+            .nth(cursor)
+            .map(|(i, _)| i)
+            .unwrap_or(self.text.len())
+    }
+
+    fn len(&self) -> usize {
+        self.text.chars().count()
+    }
+
+    pub fn insert_str(&mut self, s: &str) {
+        let at = self.byte_index(self.cursor);
+        self.text.insert_str(at, s);
+        self.cursor += s.chars().count();
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor == 0 {
+            return;
+        }
+        let start = self.byte_index(self.cursor - 1);
+        let end = self.byte_index(self.cursor);
+        self.text.replace_range(start..end, "");
+        self.cursor -= 1;
+    }
+
+    fn delete(&mut self) {
+        if self.cursor >= self.len() {
+            return;
+        }
+        let start = self.byte_index(self.cursor);
+        let end = self.byte_index(self.cursor + 1);
+        self.text.replace_range(start..end, "");
+    }
+
+    /// The start of the word before the cursor, skipping any whitespace first.
+    fn word_left(&self) -> usize {
+        let chars: Vec<char> = self.text.chars().collect();
+        let mut i = self.cursor;
+        while i > 0 && chars[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        while i > 0 && !chars[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        i
+    }
+
+    /// The end of the word after the cursor, skipping any whitespace first.
+    fn word_right(&self) -> usize {
+        let chars: Vec<char> = self.text.chars().collect();
+        let mut i = self.cursor;
+        while i < chars.len() && chars[i].is_whitespace() {
+            i += 1;
+        }
+        while i < chars.len() && !chars[i].is_whitespace() {
+            i += 1;
+        }
+        i
+    }
+
+    fn delete_word_left(&mut self) {
+        let start = self.word_left();
+        let (from, to) = (self.byte_index(start), self.byte_index(self.cursor));
+        self.text.replace_range(from..to, "");
+        self.cursor = start;
+    }
+
+    /// Remembers a sent message so Up can bring it back.
+    pub fn remember(&mut self, text: &str) {
+        if text.is_empty() || self.history.last().is_some_and(|h| h == text) {
+            return;
+        }
+        self.history.push(text.to_string());
+        if self.history.len() > HISTORY_LENGTH {
+            self.history.remove(0);
+        }
+    }
+
+    fn history_prev(&mut self) {
+        let index = match self.history_index {
+            None if self.history.is_empty() => return,
+            None => {
+                self.draft = self.text.clone();
+                self.history.len() - 1
+            }
+            Some(i) => i.saturating_sub(1),
+        };
+        self.set(self.history[index].clone());
+        self.history_index = Some(index);
+    }
+
+    fn history_next(&mut self) {
+        match self.history_index {
+            None => {}
+            Some(i) if i + 1 < self.history.len() => {
+                self.set(self.history[i + 1].clone());
+                self.history_index = Some(i + 1);
+            }
+            Some(_) => self.set(self.draft.clone()),
+        }
+    }
+
+    /// Handles an editing key.  Enter and Esc are left to the caller; Alt-Enter starts a new line.
+    pub fn key(&mut self, key: KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        match key.code {
+            KeyCode::Enter if alt => self.insert_str("\n"),
+            KeyCode::Left if ctrl || alt => self.cursor = self.word_left(),
+            KeyCode::Right if ctrl || alt => self.cursor = self.word_right(),
+            KeyCode::Char('b') if alt => self.cursor = self.word_left(),
+            KeyCode::Char('f') if alt => self.cursor = self.word_right(),
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::Char('e') if ctrl => self.cursor = self.len(),
+            KeyCode::Char('w') if ctrl => self.delete_word_left(),
+            KeyCode::Backspace if ctrl || alt => self.delete_word_left(),
+            KeyCode::Char(c) => self.insert_str(&c.to_string()),
+            KeyCode::Backspace => self.backspace(),
+            KeyCode::Delete => self.delete(),
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.len(),
+            KeyCode::Up => self.history_prev(),
+            KeyCode::Down => self.history_next(),
+            _ => {}
+        }
+    }
+
+    /// Handles a key while the send options row is open: Tab/Shift-Tab pick an option and
+    /// PgUp/PgDn change it.  Returns false for keys that belong to the text.
+    pub fn options_key(
+        &mut self,
+        key: KeyEvent,
+        nodes: &[(u32, String)],
+        channels: &[u32],
+    ) -> bool {
+        if !self.options_open {
+            return false;
+        }
+        let count = SendOption::iter().count();
+        let focus = self.option_focus as usize;
+        match key.code {
+            KeyCode::Tab => {
+                self.option_focus = SendOption::from_repr((focus + 1) % count).unwrap_or_default()
+            }
+            KeyCode::BackTab => {
+                self.option_focus =
+                    SendOption::from_repr((focus + count - 1) % count).unwrap_or_default()
+            }
+            KeyCode::PageUp => self.options.adjust(self.option_focus, -1, nodes, channels),
+            KeyCode::PageDown => self.options.adjust(self.option_focus, 1, nodes, channels),
+            _ => return false,
+        }
+        true
+    }
+
+    /// The send options row, with the focused option highlighted.
+    pub fn options_line(&self) -> Line<'static> {
+        let mut spans = vec![];
+        for option in SendOption::iter() {
+            let style = match option == self.option_focus {
+                true => THEME.tabs_selected,
+                false => Style::default(),
+            };
+            spans.push(Span::styled(
+                format!("{option}: {}", self.options.value(option)),
+                style,
+            ));
+            spans.push(Span::raw("  "));
+        }
+        Line::from(spans)
+    }
+
+    /// The canned messages as a numbered quick-pick row; only the first nine have shortcuts.
+    pub fn canned_line(messages: &[String]) -> Line<'static> {
+        let mut spans = vec![];
+        for (n, message) in messages.iter().take(9).enumerate() {
+            spans.push(Span::styled(format!("{}", n + 1), THEME.tabs_selected));
+            spans.push(Span::raw(format!(" {message}  ")));
+        }
+        Line::from(spans)
+    }
+
+    /// The text as it will go out: as is if it fits, otherwise split into numbered parts when
+    /// splitting is on.  Returns None if it's too long and splitting is off.
+    pub fn parts(&self) -> Option<Vec<String>> {
+        if self.byte_len() <= MAX_MESSAGE_BYTES {
+            return Some(vec![self.text.clone()]);
+        }
+        if !self.split {
+            return None;
+        }
+        Some(split_message(&self.text, MAX_MESSAGE_BYTES))
+    }
+
+    /// The text laid out for the popup, with the cursor drawn as a highlighted cell.
+    pub fn lines(&self) -> Text<'static> {
+        let mut lines = vec![Line::default()];
+        let mut push = |c: char, style: Style| match c {
+            '\n' => {
+                if style != Style::default() {
+                    lines
+                        .last_mut()
+                        .unwrap()
+                        .push_span(Span::styled(" ", style));
+                }
+                lines.push(Line::default());
+            }
+            c => lines
+                .last_mut()
+                .unwrap()
+                .push_span(Span::styled(c.to_string(), style)),
+        };
+        for (i, c) in self.text.chars().enumerate() {
+            let style = match i == self.cursor {
+                true => THEME.composer_cursor,
+                false => Style::default(),
+            };
+            push(c, style);
+        }
+        if self.cursor >= self.len() {
+            push(' ', THEME.composer_cursor);
+        }
+        Text::from(lines)
+    }
+
+    /// "123/200 bytes", plus how many parts it will go out as.
+    pub fn counter(&self) -> Line<'static> {
+        let bytes = self.byte_len();
+        let split = match self.split {
+            true => "split on",
+            false => "split off",
+        };
+        let text = match self.parts() {
+            Some(parts) if parts.len() > 1 => {
+                format!(
+                    " {bytes}/{MAX_MESSAGE_BYTES} bytes, {} parts ({split}) ",
+                    parts.len()
+                )
+            }
+            _ => format!(" {bytes}/{MAX_MESSAGE_BYTES} bytes ({split}) "),
+        };
+        match self.parts() {
+            Some(_) => Line::raw(text),
+            None => Line::styled(text, THEME.composer_over_limit),
+        }
+    }
+}
+
+/// Splits `text` into parts of at most `max_bytes`, each prefixed with "(n/m) ".  Breaks at
+/// whitespace where it can and never inside a character.
+pub fn split_message(text: &str, max_bytes: usize) -> Vec<String> {
+    // the prefix grows with the number of parts, so retry until the count settles
+    let mut count = 1;
+    loop {
+        let prefix_len = format!("({count}/{count}) ").len();
+        let chunks = chunk(text, max_bytes.saturating_sub(prefix_len).max(1));
+        if chunks.len() <= count {
+            let total = chunks.len();
+            return chunks
+                .into_iter()
+                .enumerate()
+                .map(|(i, c)| format!("({}/{total}) {c}", i + 1))
+                .collect();
+        }
+        count = chunks.len();
+    }
+}
+
+fn chunk(text: &str, max_bytes: usize) -> Vec<String> {
+    let mut chunks = vec![];
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        if rest.len() <= max_bytes {
+            chunks.push(rest.to_string());
+            break;
+        }
+        let mut end = max_bytes;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        // a limit narrower than the next char would never get past it, so let that char run over
+        if end == 0 {
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        if let Some(space) = rest[..end].rfind(char::is_whitespace).filter(|s| *s > 0) {
+            end = space;
+        }
+        chunks.push(rest[..end].trim_end().to_string());
+        rest = rest[end..].trim_start();
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parts_fit_the_limit_and_break_between_words() {
+        let text = "the quick brown fox jumps over the lazy dog";
+        let parts = split_message(text, 20);
+        assert_eq!(
+            parts,
+            [
+                "(1/4) the quick",
+                "(2/4) brown fox",
+                "(3/4) jumps over",
+                "(4/4) the lazy dog"
+            ]
+        );
+        assert!(parts.iter().all(|p| p.len() <= 20));
+    }
+
+    #[test]
+    fn the_prefix_counts_against_the_limit() {
+        // fourteen bytes a part would take ten parts, but "(10/10) " leaves room for only twelve
+        let text = "x".repeat(10 * 14);
+        let parts = split_message(&text, 20);
+        assert_eq!(parts.len(), 12);
+        assert!(parts[0].starts_with("(1/12) "));
+        assert!(parts[11].starts_with("(12/12) "));
+        assert!(parts.iter().all(|p| p.len() <= 20));
+        let rejoined: String = parts.iter().map(|p| p.split_once(' ').unwrap().1).collect();
+        assert_eq!(rejoined, text);
+    }
+
+    #[test]
+    fn parts_break_on_char_boundaries() {
+        // two bytes each, against an odd number of bytes for the text
+        let text = "é".repeat(20);
+        let parts = split_message(&text, 13);
+        assert!(parts.iter().all(|p| p.len() <= 13));
+        let rejoined: String = parts.iter().map(|p| p.split_once(' ').unwrap().1).collect();
+        assert_eq!(rejoined, text);
+    }
+
+    #[test]
+    fn chars_wider_than_the_limit_still_make_progress() {
+        assert_eq!(chunk("🙂🙂 a", 2), ["🙂", "🙂", "a"]);
+        let parts = split_message("🙂🙂🙂", 3);
+        assert_eq!(parts, ["(1/3) 🙂", "(2/3) 🙂", "(3/3) 🙂"]);
+    }
+}
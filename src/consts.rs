@@ -3,6 +3,9 @@ use time::macros::format_description;
 
 pub const DATE_FORMAT: &[BorrowedFormatItem<'_>] =
     format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
+pub const CHART_TIME_FORMAT: &[BorrowedFormatItem<'_>] = format_description!("[hour]:[minute]");
+pub const CHART_DATE_FORMAT: &[BorrowedFormatItem<'_>] =
+    format_description!("[month]-[day] [hour]:[minute]");
 
 pub const TICK_RATE: f64 = 4.0_f64;
 pub const FRAME_RATE: f64 = 2.0_f64;
@@ -10,6 +13,7 @@ pub const FRAME_RATE: f64 = 2.0_f64;
 pub const MPSC_BUFFER_SIZE: usize = 100_usize;
 pub const GPS_PRECISION_FACTOR: f32 = 0.0000001_f32;
 pub const MAX_MSG_RETENTION: usize = 128_usize;
+pub const MAX_TIMESERIES_RETENTION: usize = 2048_usize;
 
 pub const NODE_HELP_TEXT: &str = r######"
 The node screen shows a list of nodes as reported by your device.  The list is constantly sorted by
@@ -31,8 +35,9 @@ ID -- This is the 'address' of the node.  The value is a prefix and an 8-charact
               Short -- Shortname of node.
                Long -- Long description for node.
          RF Details -- The SNR and RSSI of the received packets.
-               Hops -- the reported number of hops away this device is in the mesh. "MQTT" if
-                       the node is reachable only via MQTT.
+               Hops -- how many hops the last packet from this node took to reach us, and
+                       which relay passed it on ("2 via ABCD"). "direct" if we heard it
+                       first-hand, "MQTT" if the node is reachable only via MQTT.
           Neighbors -- If the node is reporting its neighbor table, this is the number of
                        reported neighbors it has.
            Distance -- If your local node has a GPS fix, this will show the distance to the
@@ -43,4 +48,9 @@ ID -- This is the 'address' of the node.  The value is a prefix and an 8-charact
 Last Heard NodeInfo -- The last time we received a full NodeInfo packet for this node.
         Last Update -- the last time we updated our database with any information about
                        this node.
+
+Public keys:
+The detail view shows the fingerprint of each node's public key.  The first key we see for a node
+is pinned; if the node later presents a different one, a red banner warns you, since that can mean
+someone else is using its node id.  Press K in that node's detail view to trust the new key.
 "######;
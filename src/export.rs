@@ -0,0 +1,911 @@
+use crate::consts;
+use crate::tabs::nodes::{ComprehensiveNode, TimeSeriesData};
+use crate::tabs::range_test::RangeTestHit;
+use crate::tabs::waypoints::WaypointEntry;
+use crate::topology::{self, TopologyFilter};
+use crate::util::get_secs;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+
+/// A flattened, spreadsheet-friendly view of a node from the node table.
+#[derive(Debug, Serialize)]
+pub struct NodeRecord {
+    pub id: u32,
+    pub id_hex: String,
+    pub short_name: String,
+    pub long_name: String,
+    pub hardware: String,
+    pub role: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub altitude: Option<i32>,
+    pub last_heard_nodeinfo: Option<u32>,
+    pub last_seen: u64,
+    pub snr: Option<f32>,
+    pub rssi: Option<i32>,
+    pub hops_away: Option<u32>,
+    pub via_mqtt: bool,
+    pub battery_level: Option<u32>,
+    pub voltage: Option<f32>,
+}
+
+impl From<&ComprehensiveNode> for NodeRecord {
+    fn from(cn: &ComprehensiveNode) -> Self {
+        let user = cn.node_info.user.clone().unwrap_or_default();
+        let position = cn.node_info.position.unwrap_or_default();
+        let device = cn.node_info.device_metrics.unwrap_or_default();
+        let coordinate = |i: i32| match i {
+            0 => None,
+            i => Some(i as f64 * consts::GPS_PRECISION_FACTOR as f64),
+        };
+        NodeRecord {
+            id: cn.id,
+            id_hex: format!("!{:08x}", cn.id),
+            short_name: user.short_name.clone(),
+            long_name: user.long_name.clone(),
+            hardware: format!("{:?}", user.hw_model()),
+            role: format!("{:?}", user.role()),
+            latitude: coordinate(position.latitude_i()),
+            longitude: coordinate(position.longitude_i()),
+            altitude: position.altitude,
+            last_heard_nodeinfo: Some(cn.node_info.last_heard).filter(|t| *t > 0),
+            last_seen: cn.last_seen,
+            snr: Some(cn.last_snr).filter(|s| *s != 0.0),
+            rssi: Some(cn.last_rssi).filter(|r| *r != 0),
+            hops_away: cn.node_info.hops_away,
+            via_mqtt: cn.node_info.via_mqtt,
+            battery_level: device.battery_level,
+            voltage: device.voltage,
+        }
+    }
+}
+
+/// One telemetry sample, with every metric we know how to chart as its own column.
+#[derive(Debug, Serialize)]
+pub struct TelemetryRecord {
+    pub timestamp: u64,
+    pub time_utc: String,
+    pub rssi: Option<f64>,
+    pub snr: Option<f64>,
+    pub battery_level: Option<u32>,
+    pub voltage: Option<f32>,
+    pub channel_utilization: Option<f32>,
+    pub air_util_tx: Option<f32>,
+    pub uptime_seconds: Option<u32>,
+    pub temperature: Option<f32>,
+    pub relative_humidity: Option<f32>,
+    pub barometric_pressure: Option<f32>,
+    pub gas_resistance: Option<f32>,
+    pub env_voltage: Option<f32>,
+    pub env_current: Option<f32>,
+    pub iaq: Option<u32>,
+    pub lux: Option<f32>,
+    pub wind_direction: Option<u32>,
+    pub wind_speed: Option<f32>,
+    pub pm10_standard: Option<u32>,
+    pub pm25_standard: Option<u32>,
+    pub pm100_standard: Option<u32>,
+    pub particles_03um: Option<u32>,
+    pub particles_05um: Option<u32>,
+    pub particles_10um: Option<u32>,
+    pub particles_25um: Option<u32>,
+    pub particles_50um: Option<u32>,
+    pub particles_100um: Option<u32>,
+    pub co2: Option<u32>,
+    pub ch1_voltage: Option<f32>,
+    pub ch1_current: Option<f32>,
+    pub ch2_voltage: Option<f32>,
+    pub ch2_current: Option<f32>,
+    pub ch3_voltage: Option<f32>,
+    pub ch3_current: Option<f32>,
+}
+
+impl From<&TimeSeriesData> for TelemetryRecord {
+    fn from(d: &TimeSeriesData) -> Self {
+        TelemetryRecord {
+            timestamp: d.timestamp,
+            time_utc: format_timestamp(d.timestamp),
+            rssi: Some(d.rssi).filter(|v| *v != 0.0),
+            snr: Some(d.snr).filter(|v| *v != 0.0),
+            battery_level: d.device.battery_level,
+            voltage: d.device.voltage,
+            channel_utilization: d.device.channel_utilization,
+            air_util_tx: d.device.air_util_tx,
+            uptime_seconds: d.device.uptime_seconds,
+            temperature: d.environment.temperature,
+            relative_humidity: d.environment.relative_humidity,
+            barometric_pressure: d.environment.barometric_pressure,
+            gas_resistance: d.environment.gas_resistance,
+            env_voltage: d.environment.voltage,
+            env_current: d.environment.current,
+            iaq: d.environment.iaq,
+            lux: d.environment.lux,
+            wind_direction: d.environment.wind_direction,
+            wind_speed: d.environment.wind_speed,
+            pm10_standard: d.air_quality.pm10_standard,
+            pm25_standard: d.air_quality.pm25_standard,
+            pm100_standard: d.air_quality.pm100_standard,
+            particles_03um: d.air_quality.particles_03um,
+            particles_05um: d.air_quality.particles_05um,
+            particles_10um: d.air_quality.particles_10um,
+            particles_25um: d.air_quality.particles_25um,
+            particles_50um: d.air_quality.particles_50um,
+            particles_100um: d.air_quality.particles_100um,
+            co2: d.air_quality.co2,
+            ch1_voltage: d.power.ch1_voltage,
+            ch1_current: d.power.ch1_current,
+            ch2_voltage: d.power.ch2_voltage,
+            ch2_current: d.power.ch2_current,
+            ch3_voltage: d.power.ch3_voltage,
+            ch3_current: d.power.ch3_current,
+        }
+    }
+}
+
+pub(crate) fn format_timestamp(ts: u64) -> String {
+    OffsetDateTime::from_unix_timestamp(ts as i64)
+        .ok()
+        .and_then(|dt| dt.format(consts::DATE_FORMAT).ok())
+        .unwrap_or_default()
+}
+
+/// One range test packet, as written to the session CSV.
+#[derive(Debug, Serialize)]
+pub struct RangeTestRecord {
+    pub timestamp: u64,
+    pub time_utc: String,
+    pub from_hex: String,
+    pub from_name: String,
+    pub seq: u32,
+    pub sender_latitude: Option<f64>,
+    pub sender_longitude: Option<f64>,
+    pub our_latitude: Option<f64>,
+    pub our_longitude: Option<f64>,
+    pub distance_m: Option<f64>,
+    pub snr: f32,
+    pub rssi: i32,
+}
+
+impl From<&RangeTestHit> for RangeTestRecord {
+    fn from(hit: &RangeTestHit) -> Self {
+        RangeTestRecord {
+            timestamp: hit.timestamp,
+            time_utc: format_timestamp(hit.timestamp),
+            from_hex: format!("!{:08x}", hit.from),
+            from_name: hit.from_name.clone(),
+            seq: hit.seq,
+            sender_latitude: hit.sender_position.map(|p| p.0),
+            sender_longitude: hit.sender_position.map(|p| p.1),
+            our_latitude: hit.our_position.map(|p| p.0),
+            our_longitude: hit.our_position.map(|p| p.1),
+            distance_m: hit.distance_m,
+            snr: hit.rx_snr,
+            rssi: hit.rx_rssi,
+        }
+    }
+}
+
+fn gpx_time(ts: u64) -> String {
+    OffsetDateTime::from_unix_timestamp(ts as i64)
+        .ok()
+        .and_then(|dt| {
+            dt.format(&time::format_description::well_known::Rfc3339)
+                .ok()
+        })
+        .unwrap_or_default()
+}
+
+/// Creates a fresh, timestamped directory under `base` to hold one export run.  A second export
+/// in the same second gets a `-2` on the end, and so on, rather than writing over the first.
+pub fn export_directory(base: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(base).with_context(|| format!("creating {}", base.display()))?;
+    let stem = format!("meshtui-export-{}", get_secs());
+    for n in 1.. {
+        let dir = match n {
+            1 => base.join(&stem),
+            n => base.join(format!("{stem}-{n}")),
+        };
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("creating {}", dir.display())),
+        }
+    }
+    unreachable!("ran out of export directory names")
+}
+
+fn sorted_nodes(node_list: &HashMap<u32, ComprehensiveNode>) -> Vec<&ComprehensiveNode> {
+    let mut nodes: Vec<&ComprehensiveNode> = node_list.values().collect();
+    nodes.sort_by_key(|cn| cn.id);
+    nodes
+}
+
+pub fn write_nodes_json(path: &Path, node_list: &HashMap<u32, ComprehensiveNode>) -> Result<()> {
+    let records: Vec<NodeRecord> = sorted_nodes(node_list)
+        .into_iter()
+        .map(NodeRecord::from)
+        .collect();
+    let file = fs::File::create(path).with_context(|| format!("creating {}", path.display()))?;
+    serde_json::to_writer_pretty(file, &records)?;
+    Ok(())
+}
+
+pub fn write_nodes_csv(path: &Path, node_list: &HashMap<u32, ComprehensiveNode>) -> Result<()> {
+    let mut writer =
+        csv::Writer::from_path(path).with_context(|| format!("creating {}", path.display()))?;
+    for cn in sorted_nodes(node_list) {
+        writer.serialize(NodeRecord::from(cn))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_telemetry_csv(path: &Path, cn: &ComprehensiveNode) -> Result<()> {
+    let mut writer =
+        csv::Writer::from_path(path).with_context(|| format!("creating {}", path.display()))?;
+    for sample in cn.timeseries.iter() {
+        writer.serialize(TelemetryRecord::from(sample))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes the node table as JSON and CSV, plus one telemetry CSV per node that has history, and
+/// node positions, tracks and waypoints as GeoJSON, KML and GPX, and the mesh topology as
+/// Graphviz DOT.  Returns the directory the files were written to.
+pub fn export_all(
+    base: &Path,
+    node_list: &HashMap<u32, ComprehensiveNode>,
+    waypoints: &HashMap<u32, WaypointEntry>,
+    my_node_id: u32,
+    topology_filter: &TopologyFilter,
+) -> Result<PathBuf> {
+    let dir = export_directory(base)?;
+    write_nodes_json(&dir.join("nodes.json"), node_list)?;
+    write_nodes_csv(&dir.join("nodes.csv"), node_list)?;
+    let waypoints = current_waypoints(waypoints);
+    write_geojson(&dir.join("map.geojson"), node_list, &waypoints)?;
+    write_kml(&dir.join("map.kml"), node_list, &waypoints)?;
+    write_gpx(&dir.join("map.gpx"), node_list, &waypoints)?;
+    topology::write_dot(
+        &dir.join("topology.dot"),
+        node_list,
+        my_node_id,
+        topology_filter,
+    )?;
+    for cn in sorted_nodes(node_list) {
+        if cn.timeseries.is_empty() {
+            continue;
+        }
+        write_telemetry_csv(&dir.join(format!("telemetry-{:08x}.csv", cn.id)), cn)?;
+    }
+    Ok(dir)
+}
+
+/// The waypoints worth putting on a map: placed, and not yet expired.
+fn current_waypoints(waypoints: &HashMap<u32, WaypointEntry>) -> Vec<&WaypointEntry> {
+    let mut current: Vec<&WaypointEntry> = waypoints
+        .values()
+        .filter(|w| !w.expired() && w.location().is_some())
+        .collect();
+    current.sort_by_key(|w| w.waypoint.id);
+    current
+}
+
+/// A node's position from its record, if it has reported one.
+fn record_position(record: &NodeRecord) -> Option<(f64, f64)> {
+    Some((record.latitude?, record.longitude?))
+}
+
+fn display_name(record: &NodeRecord) -> String {
+    match record.long_name.is_empty() {
+        true => record.id_hex.clone(),
+        false => record.long_name.clone(),
+    }
+}
+
+/// One line about a node for the description fields of KML and GPX.
+fn node_description(record: &NodeRecord) -> String {
+    let mut parts = vec![
+        record.id_hex.clone(),
+        record.role.clone(),
+        record.hardware.clone(),
+    ];
+    if let Some(battery) = record.battery_level {
+        parts.push(match battery {
+            101.. => "powered".to_string(),
+            b => format!("battery {b}%"),
+        });
+    }
+    if let Some(voltage) = record.voltage {
+        parts.push(format!("{voltage:.2} V"));
+    }
+    parts.push(format!("last heard {}", format_timestamp(record.last_seen)));
+    parts.join(", ")
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Writes node positions and waypoints as GeoJSON points, and node tracks as line strings.
+/// Every feature has a `kind` property saying which it is.
+pub fn write_geojson(
+    path: &Path,
+    node_list: &HashMap<u32, ComprehensiveNode>,
+    waypoints: &[&WaypointEntry],
+) -> Result<()> {
+    let mut features = vec![];
+    for cn in sorted_nodes(node_list) {
+        let record = NodeRecord::from(cn);
+        if let Some((lat, lon)) = record_position(&record) {
+            let mut coordinates = vec![json!(lon), json!(lat)];
+            coordinates.extend(record.altitude.map(|a| json!(a)));
+            let mut properties = serde_json::to_value(&record)?;
+            properties["kind"] = json!("node");
+            properties["name"] = json!(display_name(&record));
+            properties["last_heard_utc"] = json!(format_timestamp(record.last_seen));
+            features.push(json!({
+                "type": "Feature",
+                "geometry": {"type": "Point", "coordinates": coordinates},
+                "properties": properties,
+            }));
+        }
+        if cn.track.len() > 1 {
+            let coordinates: Vec<Value> = cn
+                .track
+                .iter()
+                .map(|p| match p.altitude {
+                    Some(a) => json!([p.longitude, p.latitude, a]),
+                    None => json!([p.longitude, p.latitude]),
+                })
+                .collect();
+            features.push(json!({
+                "type": "Feature",
+                "geometry": {"type": "LineString", "coordinates": coordinates},
+                "properties": {
+                    "kind": "track",
+                    "id_hex": record.id_hex,
+                    "name": display_name(&record),
+                    "points": cn.track.len(),
+                    "start_utc": cn.track.front().map(|p| format_timestamp(p.timestamp)),
+                    "end_utc": cn.track.back().map(|p| format_timestamp(p.timestamp)),
+                },
+            }));
+        }
+    }
+    for entry in waypoints {
+        let Some((lat, lon)) = entry.location() else {
+            continue;
+        };
+        let w = &entry.waypoint;
+        features.push(json!({
+            "type": "Feature",
+            "geometry": {"type": "Point", "coordinates": [lon, lat]},
+            "properties": {
+                "kind": "waypoint",
+                "id": w.id,
+                "name": w.name,
+                "description": w.description,
+                "owner_hex": format!("!{:08x}", entry.owner),
+                "owner_name": entry.owner_name,
+                "expire_utc": (w.expire != 0).then(|| format_timestamp(w.expire as u64)),
+            },
+        }));
+    }
+    let collection = json!({"type": "FeatureCollection", "features": features});
+    let file = fs::File::create(path).with_context(|| format!("creating {}", path.display()))?;
+    serde_json::to_writer_pretty(file, &collection)?;
+    Ok(())
+}
+
+fn kml_coordinates(lat: f64, lon: f64, altitude: Option<i32>) -> String {
+    match altitude {
+        Some(a) => format!("{lon:.7},{lat:.7},{a}"),
+        None => format!("{lon:.7},{lat:.7}"),
+    }
+}
+
+/// Writes nodes, tracks and waypoints as KML, one folder each.  Node placemarks carry the
+/// node table's columns as extended data.
+pub fn write_kml(
+    path: &Path,
+    node_list: &HashMap<u32, ComprehensiveNode>,
+    waypoints: &[&WaypointEntry],
+) -> Result<()> {
+    let mut kml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <kml xmlns=\"http://www.opengis.net/kml/2.2\">\n<Document><name>meshtui</name>\n",
+    );
+    kml.push_str("<Folder><name>Nodes</name>\n");
+    for cn in sorted_nodes(node_list) {
+        let record = NodeRecord::from(cn);
+        let Some((lat, lon)) = record_position(&record) else {
+            continue;
+        };
+        let data = match serde_json::to_value(&record)? {
+            Value::Object(map) => map
+                .into_iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| {
+                    let v = match v {
+                        Value::String(s) => s,
+                        v => v.to_string(),
+                    };
+                    format!(
+                        "<Data name=\"{k}\"><value>{}</value></Data>",
+                        xml_escape(&v)
+                    )
+                })
+                .collect::<String>(),
+            _ => String::new(),
+        };
+        let timestamp = match record.last_seen {
+            0 => String::new(),
+            t => format!("<TimeStamp><when>{}</when></TimeStamp>", gpx_time(t)),
+        };
+        writeln!(
+            kml,
+            "  <Placemark><name>{}</name><description>{}</description>\
+             {timestamp}<ExtendedData>{data}</ExtendedData>\
+             <Point><coordinates>{}</coordinates></Point></Placemark>",
+            xml_escape(&display_name(&record)),
+            xml_escape(&node_description(&record)),
+            kml_coordinates(lat, lon, record.altitude),
+        )?;
+    }
+    kml.push_str("</Folder>\n<Folder><name>Tracks</name>\n");
+    for cn in sorted_nodes(node_list)
+        .into_iter()
+        .filter(|cn| cn.track.len() > 1)
+    {
+        let record = NodeRecord::from(cn);
+        let coordinates = cn
+            .track
+            .iter()
+            .map(|p| kml_coordinates(p.latitude, p.longitude, p.altitude))
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(
+            kml,
+            "  <Placemark><name>{}</name><description>{} positions, {} to {}</description>\
+             <LineString><tessellate>1</tessellate><coordinates>{coordinates}</coordinates>\
+             </LineString></Placemark>",
+            xml_escape(&display_name(&record)),
+            cn.track.len(),
+            cn.track
+                .front()
+                .map_or(String::new(), |p| format_timestamp(p.timestamp)),
+            cn.track
+                .back()
+                .map_or(String::new(), |p| format_timestamp(p.timestamp)),
+        )?;
+    }
+    kml.push_str("</Folder>\n<Folder><name>Waypoints</name>\n");
+    for entry in waypoints {
+        let Some((lat, lon)) = entry.location() else {
+            continue;
+        };
+        writeln!(
+            kml,
+            "  <Placemark><name>{}</name><description>{} (from {})</description>\
+             <Point><coordinates>{}</coordinates></Point></Placemark>",
+            xml_escape(&entry.waypoint.name),
+            xml_escape(&entry.waypoint.description),
+            xml_escape(&entry.owner_name),
+            kml_coordinates(lat, lon, None),
+        )?;
+    }
+    kml.push_str("</Folder>\n</Document>\n</kml>\n");
+    fs::write(path, kml).with_context(|| format!("creating {}", path.display()))?;
+    Ok(())
+}
+
+/// Writes node positions and waypoints as GPX waypoints, and one track per node that has moved.
+pub fn write_gpx(
+    path: &Path,
+    node_list: &HashMap<u32, ComprehensiveNode>,
+    waypoints: &[&WaypointEntry],
+) -> Result<()> {
+    let mut gpx = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <gpx version=\"1.1\" creator=\"meshtui\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n",
+    );
+    for cn in sorted_nodes(node_list) {
+        let record = NodeRecord::from(cn);
+        let Some((lat, lon)) = record_position(&record) else {
+            continue;
+        };
+        let elevation = record
+            .altitude
+            .map(|a| format!("<ele>{a}</ele>"))
+            .unwrap_or_default();
+        let time = match record.last_seen {
+            0 => String::new(),
+            t => format!("<time>{}</time>", gpx_time(t)),
+        };
+        writeln!(
+            gpx,
+            "  <wpt lat=\"{lat:.7}\" lon=\"{lon:.7}\">{elevation}{time}<name>{}</name>\
+             <desc>{}</desc><type>node</type></wpt>",
+            xml_escape(&display_name(&record)),
+            xml_escape(&node_description(&record)),
+        )?;
+    }
+    for entry in waypoints {
+        let Some((lat, lon)) = entry.location() else {
+            continue;
+        };
+        writeln!(
+            gpx,
+            "  <wpt lat=\"{lat:.7}\" lon=\"{lon:.7}\"><name>{}</name><desc>{} (from {})</desc>\
+             <type>waypoint</type></wpt>",
+            xml_escape(&entry.waypoint.name),
+            xml_escape(&entry.waypoint.description),
+            xml_escape(&entry.owner_name),
+        )?;
+    }
+    for cn in sorted_nodes(node_list)
+        .into_iter()
+        .filter(|cn| cn.track.len() > 1)
+    {
+        let record = NodeRecord::from(cn);
+        writeln!(
+            gpx,
+            "  <trk><name>{}</name><trkseg>",
+            xml_escape(&display_name(&record))
+        )?;
+        for p in &cn.track {
+            let elevation = p
+                .altitude
+                .map(|a| format!("<ele>{a}</ele>"))
+                .unwrap_or_default();
+            writeln!(
+                gpx,
+                "    <trkpt lat=\"{:.7}\" lon=\"{:.7}\">{elevation}<time>{}</time></trkpt>",
+                p.latitude,
+                p.longitude,
+                gpx_time(p.timestamp)
+            )?;
+        }
+        gpx.push_str("  </trkseg></trk>\n");
+    }
+    gpx.push_str("</gpx>\n");
+    fs::write(path, gpx).with_context(|| format!("creating {}", path.display()))?;
+    Ok(())
+}
+
+pub fn write_range_test_csv(path: &Path, hits: &[RangeTestHit]) -> Result<()> {
+    let mut writer =
+        csv::Writer::from_path(path).with_context(|| format!("creating {}", path.display()))?;
+    for hit in hits {
+        writer.serialize(RangeTestRecord::from(hit))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes each packet as a waypoint where we heard it, and the places we heard them from as a
+/// track, so the session can be dropped straight onto a map.
+pub fn write_range_test_gpx(path: &Path, hits: &[RangeTestHit]) -> Result<()> {
+    let mut gpx = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <gpx version=\"1.1\" creator=\"meshtui\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n",
+    );
+    for hit in hits {
+        let Some((lat, lon)) = hit.our_position else {
+            continue;
+        };
+        let distance = hit
+            .distance_m
+            .map(|d| format!(", {d:.0} m"))
+            .unwrap_or_default();
+        writeln!(
+            gpx,
+            "  <wpt lat=\"{lat:.7}\" lon=\"{lon:.7}\"><time>{}</time><name>seq {} from !{:08x}</name>\
+             <desc>SNR {:.2}, RSSI {}{distance}</desc></wpt>",
+            gpx_time(hit.timestamp),
+            hit.seq,
+            hit.from,
+            hit.rx_snr,
+            hit.rx_rssi,
+        )?;
+    }
+    gpx.push_str("  <trk><name>range test</name><trkseg>\n");
+    for hit in hits {
+        if let Some((lat, lon)) = hit.our_position {
+            writeln!(
+                gpx,
+                "    <trkpt lat=\"{lat:.7}\" lon=\"{lon:.7}\"><time>{}</time></trkpt>",
+                gpx_time(hit.timestamp)
+            )?;
+        }
+    }
+    gpx.push_str("  </trkseg></trk>\n</gpx>\n");
+    fs::write(path, gpx).with_context(|| format!("creating {}", path.display()))?;
+    Ok(())
+}
+
+/// Writes a range test session as CSV and GPX.  Returns the directory the files were written to.
+pub fn export_range_test(base: &Path, hits: &[RangeTestHit]) -> Result<PathBuf> {
+    let dir = export_directory(base)?;
+    write_range_test_csv(&dir.join("range-test.csv"), hits)?;
+    write_range_test_gpx(&dir.join("range-test.gpx"), hits)?;
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use meshtastic::protobufs::{DeviceMetrics, EnvironmentMetrics, NodeInfo, Position, User, Waypoint};
+
+    const NAME: &str = "Tom & Jerry's <base> \"north\"";
+    const ESCAPED: &str = "Tom &amp; Jerry's &lt;base&gt; &quot;north&quot;";
+
+    fn position(latitude: f64, longitude: f64, time: u32) -> Position {
+        Position {
+            latitude_i: Some((latitude / consts::GPS_PRECISION_FACTOR as f64).round() as i32),
+            longitude_i: Some((longitude / consts::GPS_PRECISION_FACTOR as f64).round() as i32),
+            altitude: Some(12),
+            time,
+            ..Default::default()
+        }
+    }
+
+    /// One node that has moved between two places, and one waypoint, both with awkward names.
+    fn map() -> (HashMap<u32, ComprehensiveNode>, HashMap<u32, WaypointEntry>) {
+        let mut cn = ComprehensiveNode {
+            id: 0x1234abcd,
+            node_info: NodeInfo {
+                num: 0x1234abcd,
+                user: Some(User {
+                    long_name: NAME.to_string(),
+                    short_name: "TJ".to_string(),
+                    ..Default::default()
+                }),
+                position: Some(position(51.5, -0.25, 1_700_000_100)),
+                ..Default::default()
+            },
+            last_seen: 1_700_000_100,
+            ..Default::default()
+        };
+        cn.record_position(&position(51.0, -0.5, 1_700_000_000));
+        cn.record_position(&position(51.5, -0.25, 1_700_000_100));
+        let waypoint = WaypointEntry {
+            waypoint: Waypoint {
+                id: 7,
+                latitude_i: Some(515_000_000),
+                longitude_i: Some(-2_500_000),
+                name: NAME.to_string(),
+                description: "<meet> here".to_string(),
+                ..Default::default()
+            },
+            owner: cn.id,
+            owner_name: "TJ".to_string(),
+            channel: 0,
+        };
+        (HashMap::from([(cn.id, cn)]), HashMap::from([(7, waypoint)]))
+    }
+
+    type MapWriter = fn(&Path, &HashMap<u32, ComprehensiveNode>, &[&WaypointEntry]) -> Result<()>;
+
+    /// Runs a map writer over `map()` and returns what it wrote.
+    fn write(name: &str, writer: MapWriter) -> String {
+        let (nodes, waypoints) = map();
+        let path = scratch(name);
+        writer(&path, &nodes, &current_waypoints(&waypoints)).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        text
+    }
+
+    /// GeoJSON coordinates, rounded to the precision the radio reports them at.
+    fn coordinates(geometry: &Value) -> Value {
+        match geometry {
+            Value::Array(values) => Value::Array(values.iter().map(coordinates).collect()),
+            Value::Number(n) => json!((n.as_f64().unwrap() * 1e7).round() / 1e7),
+            v => v.clone(),
+        }
+    }
+
+    #[test]
+    fn geojson_has_a_feature_per_node_track_and_waypoint() {
+        let collection: Value = serde_json::from_str(&write("map.geojson", write_geojson)).unwrap();
+        assert_eq!(collection["type"], "FeatureCollection");
+        let features = collection["features"].as_array().unwrap();
+        let kinds: Vec<&str> = features
+            .iter()
+            .map(|f| f["properties"]["kind"].as_str().unwrap())
+            .collect();
+        assert_eq!(kinds, ["node", "track", "waypoint"]);
+
+        let node = &features[0];
+        assert_eq!(node["geometry"]["type"], "Point");
+        assert_eq!(
+            coordinates(&node["geometry"]["coordinates"]),
+            json!([-0.25, 51.5, 12.0])
+        );
+        assert_eq!(node["properties"]["name"], NAME);
+        assert_eq!(node["properties"]["id_hex"], "!1234abcd");
+
+        let track = &features[1];
+        assert_eq!(track["geometry"]["type"], "LineString");
+        assert_eq!(
+            coordinates(&track["geometry"]["coordinates"]),
+            json!([[-0.5, 51.0, 12.0], [-0.25, 51.5, 12.0]])
+        );
+        assert_eq!(track["properties"]["points"], 2);
+
+        assert_eq!(features[2]["properties"]["name"], NAME);
+        assert_eq!(features[2]["properties"]["description"], "<meet> here");
+    }
+
+    #[test]
+    fn kml_has_a_folder_each_and_escapes_names() {
+        let kml = write("map.kml", write_kml);
+        assert!(kml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<kml "));
+        assert!(kml.ends_with("</Document>\n</kml>\n"));
+        for folder in ["Nodes", "Tracks", "Waypoints"] {
+            assert!(kml.contains(&format!("<Folder><name>{folder}</name>")));
+        }
+        assert_eq!(kml.matches("<Placemark>").count(), 3);
+        assert_eq!(kml.matches(&format!("<name>{ESCAPED}</name>")).count(), 3);
+        assert!(!kml.contains(NAME));
+        assert!(kml.contains("<Data name=\"id_hex\"><value>!1234abcd</value></Data>"));
+        assert!(kml.contains("<Point><coordinates>-0.2500000,51.5000000,12</coordinates></Point>"));
+        assert!(kml.contains(
+            "<coordinates>-0.5000000,51.0000000,12 -0.2500000,51.5000000,12</coordinates>"
+        ));
+        assert!(kml.contains("<description>&lt;meet&gt; here (from TJ)</description>"));
+    }
+
+    #[test]
+    fn gpx_has_waypoints_and_a_track_and_escapes_names() {
+        let gpx = write("map.gpx", write_gpx);
+        assert!(gpx.contains("<gpx version=\"1.1\" creator=\"meshtui\""));
+        assert!(gpx.ends_with("</gpx>\n"));
+        assert_eq!(gpx.matches("<wpt ").count(), 2);
+        assert_eq!(gpx.matches("<type>node</type>").count(), 1);
+        assert_eq!(gpx.matches("<type>waypoint</type>").count(), 1);
+        assert_eq!(gpx.matches(&format!("<name>{ESCAPED}</name>")).count(), 3);
+        assert!(!gpx.contains(NAME));
+        assert!(gpx.contains(
+            "<wpt lat=\"51.5000000\" lon=\"-0.2500000\"><ele>12</ele>\
+             <time>2023-11-14T22:15:00Z</time>"
+        ));
+        assert_eq!(gpx.matches("<trk>").count(), 1);
+        assert!(gpx.contains(
+            "<trkpt lat=\"51.0000000\" lon=\"-0.5000000\"><ele>12</ele>\
+             <time>2023-11-14T22:13:20Z</time></trkpt>"
+        ));
+    }
+
+    /// A scratch file name for one test.
+    fn scratch(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("meshtui-export-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn node_records_flatten_the_node() {
+        let (nodes, _) = map();
+        let mut cn = nodes[&0x1234abcd].clone();
+        cn.last_rssi = -90;
+        cn.node_info.device_metrics = Some(DeviceMetrics {
+            battery_level: Some(101),
+            voltage: Some(4.2),
+            ..Default::default()
+        });
+        let record = NodeRecord::from(&cn);
+        assert_eq!(record.id_hex, "!1234abcd");
+        assert_eq!(record.long_name, NAME);
+        assert_eq!(record.short_name, "TJ");
+        assert_eq!(record.role, "Client");
+        assert!((record.latitude.unwrap() - 51.5).abs() < 1e-6);
+        assert!((record.longitude.unwrap() + 0.25).abs() < 1e-6);
+        assert_eq!(record.altitude, Some(12));
+        // readings we never got are left empty rather than zero
+        assert_eq!(record.snr, None);
+        assert_eq!(record.rssi, Some(-90));
+        assert_eq!(record.last_heard_nodeinfo, None);
+        assert_eq!(record.battery_level, Some(101));
+        assert_eq!(record.voltage, Some(4.2));
+
+        // nodes that haven't reported a position have no coordinates, not 0,0
+        let record = NodeRecord::from(&ComprehensiveNode::with_id(1));
+        assert_eq!((record.latitude, record.longitude), (None, None));
+        assert_eq!(record.id_hex, "!00000001");
+    }
+
+    #[test]
+    fn node_table_as_csv_and_json() {
+        let (nodes, _) = map();
+        let path = scratch("nodes.csv");
+        write_nodes_csv(&path, &nodes).unwrap();
+        let csv = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some(
+                "id,id_hex,short_name,long_name,hardware,role,latitude,longitude,altitude,\
+                 last_heard_nodeinfo,last_seen,snr,rssi,hops_away,via_mqtt,battery_level,voltage"
+            )
+        );
+        let row = lines.next().unwrap();
+        assert!(row.starts_with(
+            "305441741,!1234abcd,TJ,\"Tom & Jerry's <base> \"\"north\"\"\",Unset,Client,51.5"
+        ));
+        assert!(row.ends_with(",12,,1700000100,,,,false,,"));
+        assert_eq!(lines.next(), None);
+
+        let path = scratch("nodes.json");
+        write_nodes_json(&path, &nodes).unwrap();
+        let json: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        let record = &json.as_array().unwrap()[0];
+        assert_eq!(record["long_name"], NAME);
+        assert_eq!(record["altitude"], 12);
+        assert_eq!(record["snr"], Value::Null);
+        assert_eq!(record["via_mqtt"], false);
+    }
+
+    #[test]
+    fn telemetry_records_flatten_every_metric() {
+        let sample = TimeSeriesData {
+            timestamp: 1_700_000_000,
+            device: DeviceMetrics {
+                battery_level: Some(87),
+                ..Default::default()
+            },
+            environment: EnvironmentMetrics {
+                temperature: Some(21.5),
+                voltage: Some(12.1),
+                ..Default::default()
+            },
+            snr: 6.25,
+            ..Default::default()
+        };
+        let record = TelemetryRecord::from(&sample);
+        assert_eq!(record.time_utc, format_timestamp(1_700_000_000));
+        assert_eq!(record.battery_level, Some(87));
+        assert_eq!(record.temperature, Some(21.5));
+        // the environment sensor's voltage gets its own column, apart from the battery's
+        assert_eq!(record.env_voltage, Some(12.1));
+        assert_eq!(record.voltage, None);
+        assert_eq!(record.snr, Some(6.25));
+        assert_eq!(record.rssi, None);
+
+        let mut cn = ComprehensiveNode::with_id(1);
+        cn.timeseries.push(sample);
+        let path = scratch("telemetry.csv");
+        write_telemetry_csv(&path, &cn).unwrap();
+        let csv = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("timestamp,time_utc,rssi,snr,battery_level,voltage,"));
+        assert_eq!(lines[0].split(',').count(), lines[1].split(',').count());
+        assert!(lines[1].starts_with("1700000000,"));
+    }
+
+    #[test]
+    fn exports_in_the_same_second_get_their_own_directories() {
+        let base = scratch("dirs");
+        let first = export_directory(&base).unwrap();
+        let second = export_directory(&base).unwrap();
+        let third = export_directory(&base).unwrap();
+        fs::remove_dir_all(&base).unwrap();
+        assert_ne!(first, second);
+        assert_ne!(second, third);
+        assert_ne!(first, third);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::composer::SendOptions;
 use crate::packet_handler::MessageEnvelope;
 use meshtastic::protobufs::{FromRadio, ToRadio};
 
@@ -5,5 +6,5 @@ use meshtastic::protobufs::{FromRadio, ToRadio};
 pub enum IPCMessage {
     FromRadio(FromRadio),
     ToRadio(ToRadio),
-    SendMessage(MessageEnvelope),
+    SendMessage(MessageEnvelope, SendOptions),
 }
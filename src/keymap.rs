@@ -0,0 +1,554 @@
+use anyhow::{bail, Context, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Counts past this are taken to be typos rather than a wish to press Down ten thousand times.
+const MAX_COUNT: usize = 999;
+
+/// Something a key can be bound to.  Keys that aren't bound fall through to the tab, which is
+/// where single-letter commands like the node requests live.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Leaves the current view, or the app from a top-level list.
+    Back,
+    Left,
+    Right,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    /// The first row, or with a count, that row.
+    Top,
+    /// The last row, or with a count, that row.
+    Bottom,
+    Enter,
+    NextTab,
+    PrevTab,
+    Palette,
+    Export,
+    NextTheme,
+    Reconnect,
+    /// Does whatever that function key does on the current tab.
+    FunctionKey(u8),
+}
+
+/// Action names as they're written in keymap files, and shown in the palette.
+const ACTION_NAMES: [(&str, Action, &str); 16] = [
+    ("back", Action::Back, "Back / quit"),
+    ("left", Action::Left, "Left"),
+    ("right", Action::Right, "Right"),
+    ("up", Action::Up, "Up"),
+    ("down", Action::Down, "Down"),
+    ("page-up", Action::PageUp, "Page up"),
+    ("page-down", Action::PageDown, "Page down"),
+    ("top", Action::Top, "Go to the first row"),
+    ("bottom", Action::Bottom, "Go to the last row"),
+    ("enter", Action::Enter, "Interact / send"),
+    ("next-tab", Action::NextTab, "Next tab"),
+    ("prev-tab", Action::PrevTab, "Previous tab"),
+    ("palette", Action::Palette, "Open the command palette"),
+    (
+        "export",
+        Action::Export,
+        "Export node, telemetry and map data",
+    ),
+    ("next-theme", Action::NextTheme, "Switch to the next theme"),
+    ("reconnect", Action::Reconnect, "Reconnect to the radio"),
+];
+
+impl Action {
+    pub fn parse(name: &str) -> Result<Action> {
+        if let Some(n) = name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+            return Ok(Action::FunctionKey(n));
+        }
+        ACTION_NAMES
+            .iter()
+            .find(|(n, _, _)| *n == name)
+            .map(|(_, action, _)| *action)
+            .with_context(|| format!("{name} isn't an action"))
+    }
+
+    /// The actions that make sense to run from the palette, with what they do.
+    pub fn describe_all() -> impl Iterator<Item = (Action, &'static str)> {
+        ACTION_NAMES
+            .iter()
+            .map(|(_, action, desc)| (*action, *desc))
+    }
+}
+
+/// One key press, as bindings see it.  Shift is folded into the character for printable keys,
+/// so `G` is just `G`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl From<KeyEvent> for Key {
+    fn from(event: KeyEvent) -> Self {
+        let mut modifiers = event.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT);
+        if !matches!(event.code, KeyCode::Char(_) | KeyCode::BackTab) {
+            modifiers |= event.modifiers & KeyModifiers::SHIFT;
+        }
+        Key {
+            code: event.code,
+            modifiers,
+        }
+    }
+}
+
+impl Key {
+    /// Parses keys like `j`, `G`, `ctrl-p`, `alt-<`, `shift-up`, `pagedown` or `f5`.
+    pub fn parse(spec: &str) -> Result<Key> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = spec;
+        // a key of "-" on its own is a minus, not a modifier separator
+        while let Some((modifier, key)) = rest.split_once('-').filter(|(_, k)| !k.is_empty()) {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" | "c" => KeyModifiers::CONTROL,
+                "alt" | "meta" | "m" => KeyModifiers::ALT,
+                "shift" | "s" => KeyModifiers::SHIFT,
+                _ => bail!("{modifier} isn't a modifier, in {spec}"),
+            };
+            rest = key;
+        }
+        let code = match rest.to_lowercase().as_str() {
+            _ if rest.chars().count() == 1 => KeyCode::Char(rest.chars().next().unwrap_or(' ')),
+            "space" => KeyCode::Char(' '),
+            "enter" | "return" => KeyCode::Enter,
+            "esc" | "escape" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "pageup" | "pgup" => KeyCode::PageUp,
+            "pagedown" | "pgdn" => KeyCode::PageDown,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            f if f.starts_with('f') && f[1..].parse::<u8>().is_ok() => {
+                KeyCode::F(f[1..].parse().unwrap_or(0))
+            }
+            _ => bail!("{rest} isn't a key, in {spec}"),
+        };
+        // shift-tab arrives as BackTab, and shift-a as A
+        let (code, modifiers) = match code {
+            KeyCode::Tab if modifiers.contains(KeyModifiers::SHIFT) => {
+                (KeyCode::BackTab, modifiers - KeyModifiers::SHIFT)
+            }
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => (
+                KeyCode::Char(c.to_ascii_uppercase()),
+                modifiers - KeyModifiers::SHIFT,
+            ),
+            code => (code, modifiers),
+        };
+        Ok(Key { code, modifiers })
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "^")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "M-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "S-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c) if self.modifiers.contains(KeyModifiers::CONTROL) => {
+                write!(f, "{}", c.to_ascii_uppercase())
+            }
+            KeyCode::Char(c) => write!(f, "{c}"),
+            KeyCode::Left => write!(f, "←"),
+            KeyCode::Right => write!(f, "→"),
+            KeyCode::Up => write!(f, "↑"),
+            KeyCode::Down => write!(f, "↓"),
+            KeyCode::PageUp => write!(f, "PgUp"),
+            KeyCode::PageDown => write!(f, "PgDn"),
+            KeyCode::BackTab => write!(f, "S-Tab"),
+            KeyCode::F(n) => write!(f, "F{n}"),
+            code => write!(f, "{code:?}"),
+        }
+    }
+}
+
+/// How a keymap takes a count before a command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Counts {
+    #[default]
+    Off,
+    /// `5j`, as in vim.
+    Digits,
+    /// `M-5 C-n`, as in emacs.
+    AltDigits,
+}
+
+/// What a key press came to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolved {
+    /// Run this action, with the count typed before it, if any.
+    Action(Action, Option<usize>),
+    /// The key started a sequence or a count; wait for more.
+    Pending,
+    /// Nothing is bound to the key, so the tab can have it.
+    Unbound,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Keymap {
+    /// Key sequences in the order they were bound, so help lists them the same way every time.
+    bindings: Vec<(Vec<Key>, Action)>,
+    counts: Counts,
+    /// Set while a tab wants digits for itself, as the Messages tab does for canned messages.
+    counts_paused: bool,
+    pending: Vec<Key>,
+    count: Option<usize>,
+}
+
+/// Bindings every preset starts from.
+const COMMON: &[(&str, &str)] = &[
+    ("esc", "back"),
+    ("q", "back"),
+    ("left", "left"),
+    ("right", "right"),
+    ("up", "up"),
+    ("down", "down"),
+    ("pageup", "page-up"),
+    ("pagedown", "page-down"),
+    ("home", "top"),
+    ("end", "bottom"),
+    ("enter", "enter"),
+    ("tab", "next-tab"),
+    ("backtab", "prev-tab"),
+    ("ctrl-p", "palette"),
+    ("f9", "export"),
+    ("f10", "next-theme"),
+    ("f12", "reconnect"),
+];
+const DEFAULT: &[(&str, &str)] = &[
+    ("h", "left"),
+    ("l", "right"),
+    ("k", "up"),
+    ("j", "down"),
+    ("g g", "top"),
+    ("G", "bottom"),
+    (":", "palette"),
+];
+const VIM: &[(&str, &str)] = &[
+    ("h", "left"),
+    ("l", "right"),
+    ("k", "up"),
+    ("j", "down"),
+    ("g g", "top"),
+    ("G", "bottom"),
+    ("ctrl-b", "page-up"),
+    ("ctrl-f", "page-down"),
+    ("ctrl-u", "page-up"),
+    ("ctrl-d", "page-down"),
+    ("g t", "next-tab"),
+    ("g T", "prev-tab"),
+    (":", "palette"),
+];
+const EMACS: &[(&str, &str)] = &[
+    ("ctrl-g", "back"),
+    ("ctrl-b", "left"),
+    ("ctrl-f", "right"),
+    ("ctrl-p", "up"),
+    ("ctrl-n", "down"),
+    ("alt-v", "page-up"),
+    ("ctrl-v", "page-down"),
+    ("alt-<", "top"),
+    ("alt->", "bottom"),
+    ("alt-x", "palette"),
+];
+
+/// A keymap file: a preset to start from, and bindings on top.  Binding a key to `none`
+/// unbinds it.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeymapFile {
+    preset: Option<String>,
+    #[serde(default)]
+    bindings: BTreeMap<String, String>,
+}
+
+fn parse_sequence(spec: &str) -> Result<Vec<Key>> {
+    let keys = spec
+        .split_whitespace()
+        .map(Key::parse)
+        .collect::<Result<Vec<_>>>()?;
+    if keys.is_empty() {
+        bail!("an empty key can't be bound");
+    }
+    Ok(keys)
+}
+
+impl Keymap {
+    pub fn preset(name: &str) -> Result<Keymap> {
+        let (extra, counts) = match name {
+            "default" => (DEFAULT, Counts::Off),
+            "vim" => (VIM, Counts::Digits),
+            "emacs" => (EMACS, Counts::AltDigits),
+            _ => bail!("there's no {name} keymap; try default, vim or emacs"),
+        };
+        let mut keymap = Keymap {
+            counts,
+            ..Default::default()
+        };
+        for (keys, action) in COMMON.iter().chain(extra) {
+            keymap.bind(parse_sequence(keys)?, Some(Action::parse(action)?));
+        }
+        Ok(keymap)
+    }
+
+    /// Loads `spec`, which is either a preset name or a keymap file.
+    pub fn load(spec: &str) -> Result<Keymap> {
+        let path = Path::new(spec);
+        if !path.exists() {
+            return Keymap::preset(spec);
+        }
+        let contents =
+            fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let file: KeymapFile =
+            toml::from_str(&contents).with_context(|| format!("in {}", path.display()))?;
+        let mut keymap = Keymap::preset(file.preset.as_deref().unwrap_or("default"))?;
+        for (keys, action) in &file.bindings {
+            let action = match action.as_str() {
+                "none" => None,
+                action => Some(Action::parse(action)?),
+            };
+            keymap.bind(parse_sequence(keys)?, action);
+        }
+        Ok(keymap)
+    }
+
+    fn bind(&mut self, keys: Vec<Key>, action: Option<Action>) {
+        self.bindings.retain(|(k, _)| *k != keys);
+        if let Some(action) = action {
+            self.bindings.push((keys, action));
+        }
+    }
+
+    /// The keys bound to an action, for help text, e.g. `j/↓`.
+    pub fn keys_for(&self, action: Action) -> Option<String> {
+        let keys: Vec<String> = self
+            .bindings
+            .iter()
+            .filter(|(_, a)| *a == action)
+            .map(|(keys, _)| keys.iter().map(Key::to_string).collect())
+            .collect();
+        match keys.is_empty() {
+            true => None,
+            false => Some(keys.join("/")),
+        }
+    }
+
+    /// Stops digits starting a count, so they reach the tab as plain keys instead.
+    pub fn pause_counts(&mut self, paused: bool) {
+        self.counts_paused = paused;
+    }
+
+    fn count_digit(&self, key: Key) -> Option<u32> {
+        if self.counts_paused {
+            return None;
+        }
+        let KeyCode::Char(c) = key.code else {
+            return None;
+        };
+        let digit = c.to_digit(10)?;
+        // a leading zero isn't a count
+        if digit == 0 && self.count.is_none() {
+            return None;
+        }
+        match self.counts {
+            Counts::Digits if key.modifiers.is_empty() => Some(digit),
+            Counts::AltDigits if key.modifiers == KeyModifiers::ALT => Some(digit),
+            _ => None,
+        }
+    }
+
+    /// Takes one key press and works out whether it finishes a binding.
+    pub fn press(&mut self, event: KeyEvent) -> Resolved {
+        let key = Key::from(event);
+        if key.code == KeyCode::Esc && (!self.pending.is_empty() || self.count.is_some()) {
+            self.pending.clear();
+            self.count = None;
+            return Resolved::Pending;
+        }
+        if self.pending.is_empty() {
+            if let Some(digit) = self.count_digit(key) {
+                let count = self.count.unwrap_or(0) * 10 + digit as usize;
+                self.count = Some(count.min(MAX_COUNT));
+                return Resolved::Pending;
+            }
+        }
+        self.pending.push(key);
+        if let Some((_, action)) = self.bindings.iter().find(|(k, _)| *k == self.pending) {
+            let action = *action;
+            self.pending.clear();
+            return Resolved::Action(action, self.count.take());
+        }
+        let is_prefix = self
+            .bindings
+            .iter()
+            .any(|(k, _)| k.len() > self.pending.len() && k.starts_with(&self.pending));
+        if is_prefix {
+            return Resolved::Pending;
+        }
+        let typed = self.pending.len();
+        self.pending.clear();
+        self.count = None;
+        // a sequence that went nowhere is dropped, as vim does; a lone key goes to the tab
+        match typed {
+            1 => Resolved::Unbound,
+            _ => Resolved::Pending,
+        }
+    }
+
+    /// What's been typed towards a binding so far, to show while waiting for the rest.
+    pub fn pending(&self) -> Option<String> {
+        let mut typed = self.count.map(|c| c.to_string()).unwrap_or_default();
+        typed.extend(self.pending.iter().map(Key::to_string));
+        (!typed.is_empty()).then_some(typed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(keymap: &mut Keymap, keys: &str) -> Vec<Resolved> {
+        keys.split_whitespace()
+            .map(|k| {
+                let key = Key::parse(k).unwrap();
+                keymap.press(KeyEvent::new(key.code, key.modifiers))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn vim_counts() {
+        let mut vim = Keymap::preset("vim").unwrap();
+        assert_eq!(
+            press(&mut vim, "5 j"),
+            [Resolved::Pending, Resolved::Action(Action::Down, Some(5))]
+        );
+        assert_eq!(
+            press(&mut vim, "1 2 G"),
+            [
+                Resolved::Pending,
+                Resolved::Pending,
+                Resolved::Action(Action::Bottom, Some(12))
+            ]
+        );
+        // the count doesn't carry over to the next key
+        assert_eq!(press(&mut vim, "j"), [Resolved::Action(Action::Down, None)]);
+    }
+
+    #[test]
+    fn paused_counts_leave_digits_to_the_tab() {
+        let mut vim = Keymap::preset("vim").unwrap();
+        vim.pause_counts(true);
+        assert_eq!(
+            press(&mut vim, "5 j"),
+            [Resolved::Unbound, Resolved::Action(Action::Down, None)]
+        );
+        assert_eq!(vim.pending(), None);
+        vim.pause_counts(false);
+        assert_eq!(
+            press(&mut vim, "5 j"),
+            [Resolved::Pending, Resolved::Action(Action::Down, Some(5))]
+        );
+    }
+
+    #[test]
+    fn sequences() {
+        let mut keymap = Keymap::preset("default").unwrap();
+        assert_eq!(
+            press(&mut keymap, "g g"),
+            [Resolved::Pending, Resolved::Action(Action::Top, None)]
+        );
+        // a sequence that goes nowhere is dropped rather than handed to the tab
+        assert_eq!(
+            press(&mut keymap, "g x"),
+            [Resolved::Pending, Resolved::Pending]
+        );
+        assert_eq!(keymap.pending(), None);
+        assert_eq!(
+            press(&mut keymap, "j"),
+            [Resolved::Action(Action::Down, None)]
+        );
+    }
+
+    #[test]
+    fn esc_cancels_a_sequence() {
+        let mut vim = Keymap::preset("vim").unwrap();
+        assert_eq!(
+            press(&mut vim, "3 g esc"),
+            [Resolved::Pending, Resolved::Pending, Resolved::Pending]
+        );
+        assert_eq!(vim.pending(), None);
+        assert_eq!(
+            press(&mut vim, "esc"),
+            [Resolved::Action(Action::Back, None)]
+        );
+    }
+
+    #[test]
+    fn unbound_keys_go_to_the_tab() {
+        let mut keymap = Keymap::preset("default").unwrap();
+        assert_eq!(press(&mut keymap, "x"), [Resolved::Unbound]);
+        // digits are counts under vim, but the default keymap leaves them to the tab
+        assert_eq!(press(&mut keymap, "5"), [Resolved::Unbound]);
+    }
+
+    #[test]
+    fn parses_keys() {
+        let backtab = Key::parse("shift-tab").unwrap();
+        assert_eq!(backtab.code, KeyCode::BackTab);
+        assert_eq!(backtab.modifiers, KeyModifiers::NONE);
+        assert_eq!(Key::parse("shift-g").unwrap(), Key::parse("G").unwrap());
+        let ctrl_p = Key::parse("ctrl-p").unwrap();
+        assert_eq!(ctrl_p.code, KeyCode::Char('p'));
+        assert_eq!(ctrl_p.modifiers, KeyModifiers::CONTROL);
+        assert_eq!(Key::parse("-").unwrap().code, KeyCode::Char('-'));
+        assert_eq!(Key::parse("f5").unwrap().code, KeyCode::F(5));
+        assert!(Key::parse("hyper-x").is_err());
+        assert!(Key::parse("nosuchkey").is_err());
+    }
+
+    #[test]
+    fn keymap_files() {
+        let path = std::env::temp_dir().join(format!("meshtui-keymap-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "preset = \"vim\"\n[bindings]\n\"q\" = \"none\"\n\"g e\" = \"export\"\n",
+        )
+        .unwrap();
+        let keymap = Keymap::load(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        let mut keymap = keymap.unwrap();
+        assert_eq!(press(&mut keymap, "q"), [Resolved::Unbound]);
+        assert_eq!(
+            press(&mut keymap, "g e"),
+            [Resolved::Pending, Resolved::Action(Action::Export, None)]
+        );
+        assert_eq!(
+            press(&mut keymap, "esc"),
+            [Resolved::Action(Action::Back, None)]
+        );
+        assert!(Keymap::load("nosuchpreset").is_err());
+    }
+}
@@ -0,0 +1,242 @@
+use crate::app::{DeviceConfiguration, DEFAULT_CHANNEL_LABEL};
+use crate::{DEVICE_CONFIG, KEYRING};
+use aes::{Aes128, Aes256};
+use anyhow::{bail, Context, Result};
+use base64::prelude::*;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use meshtastic::protobufs::config::lo_ra_config::ModemPreset;
+use meshtastic::protobufs::{mesh_packet, Data, MeshPacket, PortNum};
+use meshtastic::Message;
+
+/// What a one-byte PSK of 1 stands for, as on the default LongFast channel.  Other one-byte
+/// PSKs are this with the last byte bumped.
+const DEFAULT_PSK: [u8; 16] = [
+    0xd4, 0xf1, 0xbb, 0x3a, 0x20, 0x29, 0x07, 0x59, 0xf0, 0xbc, 0xff, 0xab, 0xcf, 0x4e, 0x69, 0x01,
+];
+
+/// A channel key we can try on encrypted packets, with the one-byte hash the sender puts in the
+/// packet's channel field so we needn't try every key on every packet.
+#[derive(Debug, Clone)]
+pub struct ChannelKey {
+    pub name: String,
+    key: Vec<u8>,
+    hash: u8,
+    /// Our radio's channel index, for keys that came from its config.
+    index: Option<u32>,
+}
+
+/// The extra keys given on the command line.  Keys for our radio's own channels are read from
+/// its config each time, so they're never stale.
+#[derive(Debug, Clone, Default)]
+pub struct Keyring {
+    pub extra: Vec<ChannelKey>,
+}
+
+/// Expands a channel PSK the way the firmware does.  Returns None for channels without
+/// encryption.
+pub fn expand_psk(psk: &[u8]) -> Option<Vec<u8>> {
+    match psk.len() {
+        0 => None,
+        1 if psk[0] == 0 => None,
+        1 => {
+            let mut key = DEFAULT_PSK;
+            key[15] = key[15].wrapping_add(psk[0] - 1);
+            Some(key.to_vec())
+        }
+        n if n <= 32 => {
+            let mut key = psk.to_vec();
+            key.resize(if n <= 16 { 16 } else { 32 }, 0);
+            Some(key)
+        }
+        _ => None,
+    }
+}
+
+fn xor_hash(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |acc, b| acc ^ b)
+}
+
+/// The name the firmware gives a channel that hasn't been named: its modem preset.
+fn preset_name(dc: &DeviceConfiguration) -> &'static str {
+    if !dc.lora.use_preset {
+        return "Custom";
+    }
+    match dc.lora.modem_preset() {
+        ModemPreset::LongFast => "LongFast",
+        ModemPreset::LongSlow => "LongSlow",
+        ModemPreset::VeryLongSlow => "VLongSlow",
+        ModemPreset::MediumSlow => "MediumSlow",
+        ModemPreset::MediumFast => "MediumFast",
+        ModemPreset::ShortSlow => "ShortSlow",
+        ModemPreset::ShortFast => "ShortFast",
+        ModemPreset::LongModerate => "LongMod",
+        ModemPreset::ShortTurbo => "ShortTurbo",
+    }
+}
+
+impl ChannelKey {
+    pub fn new(name: &str, psk: &[u8]) -> Option<Self> {
+        let key = expand_psk(psk)?;
+        Some(ChannelKey {
+            name: name.to_string(),
+            hash: xor_hash(name.as_bytes()) ^ xor_hash(&key),
+            key,
+            index: None,
+        })
+    }
+
+    /// Parses a `name:base64-psk` key, as given with `--channel-key`.  The name has to match
+    /// the channel's name on the mesh, since it goes into the hash packets are matched by.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let Some((name, psk)) = spec.split_once(':') else {
+            bail!("expected name:base64-psk, got {spec}");
+        };
+        let psk = BASE64_STANDARD
+            .decode(psk.trim())
+            .with_context(|| format!("the key for {name} isn't valid base64"))?;
+        match ChannelKey::new(name.trim(), &psk) {
+            Some(key) => Ok(key),
+            None => bail!("the key for {name} doesn't encrypt anything"),
+        }
+    }
+
+    /// Encrypts or decrypts `buf` in place; the nonce is the packet id and sender.
+    fn apply_keystream(&self, packet: &MeshPacket, buf: &mut [u8]) {
+        let mut nonce = [0; 16];
+        nonce[..8].copy_from_slice(&u64::from(packet.id).to_le_bytes());
+        nonce[8..12].copy_from_slice(&packet.from.to_le_bytes());
+        match self.key.len() {
+            16 => ctr::Ctr128BE::<Aes128>::new(self.key.as_slice().into(), &nonce.into())
+                .apply_keystream(buf),
+            _ => ctr::Ctr128BE::<Aes256>::new(self.key.as_slice().into(), &nonce.into())
+                .apply_keystream(buf),
+        }
+    }
+
+    fn decrypt(&self, packet: &MeshPacket, payload: &[u8]) -> Option<Data> {
+        let mut buf = payload.to_vec();
+        self.apply_keystream(packet, &mut buf);
+        // a wrong key that happens to share the hash decodes to garbage, which rarely parses
+        Data::decode(buf.as_slice())
+            .ok()
+            .filter(|d| d.portnum() != PortNum::UnknownApp)
+    }
+}
+
+/// The keys for our radio's channels, followed by the extra ones.
+async fn keys() -> Vec<ChannelKey> {
+    let mut keys = vec![];
+    if let Some(dc) = DEVICE_CONFIG.read().await.as_ref() {
+        for channel in dc.channels.values() {
+            let Some(settings) = &channel.settings else {
+                continue;
+            };
+            let name = match settings.name.as_str() {
+                "" | DEFAULT_CHANNEL_LABEL => preset_name(dc),
+                name => name,
+            };
+            if let Some(mut key) = ChannelKey::new(name, &settings.psk) {
+                key.index = Some(channel.index as u32);
+                keys.push(key);
+            }
+        }
+    }
+    keys.extend(KEYRING.read().await.extra.iter().cloned());
+    keys
+}
+
+/// Tries our keys on an encrypted packet.  On success the packet comes back decoded, on our
+/// channel index if the key is one of our radio's, along with the name of the key that worked.
+pub async fn decrypt(packet: &MeshPacket) -> Option<(MeshPacket, String)> {
+    let Some(mesh_packet::PayloadVariant::Encrypted(payload)) = &packet.payload_variant else {
+        return None;
+    };
+    let hash = u8::try_from(packet.channel).ok()?;
+    for key in keys().await.iter().filter(|k| k.hash == hash) {
+        if let Some(data) = key.decrypt(packet, payload) {
+            let mut decoded = packet.clone();
+            decoded.payload_variant = Some(mesh_packet::PayloadVariant::Decoded(data));
+            if let Some(index) = key.index {
+                decoded.channel = index;
+            }
+            return Some((decoded, key.name.clone()));
+        }
+    }
+    None
+}
+
+/// Encrypts a decoded packet with the key named `channel`, for sending where no radio will do
+/// it for us.  The packet's id and sender have to be final, since they make up the nonce.
+pub async fn encrypt(packet: &mut MeshPacket, channel: &str) -> Result<()> {
+    let Some(key) = keys().await.into_iter().find(|k| k.name == channel) else {
+        bail!("no key for channel {channel}");
+    };
+    let Some(mesh_packet::PayloadVariant::Decoded(data)) = &packet.payload_variant else {
+        bail!("packet {} isn't decoded", packet.id);
+    };
+    let mut buf = data.encode_to_vec();
+    key.apply_keystream(packet, &mut buf);
+    packet.channel = key.hash as u32;
+    packet.payload_variant = Some(mesh_packet::PayloadVariant::Encrypted(buf));
+    Ok(())
+}
+
+/// Whether we hold a key named `name`.
+pub async fn has_key(name: &str) -> bool {
+    keys().await.iter().any(|k| k.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn longfast() -> ChannelKey {
+        ChannelKey::new("LongFast", &[1]).unwrap()
+    }
+
+    #[test]
+    fn expands_the_default_psk() {
+        let key = expand_psk(&[1]).unwrap();
+        assert_eq!(BASE64_STANDARD.encode(key), "1PG7OiApB1nwvP+rz05pAQ==");
+        assert_eq!(expand_psk(&[2]).unwrap()[15], 0x02);
+        assert_eq!(expand_psk(&[]), None);
+        assert_eq!(expand_psk(&[0]), None);
+    }
+
+    #[test]
+    fn pads_short_psks() {
+        assert_eq!(expand_psk(&[7; 10]).unwrap().len(), 16);
+        assert_eq!(expand_psk(&[7; 20]).unwrap().len(), 32);
+        assert_eq!(expand_psk(&[7; 33]), None);
+    }
+
+    #[test]
+    fn longfast_hash() {
+        assert_eq!(longfast().hash, 8);
+    }
+
+    #[test]
+    fn decrypts_a_longfast_packet() {
+        // "hello" as a text message, encrypted with openssl's aes-128-ctr using the default key
+        // and the nonce 78563412 00000000 efbeadde 00000000: the packet id as a little-endian u64,
+        // then the sender as a little-endian u32
+        let packet = MeshPacket {
+            id: 0x1234_5678,
+            from: 0xdead_beef,
+            channel: 8,
+            ..Default::default()
+        };
+        let encrypted = [0xab, 0xa2, 0x29, 0x94, 0xee, 0x11, 0x05, 0x52, 0xc6];
+        let data = longfast().decrypt(&packet, &encrypted).unwrap();
+        assert_eq!(data.portnum(), PortNum::TextMessageApp);
+        assert_eq!(data.payload, b"hello");
+    }
+
+    #[test]
+    fn parses_channel_keys() {
+        let key = ChannelKey::parse("LongFast:AQ==").unwrap();
+        assert_eq!(key.hash, 8);
+        assert!(ChannelKey::parse("LongFast").is_err());
+        assert!(ChannelKey::parse("LongFast:AA==").is_err());
+    }
+}
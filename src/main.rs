@@ -2,24 +2,44 @@ extern crate tokio;
 #[macro_use]
 extern crate tracing;
 
+mod admin;
 pub mod app;
 mod clap;
+mod composer;
 pub mod consts;
+mod export;
 mod ipc;
+mod keymap;
+mod keyring;
 mod meshtastic_interaction;
+mod metrics;
+mod mouse;
+mod mqtt;
 mod packet_handler;
+mod palette;
+mod pki;
 mod tabs;
 mod theme;
+mod topology;
 pub mod tui;
+mod unishox;
 mod util;
 
+use crate::admin::{AdminTarget, RemoteSession};
 use crate::app::Preferences;
-use crate::app::{Connection, DeviceConfiguration};
+use crate::app::{Connection, DeviceConfiguration, MqttSettings};
 use crate::clap::CliArgs;
+use crate::keymap::Keymap;
+use crate::keyring::{ChannelKey, Keyring};
+use crate::metrics::Metrics;
+use crate::pki::KeyPins;
+use crate::theme::ColorDepth;
+use crate::topology::TopologyFilter;
 use ::clap::Parser;
 use app::App;
 use lazy_static::lazy_static;
 
+use std::collections::HashMap;
 use std::process;
 
 use crate::ipc::IPCMessage;
@@ -38,6 +58,11 @@ lazy_static! {
     static ref FIFTY_FIFTY: Vec<Constraint> =
         vec![Constraint::Percentage(50), Constraint::Percentage(50)];
     static ref DEVICE_CONFIG: RwLock<Option<DeviceConfiguration>> = RwLock::new(None);
+    static ref ADMIN_TARGET: RwLock<AdminTarget> = RwLock::new(AdminTarget::Local);
+    static ref REMOTE_SESSIONS: RwLock<HashMap<u32, RemoteSession>> = RwLock::new(HashMap::new());
+    static ref KEYRING: RwLock<Keyring> = RwLock::new(Keyring::default());
+    static ref KEY_PINS: RwLock<KeyPins> = RwLock::new(KeyPins::default());
+    static ref METRICS: RwLock<Metrics> = RwLock::new(Metrics::default());
 }
 
 #[tokio::main]
@@ -56,20 +81,123 @@ async fn main() -> io::Result<()> {
         app.connection = Connection::TCP(cli.ip.unwrap(), cli.tcp_port);
     } else if cli.serial_port.is_some() {
         app.connection = Connection::Serial(cli.serial_port.unwrap());
+    } else if let Some(broker) = &cli.mqtt {
+        app.connection = Connection::Mqtt(mqtt_settings(&cli, broker));
     } else {
-        println!("You must specify an ip via -i, or a serial port v ia -s.");
+        println!("You must specify an ip via -i, a serial port via -s, or a broker via --mqtt.");
         process::exit(1);
     }
+    let over_mqtt = matches!(app.connection, Connection::Mqtt(_));
 
     {
         let mut prefs = PREFERENCES.write().await;
         // setting this to a nonzero length String to help indicate we're a bona-fide
         // preferences struct and not a ::default() generated one.
         prefs.initialized = "Yes".to_owned();
-        prefs.show_mqtt = cli.show_mqtt;
+        // over MQTT, every node is an MQTT node
+        prefs.show_mqtt = cli.show_mqtt || over_mqtt;
+        prefs.export_dir = cli.export_dir;
+        prefs.export_on_exit = cli.export_on_exit;
+        prefs.metrics = cli.metrics_listen.is_some();
+        prefs.topology = TopologyFilter {
+            skip_mqtt: cli.topology_skip_mqtt,
+            max_age: cli.topology_max_age,
+        };
     }
     assert!(!PREFERENCES.read().await.initialized.is_empty());
+    for spec in &cli.channel_key {
+        match ChannelKey::parse(spec) {
+            Ok(key) => KEYRING.write().await.extra.push(key),
+            Err(e) => {
+                println!("Bad --channel-key: {e:#}");
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = cli.known_keys {
+        match KeyPins::load(path) {
+            Ok(pins) => *KEY_PINS.write().await = pins,
+            Err(e) => {
+                println!("Bad --known-keys: {e:#}");
+                process::exit(1);
+            }
+        }
+    }
+    match Keymap::load(&cli.keymap) {
+        Ok(keymap) => app.keymap = keymap,
+        Err(e) => {
+            println!("Bad --keymap: {e:#}");
+            process::exit(1);
+        }
+    }
+    app.mouse = !cli.no_mouse;
+    let depth = cli.colors.unwrap_or_else(ColorDepth::detect);
+    if let Err(e) = theme::init(cli.themes_dir.as_deref(), cli.theme.as_deref(), depth) {
+        println!("Unable to load themes: {e:#}");
+        process::exit(1);
+    }
+    if let Some(addr) = cli.metrics_listen {
+        match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => {
+                tokio::spawn(metrics::serve(listener));
+            }
+            Err(e) => {
+                println!("Unable to serve metrics on {addr}: {e}");
+                process::exit(1);
+            }
+        }
+    }
+    if over_mqtt {
+        // most of what's on a public broker is on the default channel
+        let mut keyring = KEYRING.write().await;
+        if !keyring.extra.iter().any(|k| k.name == "LongFast") {
+            keyring.extra.extend(ChannelKey::new("LongFast", &[1]));
+        }
+    }
+    if let Some(secs) = cli.export_after {
+        match app.run_headless(secs).await {
+            Ok(dir) => println!("Exported node and telemetry data to {}", dir.display()),
+            Err(e) => {
+                eprintln!("Unable to export node and telemetry data: {e:#}");
+                process::exit(1);
+            }
+        }
+        return Ok(());
+    }
     let _ = app.run().await;
 
     Ok(())
 }
+
+/// Builds the MQTT settings from `--mqtt host[:port]` and the other `--mqtt-*` arguments.
+fn mqtt_settings(cli: &CliArgs, broker: &str) -> MqttSettings {
+    let (host, port) = match broker.rsplit_once(':') {
+        Some((host, port)) => match port.parse() {
+            Ok(port) => (host, port),
+            Err(_) => {
+                println!("Bad --mqtt port: {port}");
+                process::exit(1);
+            }
+        },
+        None => (broker, 1883),
+    };
+    let node_id = match &cli.mqtt_node_id {
+        Some(id) => match u32::from_str_radix(id.trim_start_matches('!'), 16) {
+            Ok(id) => id,
+            Err(_) => {
+                println!("Bad --mqtt-node-id: {id}");
+                process::exit(1);
+            }
+        },
+        None => meshtastic::utils::generate_rand_id(),
+    };
+    MqttSettings {
+        host: host.to_string(),
+        port,
+        root_topic: cli.mqtt_topic.trim_end_matches('/').to_string(),
+        username: cli.mqtt_user.clone(),
+        password: cli.mqtt_password.clone(),
+        publish_channel: cli.mqtt_publish.clone(),
+        node_id,
+    }
+}
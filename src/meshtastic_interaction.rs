@@ -1,11 +1,15 @@
 use crate::app::Connection;
+use crate::composer::SendOptions;
 use crate::ipc::IPCMessage;
+use crate::mqtt;
+use crate::packet_handler::MessageEnvelope;
+use crate::unishox;
+use crate::util::configured_hop_limit;
 use anyhow::{bail, Result};
 
-use meshtastic::packet::PacketRouter;
+use meshtastic::packet::PacketDestination;
 
-use meshtastic::protobufs::{FromRadio, MeshPacket};
-use meshtastic::types::NodeId;
+use meshtastic::protobufs::{mesh_packet, to_radio, Data, MeshPacket, PortNum};
 use meshtastic::{api::StreamApi, utils};
 use strum::Display;
 use thiserror::Error;
@@ -21,38 +25,41 @@ pub enum DeviceUpdateError {
     NotificationDispatchFailure(String),
 }
 
-#[derive(Default)]
-struct MyPacketRouter {
-    _source_node_id: NodeId,
-}
-
-impl MyPacketRouter {
-    fn new(node_id: u32) -> Self {
-        MyPacketRouter {
-            _source_node_id: node_id.into(),
-        }
-    }
-}
-
-impl PacketRouter<(), DeviceUpdateError> for MyPacketRouter {
-    fn handle_packet_from_radio(
-        &mut self,
-        _packet: FromRadio,
-    ) -> std::result::Result<(), DeviceUpdateError> {
-        debug!("handle_packet_from_radio called but not sure what to do");
-        Ok(())
-    }
-
-    fn handle_mesh_packet(
-        &mut self,
-        _packet: MeshPacket,
-    ) -> std::result::Result<(), DeviceUpdateError> {
-        debug!("handle_mesh_packet called but not sure what to do here");
-        Ok(())
-    }
-
-    fn source_node_id(&self) -> NodeId {
-        self._source_node_id
+/// Builds a text message packet ourselves, rather than through `send_text`, so the hop limit
+/// and priority can be chosen per message, and the text compressed when that saves airtime.
+/// The message's id is kept if it has one, so replies we show match what we sent.
+pub(crate) fn text_packet(message: MessageEnvelope, options: &SendOptions) -> MeshPacket {
+    let to = match message.destination {
+        PacketDestination::Local => 0,
+        PacketDestination::Broadcast => u32::MAX,
+        PacketDestination::Node(id) => id.id(),
+    };
+    let compressed = match options.compress {
+        true => Some(unishox::compress(&message.message)),
+        false => None,
+    };
+    let (portnum, payload) = match compressed {
+        Some(c) if c.len() < message.message.len() => (PortNum::TextMessageCompressedApp, c),
+        _ => (PortNum::TextMessageApp, message.message.into_bytes()),
+    };
+    MeshPacket {
+        to,
+        channel: message.channel.channel(),
+        id: match message.id {
+            0 => utils::generate_rand_id(),
+            id => id,
+        },
+        hop_limit: options.hop_limit.unwrap_or_else(configured_hop_limit),
+        want_ack: options.want_ack,
+        priority: options.priority.into(),
+        payload_variant: Some(mesh_packet::PayloadVariant::Decoded(Data {
+            portnum: i32::from(portnum),
+            payload,
+            reply_id: message.reply_id,
+            emoji: message.emoji as u32,
+            ..Default::default()
+        })),
+        ..Default::default()
     }
 }
 
@@ -79,6 +86,7 @@ pub(crate) async fn meshtastic_loop(
                 .expect("Unable to open serial port.");
             (decoded_listener, connected_stream_api) = stream_api.connect(serial_stream).await;
         }
+        Connection::Mqtt(settings) => return mqtt::mqtt_loop(settings, tx, rx).await,
         Connection::None => {
             panic!("Neither tcp nor serial selected for connection.");
         }
@@ -86,7 +94,6 @@ pub(crate) async fn meshtastic_loop(
     let config_id = utils::generate_rand_id();
     let mut _stream_api = connected_stream_api.configure(config_id).await?;
     info!("Connected to meshtastic node!");
-    let mut packet_router = MyPacketRouter::new(0);
     loop {
         if let Ok(fr) = decoded_listener.try_recv() {
             if let Err(e) = tx.send(IPCMessage::FromRadio(fr)).await {
@@ -95,15 +102,10 @@ pub(crate) async fn meshtastic_loop(
         }
         if let Ok(inbound) = rx.try_recv() {
             match inbound {
-                IPCMessage::SendMessage(message) => {
+                IPCMessage::SendMessage(message, options) => {
+                    let packet = text_packet(message, &options);
                     if let Err(e) = _stream_api
-                        .send_text(
-                            &mut packet_router,
-                            message.message,
-                            message.destination,
-                            true,
-                            message.channel,
-                        )
+                        .send_to_radio_packet(Some(to_radio::PayloadVariant::Packet(packet)))
                         .await
                     {
                         error!("We tried to send a message but... nope: {e}");
@@ -0,0 +1,347 @@
+use crate::ipc::IPCMessage;
+use crate::tabs::nodes::ComprehensiveNode;
+use crate::util::get_secs;
+use crate::METRICS;
+use anyhow::Result;
+use meshtastic::protobufs::{mesh_packet, routing, to_radio, EnvironmentMetrics, PortNum};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// How often the app copies the node list over for the exporter.
+pub const SNAPSHOT_SECS: u64 = 5;
+/// We only need the request line, so anything past this is ignored.
+const MAX_REQUEST_BYTES: usize = 8192;
+
+/// What the exporter knows: the node list as of the last snapshot, and counters of our own.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    nodes: Vec<ComprehensiveNode>,
+    my_node_id: u32,
+    messages_sent: u64,
+    /// Routing errors reported for our packets, by reason.
+    ack_failures: BTreeMap<&'static str, u64>,
+}
+
+/// Copies the node list for the exporter to read from.
+pub async fn snapshot(node_list: &HashMap<u32, ComprehensiveNode>, my_node_id: u32) {
+    let mut metrics = METRICS.write().await;
+    metrics.nodes = node_list.values().cloned().collect();
+    metrics.nodes.sort_by_key(|cn| cn.id);
+    metrics.my_node_id = my_node_id;
+}
+
+/// Counts text messages on their way to the radio, whichever way they're sent.
+pub async fn count_sent(ipc: &IPCMessage) {
+    let is_message = match ipc {
+        IPCMessage::SendMessage(..) => true,
+        IPCMessage::ToRadio(tr) => match &tr.payload_variant {
+            Some(to_radio::PayloadVariant::Packet(pa)) => matches!(
+                &pa.payload_variant,
+                Some(mesh_packet::PayloadVariant::Decoded(de))
+                    if matches!(de.portnum(), PortNum::TextMessageApp | PortNum::TextMessageCompressedApp)
+            ),
+            _ => false,
+        },
+        _ => false,
+    };
+    if is_message {
+        METRICS.write().await.messages_sent += 1;
+    }
+}
+
+pub async fn count_ack_failure(reason: routing::Error) {
+    *METRICS
+        .write()
+        .await
+        .ack_failures
+        .entry(reason.as_str_name())
+        .or_default() += 1;
+}
+
+/// Answers scrapes of `/metrics` until the app exits.
+pub async fn serve(listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(async move {
+                    if let Err(e) = respond(stream).await {
+                        debug!("Metrics request failed: {e}");
+                    }
+                });
+            }
+            Err(e) => error!("Unable to accept a metrics connection: {e}"),
+        }
+    }
+}
+
+async fn respond(mut stream: TcpStream) -> Result<()> {
+    let mut request = vec![0; MAX_REQUEST_BYTES];
+    let mut len = 0;
+    while len < request.len() && !request[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+        match stream.read(&mut request[len..]).await? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    let request = String::from_utf8_lossy(&request[..len]);
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = match path {
+        "/metrics" => ("200 OK", render(&*METRICS.read().await)),
+        _ => ("404 Not Found", "Metrics are at /metrics\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn node_labels(cn: &ComprehensiveNode) -> String {
+    let name = cn
+        .node_info
+        .user
+        .as_ref()
+        .map(|u| u.long_name.as_str())
+        .unwrap_or("");
+    format!("node=\"!{:08x}\",name=\"{}\"", cn.id, escape(name))
+}
+
+/// The node's most recent environment reading, if it has sent one.
+fn environment(cn: &ComprehensiveNode) -> Option<&EnvironmentMetrics> {
+    cn.timeseries
+        .iter()
+        .rev()
+        .map(|s| &s.environment)
+        .find(|e| **e != EnvironmentMetrics::default())
+}
+
+/// Writes one metric family, skipping nodes that have no reading for it.
+fn family(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: impl Iterator<Item = (String, f64)>,
+) {
+    let samples: Vec<_> = samples.collect();
+    if samples.is_empty() {
+        return;
+    }
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{name}{{{labels}}} {value}");
+    }
+}
+
+fn gauge(
+    out: &mut String,
+    nodes: &[ComprehensiveNode],
+    name: &str,
+    help: &str,
+    value: impl Fn(&ComprehensiveNode) -> Option<f64>,
+) {
+    let samples = nodes
+        .iter()
+        .filter_map(|cn| value(cn).map(|v| (node_labels(cn), v)));
+    family(out, name, "gauge", help, samples);
+}
+
+fn environment_gauge(
+    out: &mut String,
+    nodes: &[ComprehensiveNode],
+    name: &str,
+    help: &str,
+    value: impl Fn(&EnvironmentMetrics) -> Option<f64>,
+) {
+    gauge(out, nodes, name, help, |cn| {
+        environment(cn).and_then(&value)
+    });
+}
+
+fn render(metrics: &Metrics) -> String {
+    let nodes = metrics.nodes.as_slice();
+    let now = get_secs();
+    let mut out = String::new();
+    let device = |cn: &ComprehensiveNode| cn.node_info.device_metrics;
+
+    gauge(
+        &mut out,
+        nodes,
+        "meshtui_node_battery_level_percent",
+        "Battery level; 101 means the node is externally powered",
+        |cn| device(cn)?.battery_level.map(f64::from),
+    );
+    gauge(
+        &mut out,
+        nodes,
+        "meshtui_node_voltage_volts",
+        "Battery or supply voltage",
+        |cn| device(cn)?.voltage.map(f64::from),
+    );
+    gauge(
+        &mut out,
+        nodes,
+        "meshtui_node_channel_utilization_percent",
+        "Share of airtime the node hears in use, including its own",
+        |cn| device(cn)?.channel_utilization.map(f64::from),
+    );
+    gauge(
+        &mut out,
+        nodes,
+        "meshtui_node_air_util_tx_percent",
+        "Share of the last hour the node spent transmitting",
+        |cn| device(cn)?.air_util_tx.map(f64::from),
+    );
+    gauge(
+        &mut out,
+        nodes,
+        "meshtui_node_uptime_seconds",
+        "How long the node has been up",
+        |cn| device(cn)?.uptime_seconds.map(f64::from),
+    );
+    gauge(
+        &mut out,
+        nodes,
+        "meshtui_node_snr_db",
+        "SNR of the last packet our radio received from the node",
+        |cn| (cn.last_snr != 0.0 || cn.last_rssi != 0).then_some(cn.last_snr as f64),
+    );
+    gauge(
+        &mut out,
+        nodes,
+        "meshtui_node_rssi_dbm",
+        "RSSI of the last packet our radio received from the node",
+        |cn| (cn.last_snr != 0.0 || cn.last_rssi != 0).then_some(cn.last_rssi as f64),
+    );
+    gauge(
+        &mut out,
+        nodes,
+        "meshtui_node_hops",
+        "Hops the node's last packet took to reach us",
+        |cn| cn.node_info.hops_away.map(f64::from),
+    );
+    gauge(
+        &mut out,
+        nodes,
+        "meshtui_node_last_heard_seconds",
+        "Seconds since we last heard from the node",
+        |cn| (cn.last_seen > 0).then(|| now.saturating_sub(cn.last_seen) as f64),
+    );
+
+    environment_gauge(
+        &mut out,
+        nodes,
+        "meshtui_node_temperature_celsius",
+        "Temperature",
+        |e| e.temperature.map(f64::from),
+    );
+    environment_gauge(
+        &mut out,
+        nodes,
+        "meshtui_node_relative_humidity_percent",
+        "Relative humidity",
+        |e| e.relative_humidity.map(f64::from),
+    );
+    environment_gauge(
+        &mut out,
+        nodes,
+        "meshtui_node_barometric_pressure_hpa",
+        "Barometric pressure",
+        |e| e.barometric_pressure.map(f64::from),
+    );
+    environment_gauge(
+        &mut out,
+        nodes,
+        "meshtui_node_gas_resistance_mohm",
+        "Gas resistance",
+        |e| e.gas_resistance.map(f64::from),
+    );
+    environment_gauge(
+        &mut out,
+        nodes,
+        "meshtui_node_iaq",
+        "Indoor air quality index",
+        |e| e.iaq.map(f64::from),
+    );
+    environment_gauge(&mut out, nodes, "meshtui_node_lux", "Light level", |e| {
+        e.lux.map(f64::from)
+    });
+    environment_gauge(
+        &mut out,
+        nodes,
+        "meshtui_node_wind_speed_mps",
+        "Wind speed",
+        |e| e.wind_speed.map(f64::from),
+    );
+    environment_gauge(
+        &mut out,
+        nodes,
+        "meshtui_node_wind_direction_degrees",
+        "Wind direction",
+        |e| e.wind_direction.map(f64::from),
+    );
+    environment_gauge(
+        &mut out,
+        nodes,
+        "meshtui_node_rainfall_1h_mm",
+        "Rainfall over the last hour",
+        |e| e.rainfall_1h.map(f64::from),
+    );
+    environment_gauge(
+        &mut out,
+        nodes,
+        "meshtui_node_rainfall_24h_mm",
+        "Rainfall over the last day",
+        |e| e.rainfall_24h.map(f64::from),
+    );
+
+    family(
+        &mut out,
+        "meshtui_node_packets_received_total",
+        "counter",
+        "Packets we've received from the node since meshtui started, by port",
+        nodes.iter().flat_map(|cn| {
+            cn.packet_counts.iter().map(move |(port, count)| {
+                (
+                    format!("{},port=\"{port}\"", node_labels(cn)),
+                    *count as f64,
+                )
+            })
+        }),
+    );
+
+    let me = nodes
+        .iter()
+        .find(|cn| cn.id == metrics.my_node_id)
+        .map(node_labels)
+        .unwrap_or_else(|| format!("node=\"!{:08x}\",name=\"\"", metrics.my_node_id));
+    family(
+        &mut out,
+        "meshtui_messages_sent_total",
+        "counter",
+        "Text messages we've sent since meshtui started",
+        std::iter::once((me.clone(), metrics.messages_sent as f64)),
+    );
+    family(
+        &mut out,
+        "meshtui_ack_failures_total",
+        "counter",
+        "Our packets the mesh reported it couldn't deliver, by reason",
+        metrics
+            .ack_failures
+            .iter()
+            .map(|(reason, count)| (format!("{me},reason=\"{reason}\""), *count as f64)),
+    );
+    out
+}
@@ -0,0 +1,91 @@
+use crate::app::MenuTabs;
+use ratatui::layout::{Margin, Position, Rect};
+use ratatui::widgets::TableState;
+use std::sync::Mutex;
+
+/// How close together two clicks on the same row have to be to count as a double-click.
+pub const DOUBLE_CLICK_MS: u128 = 400;
+
+/// Something on screen that reacts to a click.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Tab(MenuTabs),
+    /// A sub-tab in the config screens, by position.
+    SubTab(usize),
+    /// A row of the current tab's table, counted from the top of the table, not the screen.
+    Row(usize),
+}
+
+/// Where everything clickable was drawn on the last frame.  The tabs render from clones of
+/// themselves, so they leave their geometry here rather than on the tab.
+static TARGETS: Mutex<Vec<(Rect, Target)>> = Mutex::new(vec![]);
+
+fn add(area: Rect, target: Target) {
+    if let Ok(mut targets) = TARGETS.lock() {
+        targets.push((area, target));
+    }
+}
+
+/// Forgets the last frame; called before drawing the next one.
+pub fn clear() {
+    if let Ok(mut targets) = TARGETS.lock() {
+        targets.clear();
+    }
+}
+
+/// What's under the pointer.  Later targets were drawn on top of earlier ones, so they win.
+pub fn target_at(column: u16, row: u16) -> Option<Target> {
+    let targets = TARGETS.lock().ok()?;
+    targets
+        .iter()
+        .rev()
+        .find(|(area, _)| area.contains(Position { x: column, y: row }))
+        .map(|(_, target)| *target)
+}
+
+/// Registers a tab bar drawn with no divider or padding, so each title starts where the one
+/// before it ends.
+pub fn tabs(area: Rect, titles: impl Iterator<Item = String>, target: impl Fn(usize) -> Target) {
+    let mut x = area.x;
+    for (i, title) in titles.enumerate() {
+        let width = (title.chars().count() as u16).min(area.right().saturating_sub(x));
+        if width == 0 {
+            break;
+        }
+        add(Rect::new(x, area.y, width, 1), target(i));
+        x += width;
+    }
+}
+
+/// Registers the rows of a table drawn in `area`.  `header` is how many lines the header and
+/// its margin take up, and `bordered` whether the table sits inside a block with borders.  Rows
+/// are one line high, and scrolled the way ratatui does it: just far enough to show the
+/// selected row.
+pub fn table(area: Rect, bordered: bool, header: u16, state: &TableState, len: usize) {
+    let mut area = match bordered {
+        true => area.inner(Margin {
+            vertical: 1,
+            horizontal: 1,
+        }),
+        false => area,
+    };
+    area.y += header.min(area.height);
+    area.height = area.height.saturating_sub(header);
+    let visible = area.height as usize;
+    if visible == 0 {
+        return;
+    }
+    let mut offset = state.offset().min(len.saturating_sub(1));
+    if let Some(selected) = state.selected().map(|s| s.min(len.saturating_sub(1))) {
+        if selected >= offset + visible {
+            offset = selected + 1 - visible;
+        }
+        offset = offset.min(selected);
+    }
+    for (line, index) in (offset..len).take(visible).enumerate() {
+        add(
+            Rect::new(area.x, area.y + line as u16, area.width, 1),
+            Target::Row(index),
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use crate::app::MqttSettings;
+use crate::ipc::IPCMessage;
+use crate::keyring;
+use crate::meshtastic_interaction::text_packet;
+use anyhow::Result;
+use meshtastic::protobufs::{
+    from_radio, mesh_packet, to_radio, FromRadio, MeshPacket, MyNodeInfo, PortNum, ServiceEnvelope,
+    ToRadio,
+};
+use meshtastic::utils::generate_rand_id;
+use meshtastic::Message;
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+/// Every gateway that hears a packet uploads it, so we remember this many recent ones to drop
+/// the copies.
+const RECENT_PACKETS: usize = 512;
+/// How long to wait before reconnecting after the broker drops us.
+const RECONNECT_DELAY_SECS: u64 = 5;
+
+/// Stands in for the radio: packets published under the root topic are handed to the app as if
+/// our radio had heard them, and our own packets are published if that's been asked for.
+pub(crate) async fn mqtt_loop(
+    settings: MqttSettings,
+    tx: Sender<IPCMessage>,
+    mut rx: Receiver<IPCMessage>,
+) -> Result<()> {
+    let mut options = MqttOptions::new(
+        format!("meshtui-{:08x}", settings.node_id),
+        &settings.host,
+        settings.port,
+    );
+    options.set_keep_alive(Duration::from_secs(30));
+    if let Some(username) = &settings.username {
+        options.set_credentials(username, settings.password.clone().unwrap_or_default());
+    }
+    let (client, mut eventloop) = AsyncClient::new(options, 16);
+    let topic = format!("{}/2/e/#", settings.root_topic);
+    if let Some(channel) = &settings.publish_channel {
+        if !keyring::has_key(channel).await {
+            warn!("No key for {channel}, so nothing can be published; add one with --channel-key");
+        }
+    }
+
+    // there's no radio to tell the app who we are, so we do
+    from_radio(
+        &tx,
+        from_radio::PayloadVariant::MyInfo(MyNodeInfo {
+            my_node_num: settings.node_id,
+            ..Default::default()
+        }),
+    )
+    .await;
+
+    let mut recent = VecDeque::with_capacity(RECENT_PACKETS);
+    loop {
+        tokio::select! {
+            event = eventloop.poll() => match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    // a clean session forgets our subscription, so make it on every (re)connect
+                    if let Err(e) = client.try_subscribe(&topic, QoS::AtMostOnce) {
+                        error!("Couldn't subscribe to {topic}: {e}");
+                    }
+                    info!(
+                        "Connected to MQTT broker {}:{}, reading {topic}",
+                        settings.host, settings.port
+                    );
+                }
+                Ok(Event::Incoming(Packet::Publish(p))) => {
+                    if let Some(packet) = unwrap_envelope(&p.topic, &p.payload) {
+                        if recent.contains(&(packet.from, packet.id)) {
+                            continue;
+                        }
+                        if recent.len() == RECENT_PACKETS {
+                            recent.pop_front();
+                        }
+                        recent.push_back((packet.from, packet.id));
+                        from_radio(&tx, from_radio::PayloadVariant::Packet(packet)).await;
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    error!(
+                        "MQTT connection to {}:{} failed: {e}",
+                        settings.host, settings.port
+                    );
+                    tokio::time::sleep(Duration::from_secs(RECONNECT_DELAY_SECS)).await;
+                }
+            },
+            Some(outbound) = rx.recv() => publish(&client, &settings, outbound).await,
+        }
+    }
+}
+
+async fn from_radio(tx: &Sender<IPCMessage>, payload_variant: from_radio::PayloadVariant) {
+    let fr = FromRadio {
+        payload_variant: Some(payload_variant),
+        ..Default::default()
+    };
+    if let Err(e) = tx.send(IPCMessage::FromRadio(fr)).await {
+        error!("Couldn't send FromRadio packet to mpsc: {e}");
+    }
+}
+
+/// Pulls the packet out of a `ServiceEnvelope`, marked as having come over MQTT.  It's usually
+/// still encrypted; `process_packet` tries our keys on it.
+fn unwrap_envelope(topic: &str, payload: &[u8]) -> Option<MeshPacket> {
+    let envelope = match ServiceEnvelope::decode(payload) {
+        Ok(envelope) => envelope,
+        Err(e) => {
+            debug!("Skipping {topic}, it isn't a service envelope: {e}");
+            return None;
+        }
+    };
+    let mut packet = envelope.packet?;
+    packet.via_mqtt = true;
+    Some(packet)
+}
+
+async fn publish(client: &AsyncClient, settings: &MqttSettings, outbound: IPCMessage) {
+    let mut packet = match outbound {
+        IPCMessage::SendMessage(message, options) => text_packet(message, &options),
+        IPCMessage::ToRadio(ToRadio {
+            payload_variant: Some(to_radio::PayloadVariant::Packet(packet)),
+        }) => packet,
+        _ => return,
+    };
+    if let Some(mesh_packet::PayloadVariant::Decoded(data)) = &packet.payload_variant {
+        if data.portnum() == PortNum::AdminApp {
+            debug!(
+                "Admin messages need a radio; not publishing packet {}",
+                packet.id
+            );
+            return;
+        }
+    }
+    let Some(channel) = &settings.publish_channel else {
+        warn!(
+            "Not sending packet {}; start with --mqtt-publish <channel> to send over MQTT",
+            packet.id
+        );
+        return;
+    };
+    packet.from = settings.node_id;
+    packet.hop_start = packet.hop_limit;
+    // a radio would number the packet itself; the id is half the nonce, so it can't repeat
+    if packet.id == 0 {
+        packet.id = generate_rand_id();
+    }
+    if let Err(e) = keyring::encrypt(&mut packet, channel).await {
+        error!("Unable to publish packet {}: {e}", packet.id);
+        return;
+    }
+    let gateway_id = format!("!{:08x}", settings.node_id);
+    let topic = format!("{}/2/e/{channel}/{gateway_id}", settings.root_topic);
+    let envelope = ServiceEnvelope {
+        packet: Some(packet),
+        channel_id: channel.clone(),
+        gateway_id,
+    };
+    match client
+        .publish(&topic, QoS::AtMostOnce, false, envelope.encode_to_vec())
+        .await
+    {
+        Ok(()) => info!("Published to {topic}"),
+        Err(e) => error!("Unable to publish to {topic}: {e}"),
+    }
+}
@@ -1,15 +1,18 @@
+use crate::admin;
 use crate::app::DeviceConfiguration;
 use crate::ipc::IPCMessage;
-use crate::tabs::nodes::{ComprehensiveNode, TimeSeriesData};
+use crate::keyring;
+use crate::tabs::nodes::{ComprehensiveNode, TimeSeriesData, TracedRoute};
+use crate::tabs::range_test::{self, RangeTestHit};
+use crate::tabs::waypoints::WaypointEntry;
 use crate::util::get_secs;
-use crate::{util, DEVICE_CONFIG};
+use crate::{unishox, util, DEVICE_CONFIG};
 use meshtastic::packet::PacketDestination;
-use meshtastic::protobufs::config::PayloadVariant;
 use meshtastic::protobufs::log_record::Level;
-use meshtastic::protobufs::module_config::PayloadVariant as mpv;
 use meshtastic::protobufs::{
-    from_radio, mesh_packet, routing, telemetry, NeighborInfo, NodeInfo, PortNum, Position,
-    RouteDiscovery, Routing, User,
+    from_radio, mesh_packet, AdminMessage, routing, telemetry, NeighborInfo, NodeInfo, PortNum,
+    store_and_forward, MeshPacket, Position, RouteDiscovery, Routing, StoreAndForward, User,
+    Waypoint,
 };
 use meshtastic::types::MeshChannel;
 use meshtastic::Message;
@@ -19,6 +22,9 @@ pub(crate) enum PacketResponse {
     NodeUpdate(u32, Box<ComprehensiveNode>),
     UserUpdate(u32, User),
     InboundMessage(MessageEnvelope),
+    Waypoint(WaypointEntry),
+    RangeTest(RangeTestHit),
+    StoreForward(u32, String, StoreAndForward),
     OurAddress(u32),
 }
 
@@ -31,11 +37,219 @@ pub struct MessageEnvelope {
     pub(crate) message: String,
     pub(crate) rx_rssi: i32,
     pub(crate) rx_snr: f32,
+    pub(crate) id: u32,
+    pub(crate) via_store_forward: bool,
+    /// The id of the message this one answers, or 0.
+    pub(crate) reply_id: u32,
+    /// Set when this message is an emoji reaction to `reply_id` rather than a reply.
+    pub(crate) emoji: bool,
+    pub(crate) kind: MessageKind,
+    /// The key we decrypted this with ourselves, when our radio couldn't.
+    pub(crate) decrypted_with: Option<String>,
+    /// Set when this was a direct message encrypted to our radio's public key.
+    pub(crate) pki_encrypted: bool,
 }
 
+/// Which port a message arrived on.  Alerts and sensor events are shown with the chat, marked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MessageKind {
+    #[default]
+    Text,
+    Alert,
+    DetectionSensor,
+}
+
+impl MessageKind {
+    pub fn marker(self) -> &'static str {
+        match self {
+            MessageKind::Text => "",
+            MessageKind::Alert => "🔔 ",
+            MessageKind::DetectionSensor => "👁 ",
+        }
+    }
+}
+
+pub enum RequestReply {
+    Answered,
+    Acknowledged,
+    Failed(routing::Error),
+}
+
+/// If this packet is a reply to one of our own requests, returns the id of the request it
+/// answers and whether it carried data or a routing error.
+pub fn request_reply(packet: &IPCMessage) -> Option<(u32, RequestReply)> {
+    let IPCMessage::FromRadio(fr) = packet else {
+        return None;
+    };
+    let Some(from_radio::PayloadVariant::Packet(pa)) = &fr.payload_variant else {
+        return None;
+    };
+    let Some(mesh_packet::PayloadVariant::Decoded(de)) = &pa.payload_variant else {
+        return None;
+    };
+    if de.request_id == 0 {
+        return None;
+    }
+    if de.portnum() != PortNum::RoutingApp {
+        return Some((de.request_id, RequestReply::Answered));
+    }
+    match Routing::decode(de.payload.as_slice()).ok()?.variant {
+        Some(routing::Variant::ErrorReason(0)) => Some((de.request_id, RequestReply::Acknowledged)),
+        Some(routing::Variant::ErrorReason(er)) => Some((
+            de.request_id,
+            RequestReply::Failed(routing::Error::try_from(er).unwrap_or_default()),
+        )),
+        _ => None,
+    }
+}
+
+/// How a packet reached us: the hops it took, the relay that handed it to our radio and the
+/// signal it arrived with.
+pub struct LinkObservation {
+    pub from: u32,
+    pub hops: Option<u32>,
+    pub relay_node: Option<u32>,
+    pub via_mqtt: bool,
+    pub rx_snr: f32,
+    pub rx_rssi: i32,
+    /// `None` if the packet was encrypted for a channel we don't have.
+    pub port: Option<PortNum>,
+}
+
+/// Reads the routing header of any received mesh packet, decoded or not.  Firmware that predates
+/// `hop_start` leaves it at zero, in which case the hop count is unknown.
+pub fn observe_link(packet: &IPCMessage) -> Option<LinkObservation> {
+    let IPCMessage::FromRadio(fr) = packet else {
+        return None;
+    };
+    let Some(from_radio::PayloadVariant::Packet(pa)) = &fr.payload_variant else {
+        return None;
+    };
+    if pa.from == 0 {
+        return None;
+    }
+    Some(LinkObservation {
+        from: pa.from,
+        hops: (pa.hop_start > 0 && pa.hop_start >= pa.hop_limit)
+            .then(|| pa.hop_start - pa.hop_limit),
+        relay_node: Some(pa.relay_node).filter(|r| *r != 0),
+        via_mqtt: pa.via_mqtt,
+        rx_snr: pa.rx_snr,
+        rx_rssi: pa.rx_rssi,
+        port: match &pa.payload_variant {
+            Some(mesh_packet::PayloadVariant::Decoded(de)) => Some(de.portnum()),
+            _ => None,
+        },
+    })
+}
+
+/// The short name of a node we know, or its id if we don't.
+fn node_label(node_list: &HashMap<u32, ComprehensiveNode>, id: u32) -> String {
+    match node_list.get(&id).and_then(|cn| cn.node_info.user.clone()) {
+        Some(user) if !user.short_name.is_empty() => user.short_name,
+        _ => format!("!{:x}", id),
+    }
+}
+
+/// A range test hit, with the sender's last known position.  Our own position and the distance
+/// are filled in by the range test tab.
+fn range_test_hit(
+    pa: &MeshPacket,
+    seq: u32,
+    node_list: &HashMap<u32, ComprehensiveNode>,
+) -> RangeTestHit {
+    RangeTestHit {
+        timestamp: match pa.rx_time {
+            0 => get_secs(),
+            t => t as u64,
+        },
+        from: pa.from,
+        from_name: node_label(node_list, pa.from),
+        seq,
+        sender_position: node_list
+            .get(&pa.from)
+            .and_then(|cn| cn.node_info.position.as_ref())
+            .and_then(util::position_degrees),
+        our_position: None,
+        distance_m: None,
+        rx_snr: pa.rx_snr,
+        rx_rssi: pa.rx_rssi,
+    }
+}
+
+fn message_envelope(pa: &MeshPacket, source: NodeInfo, message: String) -> MessageEnvelope {
+    let destination = match pa.to {
+        0 => PacketDestination::Local,
+        u32::MAX => PacketDestination::Broadcast,
+        s => PacketDestination::Node(s.into()),
+    };
+    let (reply_id, emoji) = match &pa.payload_variant {
+        Some(mesh_packet::PayloadVariant::Decoded(de)) => (de.reply_id, de.emoji != 0),
+        _ => (0, false),
+    };
+    MessageEnvelope {
+        timestamp: match pa.rx_time {
+            0 => get_secs() as u32,
+            t => t,
+        },
+        source: Some(source),
+        destination,
+        channel: MeshChannel::from(pa.channel),
+        message,
+        rx_rssi: pa.rx_rssi,
+        rx_snr: pa.rx_snr,
+        id: pa.id,
+        via_store_forward: false,
+        reply_id,
+        emoji,
+        kind: MessageKind::Text,
+        decrypted_with: None,
+        pki_encrypted: pa.pki_encrypted,
+    }
+}
+
+/// Handles a packet from the radio.  Packets our radio couldn't decrypt are tried against the
+/// keyring first, and if that works they're handled like any other, marked as decrypted here.
 pub async fn process_packet(
     packet: IPCMessage,
-    node_list: HashMap<u32, ComprehensiveNode>,
+    node_list: &HashMap<u32, ComprehensiveNode>,
+    my_node_id: u32,
+) -> Option<PacketResponse> {
+    let (packet, decrypted_with) = decrypt_locally(packet).await;
+    let mut response = handle_packet(packet, node_list, my_node_id).await;
+    if let (Some(PacketResponse::InboundMessage(envelope)), Some(key)) =
+        (response.as_mut(), decrypted_with)
+    {
+        envelope.decrypted_with = Some(key);
+    }
+    response
+}
+
+async fn decrypt_locally(packet: IPCMessage) -> (IPCMessage, Option<String>) {
+    let IPCMessage::FromRadio(fr) = &packet else {
+        return (packet, None);
+    };
+    let Some(from_radio::PayloadVariant::Packet(pa)) = &fr.payload_variant else {
+        return (packet, None);
+    };
+    match keyring::decrypt(pa).await {
+        Some((decoded, key)) => {
+            info!(
+                "Decrypted a packet from !{:x} locally with the {key} key",
+                pa.from
+            );
+            let mut fr = fr.clone();
+            fr.payload_variant = Some(from_radio::PayloadVariant::Packet(decoded));
+            (IPCMessage::FromRadio(fr), Some(key))
+        }
+        None => (packet, None),
+    }
+}
+
+async fn handle_packet(
+    packet: IPCMessage,
+    node_list: &HashMap<u32, ComprehensiveNode>,
+    my_node_id: u32,
 ) -> Option<PacketResponse> {
     if let IPCMessage::FromRadio(fr) = packet {
         if let Some(some_fr) = fr.payload_variant {
@@ -66,10 +280,9 @@ pub async fn process_packet(
                                                 .id,
                                             pa.from
                                         );
+                                        cn.record_position(&data);
                                         cn.node_info.position = Some(data);
                                         cn.last_seen = util::get_secs();
-                                        cn.last_rssi = pa.rx_rssi;
-                                        cn.last_snr = pa.rx_snr;
                                         return Some(PacketResponse::NodeUpdate(
                                             cn.node_info.num,
                                             Box::new(cn),
@@ -84,52 +297,22 @@ pub async fn process_packet(
                                             }
                                         };
                                         if let Some(v) = data.variant {
+                                            let mut cn = match node_list.get(&pa.from) {
+                                                Some(n) => n.clone(),
+                                                None => ComprehensiveNode::with_id(pa.from),
+                                            };
+                                            let mut tsd = TimeSeriesData {
+                                                timestamp: get_secs(),
+                                                rssi: pa.rx_rssi as f64,
+                                                snr: pa.rx_snr as f64,
+                                                ..Default::default()
+                                            };
                                             match v {
                                                 telemetry::Variant::EnvironmentMetrics(env) => {
-                                                    let mut cn = match node_list
-                                                        .contains_key(&pa.from)
-                                                    {
-                                                        true => node_list
-                                                            .get(&pa.from)
-                                                            .unwrap()
-                                                            .to_owned(),
-                                                        false => {
-                                                            ComprehensiveNode::with_id(pa.from)
-                                                        }
-                                                    };
                                                     info!("Received EnvironmentalMetrics from !{:x} ({})", pa.from, pa.from);
-                                                    cn.timeseries.push_back(
-                                                        TimeSeriesData {
-                                                            timestamp: get_secs(),
-                                                            environment: env.clone(),
-                                                            rssi: pa.rx_rssi as f64,
-                                                            snr: pa.rx_snr as f64,
-                                                            ..Default::default()
-                                                        });
-                                                    if cn.timeseries_start == 0 {
-                                                        cn.timeseries_start = get_secs();
-                                                    };
-                                                    cn.last_seen = util::get_secs();
-                                                    cn.last_rssi = pa.rx_rssi;
-                                                    cn.last_snr = pa.rx_snr;
-                                                    return Some(PacketResponse::NodeUpdate(
-                                                        cn.node_info.num,
-                                                        Box::new(cn),
-                                                    ));
-                                                    
+                                                    tsd.environment = env;
                                                 }
                                                 telemetry::Variant::DeviceMetrics(dm) => {
-                                                    let mut cn = match node_list
-                                                        .contains_key(&pa.from)
-                                                    {
-                                                        true => node_list
-                                                            .get(&pa.from)
-                                                            .unwrap()
-                                                            .to_owned(),
-                                                        false => {
-                                                            ComprehensiveNode::with_id(pa.from)
-                                                        }
-                                                    };
                                                     info!(
                                                         "Updating DeviceMetrics for {} ({})",
                                                         cn.clone()
@@ -139,32 +322,31 @@ pub async fn process_packet(
                                                             .id,
                                                         pa.from
                                                     );
-                                                    cn.node_info.device_metrics = Some(dm.clone());
-                                                    cn.timeseries.push_back(
-                                                        TimeSeriesData {
-                                                            timestamp: get_secs(),
-                                                            device: dm.clone(),
-                                                            rssi: pa.rx_rssi as f64,
-                                                            snr: pa.rx_snr as f64,
-                                                            ..Default::default()
-                                                        });
-                                                    if cn.timeseries_start == 0 {
-                                                        cn.timeseries_start = get_secs();
-                                                    };
-                                                    cn.last_seen = util::get_secs();
-                                                    cn.last_rssi = pa.rx_rssi;
-                                                    cn.last_snr = pa.rx_snr;
-                                                    return Some(PacketResponse::NodeUpdate(
-                                                        cn.node_info.num,
-                                                        Box::new(cn),
-                                                    ));
+                                                    cn.node_info.device_metrics = Some(dm);
+                                                    tsd.device = dm;
+                                                }
+                                                telemetry::Variant::AirQualityMetrics(aq) => {
+                                                    info!("Received AirQualityMetrics from !{:x} ({})", pa.from, pa.from);
+                                                    cn.air_quality = Some(aq);
+                                                    tsd.air_quality = aq;
+                                                }
+                                                telemetry::Variant::PowerMetrics(pm) => {
+                                                    info!("Received PowerMetrics from !{:x} ({})", pa.from, pa.from);
+                                                    cn.power = Some(pm);
+                                                    tsd.power = pm;
                                                 }
                                                 _ => {
                                                     return None;
-                                                } // Variant::EnvironmentMetrics(_) => {}
-                                                  // Variant::AirQualityMetrics(_) => {}
-                                                  // Variant::PowerMetrics(_) => {}
+                                                } // Variant::LocalStats(_) => {}
+                                                  // Variant::HealthMetrics(_) => {}
+                                                  // Variant::HostMetrics(_) => {}
                                             }
+                                            cn.record(tsd);
+                                            cn.last_seen = util::get_secs();
+                                            return Some(PacketResponse::NodeUpdate(
+                                                cn.id,
+                                                Box::new(cn),
+                                            ));
                                         }
                                         return None;
                                     }
@@ -204,8 +386,7 @@ pub async fn process_packet(
                                         };
                                         cn.neighbors = data.neighbors;
                                         cn.last_seen = util::get_secs();
-                                        cn.last_rssi = pa.rx_rssi;
-                                        cn.last_snr = pa.rx_snr;
+                                        cn.neighbors_heard = cn.last_seen;
                                         return Some(PacketResponse::NodeUpdate(
                                             cn.node_info.num,
                                             Box::new(cn),
@@ -279,7 +460,18 @@ pub async fn process_packet(
                                                 }
                                                 Some(n) => n.clone(),
                                             };
-                                            cn.route_list.insert(to_id, route.clone().route);
+                                            // a request addressed to us runs the other way, so
+                                            // only replies go in as routes from `to_id` to `from_id`
+                                            if de.request_id != 0 {
+                                                cn.route_list.insert(to_id, route.clone().route);
+                                                cn.traceroutes.insert(
+                                                    to_id,
+                                                    TracedRoute {
+                                                        discovery: route.clone(),
+                                                        timestamp: util::get_secs(),
+                                                    },
+                                                );
+                                            }
                                             info!(
                                                 "updating route table to {:#?} for !{:x}->!{:x}",
                                                 route.route, from_id, to_id
@@ -291,8 +483,33 @@ pub async fn process_packet(
                                         info!("We were just pinged.");
                                     }
 
-                                    PortNum::TextMessageApp => {
-                                        if let Ok(message) = String::from_utf8(de.payload) {
+                                    PortNum::RangeTestApp => {
+                                        let payload = String::from_utf8_lossy(&de.payload);
+                                        return match range_test::parse_seq(&payload) {
+                                            Some(seq) => Some(PacketResponse::RangeTest(
+                                                range_test_hit(&pa, seq, node_list),
+                                            )),
+                                            None => {
+                                                warn!("Unexpected range test payload: {payload}");
+                                                None
+                                            }
+                                        };
+                                    }
+                                    PortNum::TextMessageApp | PortNum::TextMessageCompressedApp => {
+                                        let payload = match de.portnum() {
+                                            PortNum::TextMessageCompressedApp => {
+                                                unishox::decompress(&de.payload)
+                                            }
+                                            _ => de.payload,
+                                        };
+                                        if let Ok(message) = String::from_utf8(payload) {
+                                            // some range test senders use the text port; keep
+                                            // those out of the chat
+                                            if let Some(seq) = range_test::parse_seq(&message) {
+                                                return Some(PacketResponse::RangeTest(
+                                                    range_test_hit(&pa, seq, node_list),
+                                                ));
+                                            }
                                             let source_ni = match node_list.get(&pa.from) {
                                                 Some(s) => s.clone().node_info,
                                                 None => {
@@ -303,24 +520,8 @@ pub async fn process_packet(
                                                     return None;
                                                 }
                                             };
-                                            let _dest_ni =
-                                                node_list.get(&pa.to).map(|s| s.clone().node_info);
-                                            let destinated: PacketDestination = match pa.to {
-                                                0 => PacketDestination::Local,
-                                                u32::MAX => PacketDestination::Broadcast,
-                                                s => PacketDestination::Node(s.into()),
-                                            };
-
                                             return Some(PacketResponse::InboundMessage(
-                                                MessageEnvelope {
-                                                    timestamp: pa.rx_time,
-                                                    source: Some(source_ni),
-                                                    destination: destinated,
-                                                    channel: MeshChannel::from(pa.channel),
-                                                    message,
-                                                    rx_rssi: pa.rx_rssi,
-                                                    rx_snr: pa.rx_snr,
-                                                },
+                                                message_envelope(&pa, source_ni, message),
                                             ));
                                         } else {
                                             warn!(
@@ -329,19 +530,92 @@ pub async fn process_packet(
                                             );
                                         }
                                     }
+                                    PortNum::AlertApp | PortNum::DetectionSensorApp => {
+                                        let kind = match de.portnum() {
+                                            PortNum::AlertApp => MessageKind::Alert,
+                                            _ => MessageKind::DetectionSensor,
+                                        };
+                                        let source = node_list
+                                            .get(&pa.from)
+                                            .map(|n| n.node_info.clone())
+                                            .unwrap_or_else(|| NodeInfo {
+                                                num: pa.from,
+                                                ..Default::default()
+                                            });
+                                        let message =
+                                            String::from_utf8_lossy(&de.payload).to_string();
+                                        info!("{kind:?} from !{:x}: {message}", pa.from);
+                                        let mut envelope = message_envelope(&pa, source, message);
+                                        envelope.kind = kind;
+                                        return Some(PacketResponse::InboundMessage(envelope));
+                                    }
+                                    PortNum::StoreForwardApp => {
+                                        let sf =
+                                            match StoreAndForward::decode(de.payload.as_slice()) {
+                                                Ok(sf) => sf,
+                                                Err(e) => {
+                                                    error!("Error decoding store & forward: {}", e);
+                                                    return None;
+                                                }
+                                            };
+                                        // replayed messages keep their original sender
+                                        if let Some(store_and_forward::Variant::Text(text)) =
+                                            sf.variant.clone()
+                                        {
+                                            let source = node_list
+                                                .get(&pa.from)
+                                                .map(|cn| cn.node_info.clone())
+                                                .unwrap_or(NodeInfo {
+                                                    num: pa.from,
+                                                    ..Default::default()
+                                                });
+                                            let message =
+                                                String::from_utf8_lossy(&text).to_string();
+                                            let mut envelope =
+                                                message_envelope(&pa, source, message);
+                                            envelope.via_store_forward = true;
+                                            return Some(PacketResponse::InboundMessage(envelope));
+                                        }
+                                        return Some(PacketResponse::StoreForward(
+                                            pa.from,
+                                            node_label(node_list, pa.from),
+                                            sf,
+                                        ));
+                                    }
+                                    PortNum::AdminApp => {
+                                        match AdminMessage::decode(de.payload.as_slice()) {
+                                            Ok(am) => {
+                                                admin::handle_admin_message(pa.from, my_node_id, am)
+                                                    .await
+                                            }
+                                            Err(e) => error!("Unable to decode admin message: {e}"),
+                                        }
+                                        return None;
+                                    }
+                                    PortNum::WaypointApp => {
+                                        let waypoint = match Waypoint::decode(de.payload.as_slice())
+                                        {
+                                            Ok(w) => w,
+                                            Err(e) => {
+                                                error!("Error decoding waypoint: {}", e);
+                                                return None;
+                                            }
+                                        };
+                                        return Some(PacketResponse::Waypoint(WaypointEntry {
+                                            waypoint,
+                                            owner: pa.from,
+                                            owner_name: node_label(node_list, pa.from),
+                                            channel: pa.channel,
+                                        }));
+                                    }
                                     _ => {
                                         error!("{:#?}", de);
                                         return None;
-                                    } // PortNum::AdminApp => {}
-                                      // PortNum::WaypointApp => {}
-
-                                      // PortNum::PaxcounterApp => {}
-                                      // PortNum::StoreForwardApp => {}
-                                      // PortNum::RangeTestApp => {}
+                                    } // PortNum::PaxcounterApp => {}
                                 }
                             }
                             mesh_packet::PayloadVariant::Encrypted(_) => {
-                                info!("Received an encrypted packet.");
+                                info!("Received an encrypted packet we have no key for.");
                                 return None;
                             }
                         }
@@ -363,6 +637,9 @@ pub async fn process_packet(
                     cn.last_seen = util::get_secs();
                     cn.last_rssi = 0;
                     cn.last_snr = ni.snr;
+                    if let Some(position) = &ni.position {
+                        cn.record_position(position);
+                    }
 
                     return Some(PacketResponse::NodeUpdate(ni.num, Box::new(cn)));
                 }
@@ -376,19 +653,7 @@ pub async fn process_packet(
                                 *f = Some(DeviceConfiguration::default());
                             }
                             let mut devcfg = f.clone().unwrap();
-                            match s {
-                                PayloadVariant::Device(d) => devcfg.device = d,
-                                PayloadVariant::Position(p) => devcfg.position = p,
-                                PayloadVariant::Power(p) => devcfg.power = p,
-                                PayloadVariant::Network(n) => devcfg.network = n,
-                                PayloadVariant::Display(d) => devcfg.display = d,
-                                PayloadVariant::Lora(l) => devcfg.lora = l,
-                                PayloadVariant::Bluetooth(b) => devcfg.bluetooth = b,
-                                PayloadVariant::Security(s) => devcfg.security = s,
-                                PayloadVariant::Sessionkey(sk) => devcfg.session_key = sk,
-                                PayloadVariant::DeviceUi(d) => devcfg.device_ui = d,
-                            }
-                            devcfg.last_update = get_secs();
+                            devcfg.apply_config(s);
                             *f = Some(devcfg);
                         }
                     }
@@ -434,23 +699,7 @@ pub async fn process_packet(
                             *f = Some(DeviceConfiguration::default());
                         }
                         let mut devcfg = f.clone().unwrap();
-
-                        match module {
-                            mpv::Mqtt(o) => devcfg.mqtt = o,
-                            mpv::Serial(o) => devcfg.serial = o,
-                            mpv::ExternalNotification(o) => devcfg.external_notification = o,
-                            mpv::StoreForward(o) => devcfg.store_forward = o,
-                            mpv::RangeTest(o) => devcfg.range_test = o,
-                            mpv::Telemetry(o) => devcfg.telemetry = o,
-                            mpv::CannedMessage(o) => devcfg.canned_message = o,
-                            mpv::Audio(o) => devcfg.audio = o,
-                            mpv::RemoteHardware(o) => devcfg.remote_hardware = o,
-                            mpv::NeighborInfo(o) => devcfg.neighbor_info = o,
-                            mpv::AmbientLighting(o) => devcfg.ambient_lighting = o,
-                            mpv::DetectionSensor(o) => devcfg.detection_sensor = o,
-                            mpv::Paxcounter(o) => devcfg.paxcounter = o,
-                        }
-                        devcfg.last_update = get_secs();
+                        devcfg.apply_module_config(module);
                         *f = Some(devcfg);
                     }
                 }
@@ -461,26 +710,13 @@ pub async fn process_packet(
                     );
                 }
                 from_radio::PayloadVariant::Channel(c) => {
-                    let mut channelpacket = c.clone();
-                    if let Some(mut channel) = channelpacket.settings.clone() {
-                        let mut f = DEVICE_CONFIG.write().await;
-                        if f.is_none() {
-                            *f = Some(DeviceConfiguration::default());
-                        }
-                        let mut devcfg = f.clone().unwrap();
-                        if c.index == 0 && channel.name.is_empty() && channel.psk == [1] {
-                            channel.name = "LongFast (Default)".to_string();
-                        };
-                        channelpacket.settings = Some(channel.clone());
-                        info!(
-                            "Storing channel config for {} (Ch: {})",
-                            channel.name, c.index
-                        );
-                        devcfg.channels.insert(c.index, channelpacket.clone());
-
-                        devcfg.last_update = get_secs();
-                        *f = Some(devcfg);
+                    let mut f = DEVICE_CONFIG.write().await;
+                    if f.is_none() {
+                        *f = Some(DeviceConfiguration::default());
                     }
+                    let mut devcfg = f.clone().unwrap();
+                    devcfg.apply_channel(c);
+                    *f = Some(devcfg);
                 }
                 from_radio::PayloadVariant::QueueStatus(v) => {
                     debug!(
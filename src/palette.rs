@@ -0,0 +1,268 @@
+use crate::app::{centered_rect, Connection, MenuTabs};
+use crate::keymap::Action;
+use crate::theme::THEME;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::{prelude::*, widgets::*};
+
+/// What a palette entry does when it's picked.
+#[derive(Debug, Clone)]
+pub enum Command {
+    Action(Action),
+    GoTo(MenuTabs),
+    /// Opens the composer with the message addressed to this node.
+    DirectMessage(u32, String),
+    Traceroute(u32),
+    CannedMessage(String),
+    ToggleMqtt,
+    Connect(Connection),
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub label: String,
+    pub command: Command,
+}
+
+impl Entry {
+    pub fn new(label: impl Into<String>, command: Command) -> Entry {
+        Entry {
+            label: label.into(),
+            command,
+        }
+    }
+}
+
+/// The function keys each tab handles, for the palette.
+pub fn function_keys(tab: MenuTabs) -> &'static [(u8, &'static str)] {
+    match tab {
+        MenuTabs::Nodes => &[
+            (1, "Show the nodes help"),
+            (2, "Traceroute the selected node"),
+            (3, "Change the chart's time window"),
+            (4, "Overlay another graph on the chart"),
+            (5, "Switch the chart between graphs and the node list"),
+            (6, "Mark the node for comparison"),
+        ],
+        MenuTabs::Messages => &[
+            (2, "Ask a Store & Forward router for missed messages"),
+            (3, "Pick the next Store & Forward router"),
+            (4, "Ask the Store & Forward router for its statistics"),
+        ],
+        MenuTabs::Waypoints => &[
+            (2, "New waypoint, or send the one being edited"),
+            (3, "Delete the selected waypoint"),
+        ],
+        MenuTabs::RangeTest => &[(2, "Start or stop recording"), (3, "Export the session")],
+        MenuTabs::DeviceConfig => &[
+            (2, "Switch between the local radio and remote nodes"),
+            (3, "Ask the remote node for this section again"),
+            (4, "Reboot the node"),
+            (5, "Confirm the pending security change"),
+        ],
+        MenuTabs::ModulesConfig => &[
+            (2, "Switch between the local radio and remote nodes"),
+            (3, "Ask the remote node for this section again"),
+            (4, "Reboot the node"),
+        ],
+        MenuTabs::Channels | MenuTabs::About => &[],
+    }
+}
+
+/// Reads `connect HOST[:PORT]` or `connect /dev/ttyUSB0` from what's been typed.
+fn connect_target(query: &str) -> Option<Connection> {
+    let target = query.trim().strip_prefix("connect ")?.trim();
+    if target.is_empty() {
+        return None;
+    }
+    if target.starts_with('/') || target.to_uppercase().starts_with("COM") {
+        return Some(Connection::Serial(target.to_string()));
+    }
+    Some(match target.rsplit_once(':') {
+        Some((host, port)) => Connection::TCP(host.to_string(), port.parse().ok()?),
+        None => Connection::TCP(target.to_string(), 4403),
+    })
+}
+
+/// How well `query` matches `label`: its characters have to appear in order, and runs of them
+/// and matches at the start of words score higher.
+pub fn fuzzy_score(query: &str, label: &str) -> Option<i32> {
+    let label: Vec<char> = label.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut from = 0;
+    let mut previous: Option<usize> = None;
+    for q in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = (from..label.len()).find(|i| label[*i] == q)?;
+        score += 1;
+        if previous.is_some_and(|p| p + 1 == found) {
+            score += 5;
+        }
+        if found == 0 || !label[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+        score -= (found - from).min(5) as i32;
+        previous = Some(found);
+        from = found + 1;
+    }
+    Some(score * 10 - label.len() as i32 / 10)
+}
+
+/// The `:` / Ctrl-P popup: type to narrow the commands down, Enter to run one.
+#[derive(Debug, Clone, Default)]
+pub struct Palette {
+    pub query: String,
+    selected: usize,
+    entries: Vec<Entry>,
+}
+
+pub enum PaletteKey {
+    /// Still picking.
+    Open,
+    Close,
+    Run(Command),
+}
+
+impl Palette {
+    pub fn new(entries: Vec<Entry>) -> Palette {
+        Palette {
+            entries,
+            ..Default::default()
+        }
+    }
+
+    /// The entries that match what's been typed, best first.
+    fn matches(&self) -> Vec<Entry> {
+        let mut matches: Vec<(i32, &Entry)> = self
+            .entries
+            .iter()
+            .filter_map(|e| fuzzy_score(&self.query, &e.label).map(|s| (s, e)))
+            .collect();
+        matches.sort_by_key(|(score, _)| -score);
+        let mut entries: Vec<Entry> = matches.into_iter().map(|(_, e)| e.clone()).collect();
+        if let Some(connection) = connect_target(&self.query) {
+            let label = match &connection {
+                Connection::TCP(host, port) => format!("Connect to {host}:{port}"),
+                Connection::Serial(port) => format!("Connect to serial port {port}"),
+                _ => String::new(),
+            };
+            entries.insert(0, Entry::new(label, Command::Connect(connection)));
+        }
+        entries
+    }
+
+    pub fn key(&mut self, press: KeyEvent) -> PaletteKey {
+        let ctrl = press.modifiers.contains(KeyModifiers::CONTROL);
+        match press.code {
+            KeyCode::Esc => return PaletteKey::Close,
+            KeyCode::Char('g') if ctrl => return PaletteKey::Close,
+            KeyCode::Enter => {
+                return match self.matches().into_iter().nth(self.selected) {
+                    Some(entry) => PaletteKey::Run(entry.command),
+                    None => PaletteKey::Open,
+                }
+            }
+            KeyCode::Up | KeyCode::BackTab => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Char('p') | KeyCode::Char('k') if ctrl => {
+                self.selected = self.selected.saturating_sub(1)
+            }
+            KeyCode::Down | KeyCode::Tab => self.select_next(),
+            KeyCode::Char('n') | KeyCode::Char('j') if ctrl => self.select_next(),
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.selected = 0;
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.query.push(c);
+                self.selected = 0;
+            }
+            _ => {}
+        }
+        PaletteKey::Open
+    }
+
+    fn select_next(&mut self) {
+        self.selected = self
+            .selected
+            .saturating_add(1)
+            .min(self.matches().len().saturating_sub(1));
+    }
+
+    pub fn render(&self, area: Rect, buf: &mut Buffer) {
+        let area = centered_rect(area, 60, 70);
+        Clear.render(area, buf);
+        let block = Block::new()
+            .borders(Borders::ALL)
+            .title("Commands (Enter runs, Esc closes)")
+            .title_alignment(Alignment::Center)
+            .border_set(symbols::border::DOUBLE)
+            .style(THEME.popup_window);
+        let inner = block.inner(area);
+        block.render(area, buf);
+        let [input, list] =
+            Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(inner);
+        Line::from(vec![
+            Span::raw("> "),
+            Span::raw(self.query.clone()),
+            Span::styled(" ", THEME.composer_cursor),
+        ])
+        .render(input, buf);
+
+        let matches = self.matches();
+        let visible = list.height as usize;
+        let skip = (self.selected + 1).saturating_sub(visible);
+        let lines: Vec<Line> = matches
+            .iter()
+            .enumerate()
+            .skip(skip)
+            .take(visible)
+            .map(|(i, entry)| match i == self.selected {
+                true => Line::styled(format!("> {}", entry.label), THEME.tabs_selected),
+                false => Line::raw(format!("  {}", entry.label)),
+            })
+            .collect();
+        match lines.is_empty() {
+            true => Paragraph::new("No matching commands.  \"connect HOST[:PORT]\" or \"connect /dev/ttyUSB0\" switches radios.").render(list, buf),
+            false => Paragraph::new(lines).render(list, buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_matches_in_order() {
+        assert!(fuzzy_score("trbob", "Traceroute BOB").is_some());
+        assert!(fuzzy_score("bobtr", "Traceroute BOB").is_none());
+        assert!(fuzzy_score("exp", "Export") > fuzzy_score("exp", "Next page"));
+    }
+
+    #[test]
+    fn best_match_first() {
+        let mut palette = Palette::new(vec![
+            Entry::new("Next page", Command::Action(Action::PageDown)),
+            Entry::new("Go to Nodes", Command::GoTo(MenuTabs::Nodes)),
+            Entry::new("Export", Command::Action(Action::Export)),
+        ]);
+        palette.query = "exp".to_string();
+        let labels: Vec<String> = palette.matches().into_iter().map(|e| e.label).collect();
+        assert_eq!(labels, ["Export", "Next page"]);
+    }
+
+    #[test]
+    fn connect_targets() {
+        assert!(matches!(
+            connect_target("connect radio.local"),
+            Some(Connection::TCP(host, 4403)) if host == "radio.local"
+        ));
+        assert!(matches!(
+            connect_target("connect 10.0.0.2:4000"),
+            Some(Connection::TCP(host, 4000)) if host == "10.0.0.2"
+        ));
+        assert!(matches!(
+            connect_target("connect /dev/ttyUSB0"),
+            Some(Connection::Serial(port)) if port == "/dev/ttyUSB0"
+        ));
+        assert!(connect_target("connect ").is_none());
+    }
+}
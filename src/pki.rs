@@ -0,0 +1,122 @@
+use crate::ipc::IPCMessage;
+use anyhow::{Context, Result};
+use base64::prelude::*;
+use itertools::Itertools;
+use meshtastic::protobufs::from_radio;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// What a node's public key told us about it, measured against the key we pinned for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyCheck {
+    /// We hadn't seen a key for this node before, so this one is now pinned.
+    First,
+    Known,
+    /// The node presented a different key from the pinned one, which could be someone else
+    /// using its node id.  The pin is left alone until the user accepts the new key.
+    Changed {
+        pinned: Vec<u8>,
+    },
+}
+
+/// Public keys pinned trust-on-first-use, by node id.  Pins only outlive the session when a
+/// `--known-keys` file is given.
+#[derive(Debug, Clone, Default)]
+pub struct KeyPins {
+    pins: HashMap<u32, Vec<u8>>,
+    path: Option<PathBuf>,
+}
+
+/// A short, readable form of a public key: its first eight bytes in hex.
+pub fn fingerprint(key: &[u8]) -> String {
+    key.iter()
+        .take(8)
+        .map(|b| format!("{b:02x}"))
+        .chunks(2)
+        .into_iter()
+        .map(|mut pair| pair.join(""))
+        .join(" ")
+}
+
+/// The sender and key of a packet our radio decrypted with PKI.
+pub fn packet_key(packet: &IPCMessage) -> Option<(u32, Vec<u8>)> {
+    let IPCMessage::FromRadio(fr) = packet else {
+        return None;
+    };
+    let Some(from_radio::PayloadVariant::Packet(pa)) = &fr.payload_variant else {
+        return None;
+    };
+    (pa.pki_encrypted && !pa.public_key.is_empty()).then(|| (pa.from, pa.public_key.clone()))
+}
+
+impl KeyPins {
+    /// Loads the pins kept in `path`, one `!nodeid base64-key` per line.  A file that doesn't
+    /// exist yet is fine; it's created when the first key is pinned.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let mut pins = HashMap::new();
+        if path.exists() {
+            let contents =
+                fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+            for (n, line) in contents.lines().enumerate() {
+                if line.trim().is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let parsed = line.split_once(' ').and_then(|(id, key)| {
+                    let id = u32::from_str_radix(id.trim_start_matches('!'), 16).ok()?;
+                    Some((id, BASE64_STANDARD.decode(key.trim()).ok()?))
+                });
+                match parsed {
+                    Some((id, key)) => {
+                        pins.insert(id, key);
+                    }
+                    None => warn!("Skipping line {} of {}: {line}", n + 1, path.display()),
+                }
+            }
+        }
+        Ok(KeyPins {
+            pins,
+            path: Some(path),
+        })
+    }
+
+    pub fn pinned(&self, node: u32) -> Option<&[u8]> {
+        self.pins.get(&node).map(Vec::as_slice)
+    }
+
+    /// Checks a key a node presented against its pin, pinning it if it's the first we've seen.
+    pub fn check(&mut self, node: u32, key: &[u8]) -> KeyCheck {
+        match self.pins.get(&node) {
+            Some(pinned) if pinned == key => KeyCheck::Known,
+            Some(pinned) => KeyCheck::Changed {
+                pinned: pinned.clone(),
+            },
+            None => {
+                self.pins.insert(node, key.to_vec());
+                self.save();
+                KeyCheck::First
+            }
+        }
+    }
+
+    /// Replaces a node's pin with the key it's presenting now.
+    pub fn accept(&mut self, node: u32, key: &[u8]) {
+        self.pins.insert(node, key.to_vec());
+        self.save();
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let contents: String = self
+            .pins
+            .iter()
+            .sorted_by_key(|(id, _)| **id)
+            .map(|(id, key)| format!("!{id:08x} {}\n", BASE64_STANDARD.encode(key)))
+            .collect();
+        if let Err(e) = fs::write(path, contents) {
+            error!("Unable to save pinned keys to {}: {e}", path.display());
+        }
+    }
+}
@@ -1,9 +1,12 @@
 pub(crate) mod about;
 mod channels;
+mod config_fields;
 pub(crate) mod device_config;
 pub(crate) mod messages;
 pub(crate) mod modules_config;
 pub(crate) mod nodes;
+pub(crate) mod range_test;
+pub(crate) mod waypoints;
 
 pub use about::AboutTab;
 pub use channels::ChannelsTab;
@@ -11,3 +14,5 @@ pub use device_config::ConfigTab;
 pub use messages::MessagesTab;
 pub use modules_config::ModulesConfigTab;
 pub use nodes::NodesTab;
+pub use range_test::RangeTestTab;
+pub use waypoints::WaypointsTab;
@@ -1,4 +1,6 @@
+use crate::admin::{self, AdminTarget};
 use crate::app::Mode;
+use crate::mouse;
 use crate::theme::THEME;
 use crate::{DEVICE_CONFIG, PAGE_SIZE};
 use meshtastic::protobufs::Channel;
@@ -16,17 +18,28 @@ pub struct ChannelsTab {
     row_index: usize,
     page_size: u16,
     table_contents: Vec<Channel>,
+    target: AdminTarget,
 }
 
 impl ChannelsTab {
     pub async fn run(&mut self) {
         self.page_size = *PAGE_SIZE.read().await;
 
-        // get channel list and release lock asap
-        {
-            let dc = DEVICE_CONFIG.read().await;
-            if let Some(config) = dc.clone() {
-                self.table_contents = config.channels.values().cloned().collect();
+        self.target = admin::target().await;
+        match self.target {
+            AdminTarget::Local => {
+                // get channel list and release lock asap
+                let dc = DEVICE_CONFIG.read().await;
+                if let Some(config) = dc.clone() {
+                    self.table_contents = config.channels.values().cloned().collect();
+                }
+            }
+            AdminTarget::Remote(node_id) => {
+                admin::ensure_channels(node_id).await;
+                self.table_contents = admin::session(node_id)
+                    .await
+                    .map(|s| s.config.channels.into_values().collect())
+                    .unwrap_or_default();
             }
         }
         self.table_contents.sort_by(|a, b| a.index.cmp(&b.index));
@@ -42,6 +55,10 @@ impl ChannelsTab {
     pub fn next_row(&mut self) {
         self.row_index = self.row_index.saturating_add(1);
     }
+    /// Jumps to a row, or the last one if `index` is past the end.
+    pub fn select_row(&mut self, index: usize) {
+        self.row_index = index.min(self.table_contents.len().saturating_sub(1));
+    }
     pub async fn function_key(&mut self, _num: u8) {
         {}
     }
@@ -70,11 +87,13 @@ impl Widget for ChannelsTab {
             })
             .collect();
 
+        let table_state = TableState::default().with_selected(Some(self.row_index));
+        mouse::table(area, true, 0, &table_state, rows.len());
         Widget::render(
             Table::new(rows, constraints).block(
                 Block::new()
                     .borders(Borders::ALL)
-                    .title("About meshtui")
+                    .title(format!("Channels ({})", self.target))
                     .title_alignment(Alignment::Center)
                     .border_set(symbols::border::DOUBLE)
                     .style(THEME.middle),
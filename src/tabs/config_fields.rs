@@ -0,0 +1,182 @@
+use crate::admin::{AdminTarget, RemoteSession};
+use crate::mouse;
+use crate::theme::THEME;
+use anyhow::{anyhow, bail, Result};
+use ratatui::{prelude::*, widgets::*};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Number, Value};
+
+/// One leaf of a config struct, addressed by its dotted (camelCase) path.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigField {
+    pub path: String,
+    pub value: Value,
+    pub kind: FieldKind,
+}
+
+/// How a field may be shown and edited, for the fields a page builds by hand.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FieldKind {
+    #[default]
+    Plain,
+    ReadOnly,
+    /// Editable, but never shown or put in the editor, like a private key.
+    Secret,
+}
+
+impl ConfigField {
+    /// Lists, byte strings and unset sub-messages are shown but can't be edited in place.
+    pub fn editable(&self) -> bool {
+        self.kind != FieldKind::ReadOnly
+            && matches!(
+                self.value,
+                Value::Bool(_) | Value::Number(_) | Value::String(_)
+            )
+    }
+
+    pub fn display(&self) -> String {
+        match &self.value {
+            _ if self.kind == FieldKind::Secret => "(hidden)".to_string(),
+            Value::Null => "(unset)".to_string(),
+            Value::String(s) => s.clone(),
+            v => v.to_string(),
+        }
+    }
+
+    /// What the editor starts out holding when the field is edited.
+    pub fn initial_input(&self) -> String {
+        match self.kind {
+            FieldKind::Secret => String::new(),
+            _ => self.display(),
+        }
+    }
+}
+
+pub fn fields<T: Serialize>(section: &T) -> Vec<ConfigField> {
+    let mut out = vec![];
+    if let Ok(value) = serde_json::to_value(section) {
+        flatten("", value, &mut out);
+    }
+    out
+}
+
+fn flatten(prefix: &str, value: Value, out: &mut Vec<ConfigField>) {
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map {
+                let path = match prefix {
+                    "" => key,
+                    p => format!("{p}.{key}"),
+                };
+                flatten(&path, v, out);
+            }
+        }
+        value => out.push(ConfigField {
+            path: prefix.to_string(),
+            value,
+            ..Default::default()
+        }),
+    }
+}
+
+/// Returns a copy of `section` with the field at `path` replaced by `input`, which is parsed as
+/// whatever type the field already holds.
+pub fn with_field<T: Serialize + DeserializeOwned>(
+    section: &T,
+    path: &str,
+    input: &str,
+) -> Result<T> {
+    let mut root = serde_json::to_value(section)?;
+    let pointer = format!("/{}", path.replace('.', "/"));
+    let field = root
+        .pointer_mut(&pointer)
+        .ok_or_else(|| anyhow!("no field named {path}"))?;
+    let input = input.trim();
+    *field = match field {
+        Value::Bool(_) => Value::Bool(input.parse()?),
+        Value::String(_) => Value::String(input.to_string()),
+        Value::Number(n) if n.is_f64() => Value::Number(
+            Number::from_f64(input.parse()?).ok_or_else(|| anyhow!("{input} isn't a number"))?,
+        ),
+        Value::Number(_) => match input.parse::<i64>() {
+            Ok(i) => Value::from(i),
+            Err(_) => Value::from(input.parse::<u64>()?),
+        },
+        _ => bail!("{path} can't be edited here"),
+    };
+    Ok(serde_json::from_value(root)?)
+}
+
+/// The block title for a config page, making it obvious when we're looking at another node.
+pub fn target_title(section: String, target: AdminTarget) -> Line<'static> {
+    match target {
+        AdminTarget::Local => Line::from(format!("{section} ({target})")),
+        AdminTarget::Remote(_) => Line::from(vec![
+            Span::raw(format!("{section} ")),
+            Span::styled(format!("[{target}]"), THEME.remote_target),
+        ]),
+    }
+}
+
+/// Draws the field table for a config page.  Remote targets get a footer with the changes we've
+/// sent them, or a placeholder while the section is still on its way.
+pub fn render_fields(
+    fields: &[ConfigField],
+    row_index: usize,
+    block: Block,
+    session: Option<(&RemoteSession, bool)>,
+    area: Rect,
+    buf: &mut Buffer,
+) {
+    if let Some((_, false)) = session {
+        Paragraph::new("Waiting for this section from the remote node... (F3 asks again)")
+            .block(block)
+            .render(area, buf);
+        return;
+    }
+    let inner = block.inner(area);
+    block.render(area, buf);
+    let [table_area, footer] = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(1), Constraint::Length(1)])
+        .areas(inner);
+
+    let rows = fields.iter().map(|f| {
+        let row = Row::new(vec![f.path.clone(), f.display()]);
+        match f.editable() {
+            true => row,
+            false => row.style(THEME.message_header),
+        }
+    });
+    let mut table_state = TableState::default().with_selected(Some(row_index));
+    mouse::table(table_area, false, 0, &table_state, fields.len());
+    StatefulWidget::render(
+        Table::new(
+            rows,
+            [Constraint::Percentage(40), Constraint::Percentage(60)],
+        )
+        .highlight_style(THEME.tabs_selected),
+        table_area,
+        buf,
+        &mut table_state,
+    );
+
+    let footer_text = match session {
+        Some((s, _)) => match s.changes.last() {
+            Some(last) => format!(
+                "{} change(s) sent to this node, last: {last}",
+                s.changes.len()
+            ),
+            None => "No changes sent to this node yet".to_string(),
+        },
+        None => "Enter edits the selected field".to_string(),
+    };
+    let footer_style = match session {
+        Some(_) => THEME.remote_target,
+        None => THEME.middle,
+    };
+    Paragraph::new(footer_text)
+        .style(footer_style)
+        .render(footer, buf);
+}
@@ -1,14 +1,39 @@
+use crate::admin::{self, AdminTarget, ConfigChange, ConfigSection, RemoteSession};
 use crate::app::{DeviceConfiguration, Mode};
+use crate::mouse::{self, Target};
+use crate::tabs::config_fields::{self, ConfigField, FieldKind};
 use crate::theme::THEME;
+use crate::util::get_secs;
 use crate::DEVICE_CONFIG;
+use anyhow::{bail, Result};
+use meshtastic::protobufs::admin_message::ConfigType;
+use meshtastic::protobufs::config::PayloadVariant;
 use ratatui::{prelude::*, widgets::*};
+use security::SecurityChange;
+use serde_json::Value;
 use strum::{Display, EnumIter, FromRepr, IntoEnumIterator};
 
+mod security;
+
+/// How long a first press of the reboot key stays armed waiting for the confirming second press.
+pub(crate) const REBOOT_CONFIRM_SECS: u64 = 5;
+/// How long a risky security change waits for F5 before it's dropped.
+const SECURITY_CONFIRM_SECS: u64 = 15;
+
 #[derive(Debug, Clone, Default)]
 pub struct ConfigTab {
     row_index: usize,
     pub device_config: DeviceConfiguration,
     tab: InnerConfigTabs,
+    pub my_node_id: u32,
+    target: AdminTarget,
+    session: Option<RemoteSession>,
+    fields: Vec<ConfigField>,
+    reboot_armed_at: u64,
+    /// A security change that could lock us out, waiting on F5, and when it was made.
+    pending: Option<(SecurityChange, u64)>,
+    /// Whether the input popup is asking for the node id that confirms `pending`.
+    confirming: bool,
 }
 
 #[derive(Debug, Clone, Copy, Default, Display, EnumIter, FromRepr, PartialEq, Eq)]
@@ -21,6 +46,9 @@ pub enum InnerConfigTabs {
     Network,
     Position,
     Power,
+    Security,
+    DeviceUi,
+    SessionKey,
 }
 
 impl InnerConfigTabs {
@@ -38,14 +66,265 @@ impl InnerConfigTabs {
         let tab = self;
         format!(" {tab} ")
     }
+    fn section(self) -> ConfigSection {
+        ConfigSection::Config(match self {
+            InnerConfigTabs::Device => ConfigType::DeviceConfig,
+            InnerConfigTabs::Bluetooth => ConfigType::BluetoothConfig,
+            InnerConfigTabs::Display => ConfigType::DisplayConfig,
+            InnerConfigTabs::LoRa => ConfigType::LoraConfig,
+            InnerConfigTabs::Network => ConfigType::NetworkConfig,
+            InnerConfigTabs::Position => ConfigType::PositionConfig,
+            InnerConfigTabs::Power => ConfigType::PowerConfig,
+            InnerConfigTabs::Security => ConfigType::SecurityConfig,
+            InnerConfigTabs::DeviceUi => ConfigType::DeviceuiConfig,
+            InnerConfigTabs::SessionKey => ConfigType::SessionkeyConfig,
+        })
+    }
 }
 
 impl ConfigTab {
     pub async fn run(&mut self) {
-        let dc = DEVICE_CONFIG.read().await;
-        if let Some(config) = dc.clone() {
-            self.device_config = config.clone();
+        self.target = admin::target().await;
+        match self.target {
+            AdminTarget::Local => {
+                self.session = None;
+                let dc = DEVICE_CONFIG.read().await;
+                if let Some(config) = dc.clone() {
+                    self.device_config = config.clone();
+                }
+            }
+            AdminTarget::Remote(node_id) => {
+                admin::ensure_section(node_id, self.tab.section()).await;
+                self.session = admin::session(node_id).await;
+                if let Some(session) = &self.session {
+                    self.device_config = session.config.clone();
+                }
+            }
+        }
+        self.fields = self.section_fields();
+        self.row_index = self.row_index.min(self.fields.len().saturating_sub(1));
+    }
+
+    fn section_fields(&self) -> Vec<ConfigField> {
+        let dc = &self.device_config;
+        match self.tab {
+            InnerConfigTabs::Device => config_fields::fields(&dc.device),
+            InnerConfigTabs::Bluetooth => config_fields::fields(&dc.bluetooth),
+            InnerConfigTabs::Display => config_fields::fields(&dc.display),
+            InnerConfigTabs::LoRa => config_fields::fields(&dc.lora),
+            InnerConfigTabs::Network => config_fields::fields(&dc.network),
+            InnerConfigTabs::Position => config_fields::fields(&dc.position),
+            InnerConfigTabs::Power => config_fields::fields(&dc.power),
+            InnerConfigTabs::Security => security::fields(&dc.security),
+            InnerConfigTabs::DeviceUi => config_fields::fields(&dc.device_ui),
+            InnerConfigTabs::SessionKey => self.session_key_fields(),
+        }
+    }
+
+    /// The session key config has no fields; the page shows the passkey we hold instead.
+    fn session_key_fields(&self) -> Vec<ConfigField> {
+        let status = match &self.session {
+            None => "not needed for the local radio".to_string(),
+            Some(session) => match session.passkey_expires_in() {
+                Some(secs) => format!("held, good for another {secs}s"),
+                None => "none or expired; F3 asks for a new one".to_string(),
+            },
+        };
+        let mut fields = vec![ConfigField {
+            path: "sessionPasskey".to_string(),
+            value: Value::String(status),
+            kind: FieldKind::ReadOnly,
+        }];
+        if let Some(session) = &self.session {
+            fields.push(ConfigField {
+                path: "changesSent".to_string(),
+                value: Value::from(session.changes.len()),
+                kind: FieldKind::ReadOnly,
+            });
+        }
+        fields
+    }
+
+    fn updated_section(&self, path: &str, input: &str) -> Result<PayloadVariant> {
+        use config_fields::with_field;
+        let dc = &self.device_config;
+        Ok(match self.tab {
+            InnerConfigTabs::Device => PayloadVariant::Device(with_field(&dc.device, path, input)?),
+            InnerConfigTabs::Bluetooth => {
+                PayloadVariant::Bluetooth(with_field(&dc.bluetooth, path, input)?)
+            }
+            InnerConfigTabs::Display => {
+                PayloadVariant::Display(with_field(&dc.display, path, input)?)
+            }
+            InnerConfigTabs::LoRa => PayloadVariant::Lora(with_field(&dc.lora, path, input)?),
+            InnerConfigTabs::Network => {
+                PayloadVariant::Network(with_field(&dc.network, path, input)?)
+            }
+            InnerConfigTabs::Position => {
+                PayloadVariant::Position(with_field(&dc.position, path, input)?)
+            }
+            InnerConfigTabs::Power => PayloadVariant::Power(with_field(&dc.power, path, input)?),
+            InnerConfigTabs::DeviceUi => {
+                PayloadVariant::DeviceUi(with_field(&dc.device_ui, path, input)?)
+            }
+            InnerConfigTabs::Security | InnerConfigTabs::SessionKey => {
+                bail!("{} isn't edited field by field", self.tab)
+            }
+        })
+    }
+
+    pub fn selected_field(&self) -> Option<&ConfigField> {
+        self.fields.get(self.row_index)
+    }
+
+    pub fn edit_title(&self) -> String {
+        if self.confirming {
+            let risk = self.pending.as_ref().and_then(|(c, _)| c.risk.as_deref());
+            return format!(
+                "This {}.  Type {} to go ahead",
+                risk.unwrap_or("could lock you out"),
+                self.confirm_word()
+            );
         }
+        let path = self.selected_field().map(|f| f.path.as_str()).unwrap_or("");
+        format!("Set {} {path} on {}", self.tab, self.target)
+    }
+
+    pub async fn apply_edit(&mut self, input: &str) {
+        if self.confirming {
+            self.finish_confirmation(input).await;
+            return;
+        }
+        let Some(field) = self.selected_field().cloned() else {
+            return;
+        };
+        if self.tab == InnerConfigTabs::Security {
+            self.edit_security(&field.path, input).await;
+            return;
+        }
+        let change = match self.updated_section(&field.path, input) {
+            Ok(c) => ConfigChange::Config(c),
+            Err(e) => {
+                error!("Couldn't set {} to {input}: {e}", field.path);
+                return;
+            }
+        };
+        let description = format!("{} {} = {}", self.tab, field.path, input.trim());
+        if let Err(e) = admin::apply_change(self.target, self.my_node_id, change, description).await
+        {
+            error!("Unable to change {} on {}: {e}", field.path, self.target);
+        }
+    }
+
+    async fn edit_security(&mut self, path: &str, input: &str) {
+        let our_key = DEVICE_CONFIG
+            .read()
+            .await
+            .as_ref()
+            .map(|dc| dc.security.public_key.clone())
+            .unwrap_or_default();
+        let change = match security::change(
+            &self.device_config.security,
+            self.target,
+            &our_key,
+            path,
+            input,
+        ) {
+            Ok(change) => change,
+            Err(e) => {
+                error!("Couldn't set {path}: {e:#}");
+                return;
+            }
+        };
+        match &change.risk {
+            Some(risk) => {
+                warn!(
+                    "CAREFUL: this {risk}.  If you're sure, press F5 within \
+                     {SECURITY_CONFIRM_SECS} seconds and type {}.",
+                    self.confirm_word()
+                );
+                self.pending = Some((change, get_secs()));
+            }
+            None => self.apply_security(change).await,
+        }
+    }
+
+    async fn apply_security(&mut self, change: SecurityChange) {
+        let variant = PayloadVariant::Security(change.config);
+        let section = self.tab.section();
+        if let Err(e) = admin::apply_change(
+            self.target,
+            self.my_node_id,
+            ConfigChange::Config(variant),
+            change.description,
+        )
+        .await
+        {
+            error!(
+                "Unable to change the security config on {}: {e}",
+                self.target
+            );
+            return;
+        }
+        if change.regenerates {
+            match self.target {
+                AdminTarget::Local => admin::request_local_section(self.my_node_id, section).await,
+                AdminTarget::Remote(node_id) => admin::request_section(node_id, section).await,
+            }
+        }
+    }
+
+    /// What has to be typed to confirm a risky change: the id of the node it's going to.
+    fn confirm_word(&self) -> String {
+        match self.target {
+            AdminTarget::Local => format!("!{:08x}", self.my_node_id),
+            AdminTarget::Remote(node_id) => format!("!{node_id:08x}"),
+        }
+    }
+
+    /// F5 on a pending change asks for the node id before it goes out, so a stray key press
+    /// can't lock us out.  Returns whether to open the input popup for it.
+    pub fn begin_confirmation(&mut self) -> bool {
+        match (self.pending_risk().is_some(), self.pending.is_some()) {
+            (true, _) => self.confirming = true,
+            (false, true) => {
+                self.pending = None;
+                warn!("That security change waited too long; make it again");
+            }
+            (false, false) => {}
+        }
+        self.confirming
+    }
+
+    async fn finish_confirmation(&mut self, input: &str) {
+        self.confirming = false;
+        let Some((change, _)) = self.pending.take() else {
+            return;
+        };
+        match input.trim().eq_ignore_ascii_case(&self.confirm_word()) {
+            true => self.apply_security(change).await,
+            false => warn!(
+                "That wasn't {}; the security change was dropped",
+                self.confirm_word()
+            ),
+        }
+    }
+
+    /// Esc on the confirmation popup drops the change it was for.
+    pub fn cancel_confirmation(&mut self) {
+        if self.confirming {
+            self.confirming = false;
+            self.pending = None;
+            info!("Dropped the pending security change");
+        }
+    }
+
+    /// The pending security change, if it's still waiting to be confirmed.
+    fn pending_risk(&self) -> Option<&str> {
+        self.pending
+            .as_ref()
+            .filter(|(_, at)| get_secs().saturating_sub(*at) <= SECURITY_CONFIRM_SECS)
+            .and_then(|(change, _)| change.risk.as_deref())
     }
 
     pub fn escape(&mut self) -> Mode {
@@ -57,12 +336,48 @@ impl ConfigTab {
     }
 
     pub fn next_row(&mut self) {
-        self.row_index = self.row_index.saturating_add(1);
+        self.row_index = self
+            .row_index
+            .saturating_add(1)
+            .min(self.fields.len().saturating_sub(1));
+    }
+    /// Jumps to a row, or the last one if `index` is past the end.
+    pub fn select_row(&mut self, index: usize) {
+        self.row_index = index.min(self.fields.len().saturating_sub(1));
     }
-    pub fn function_key(&mut self, _num: u8) {
-        {}
+    pub async fn function_key(&mut self, num: u8) {
+        match num {
+            2 => {
+                admin::cycle_target().await;
+            }
+            3 => {
+                if let AdminTarget::Remote(node_id) = self.target {
+                    admin::request_section(node_id, self.tab.section()).await;
+                }
+            }
+            4 => {
+                if get_secs().saturating_sub(self.reboot_armed_at) > REBOOT_CONFIRM_SECS {
+                    self.reboot_armed_at = get_secs();
+                    warn!(
+                        "Press F4 again within {REBOOT_CONFIRM_SECS} seconds to reboot the {}",
+                        self.target
+                    );
+                    return;
+                }
+                self.reboot_armed_at = 0;
+                if let Err(e) = admin::reboot(self.target, self.my_node_id).await {
+                    error!("Unable to reboot the {}: {e}", self.target);
+                }
+            }
+            _ => {}
+        }
     }
     pub fn render_tabs(&self, area: Rect, buf: &mut Buffer) {
+        mouse::tabs(
+            area,
+            InnerConfigTabs::iter().map(InnerConfigTabs::title),
+            Target::SubTab,
+        );
         let titles = InnerConfigTabs::iter().map(InnerConfigTabs::title);
         Tabs::new(titles)
             .style(THEME.tabs)
@@ -74,9 +389,18 @@ impl ConfigTab {
     }
     pub fn left(&mut self) {
         self.tab = self.tab.prev();
+        self.row_index = 0;
     }
     pub fn right(&mut self) {
         self.tab = self.tab.next();
+        self.row_index = 0;
+    }
+    /// Switches to a sub-tab by its position in the bar.
+    pub fn select_tab(&mut self, index: usize) {
+        if let Some(tab) = InnerConfigTabs::from_repr(index) {
+            self.tab = tab;
+            self.row_index = 0;
+        }
     }
 }
 
@@ -96,18 +420,41 @@ impl Widget for ConfigTab {
             .margin(1)
             .areas(area);
 
-        let device_block = default_inner_block.clone().title("Configuration");
+        let device_block = default_inner_block
+            .clone()
+            .title(config_fields::target_title(
+                self.tab.to_string(),
+                self.target,
+            ));
 
         self.render_tabs(bar, buf);
-        let pg = match self.tab {
-            InnerConfigTabs::Device => format!("{:#?}", self.device_config.device),
-            InnerConfigTabs::Bluetooth => format!("{:#?}", self.device_config.bluetooth),
-            InnerConfigTabs::Display => format!("{:#?}", self.device_config.display),
-            InnerConfigTabs::LoRa => format!("{:#?}", self.device_config.lora),
-            InnerConfigTabs::Network => format!("{:#?}", self.device_config.network),
-            InnerConfigTabs::Position => format!("{:#?}", self.device_config.position),
-            InnerConfigTabs::Power => format!("{:#?}", self.device_config.power),
+        let field = match self.pending_risk() {
+            Some(risk) => {
+                let [warning, rest] = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Length(1), Constraint::Min(0)])
+                    .areas(field);
+                Paragraph::new(format!(
+                    " Pending: this {risk}.  F5 and the node id {} confirm it. ",
+                    self.confirm_word()
+                ))
+                .style(THEME.key_warning)
+                .render(warning, buf);
+                rest
+            }
+            None => field,
         };
-        Paragraph::new(pg).block(device_block).render(field, buf);
+        let session = self
+            .session
+            .as_ref()
+            .map(|s| (s, s.has(self.tab.section())));
+        config_fields::render_fields(
+            &self.fields,
+            self.row_index,
+            device_block,
+            session,
+            field,
+            buf,
+        );
     }
 }
@@ -0,0 +1,186 @@
+use crate::admin::AdminTarget;
+use crate::pki;
+use crate::tabs::config_fields::{self, ConfigField, FieldKind};
+use anyhow::{bail, Context, Result};
+use base64::prelude::*;
+use meshtastic::protobufs::config::SecurityConfig;
+use serde_json::Value;
+
+/// The firmware keeps up to this many admin keys.
+const ADMIN_KEY_SLOTS: usize = 3;
+const PUBLIC_KEY_PATH: &str = "publicKey";
+const PRIVATE_KEY_PATH: &str = "privateKey";
+const ADMIN_KEY_PREFIX: &str = "adminKey.";
+
+/// A change to the Security page, and what could go wrong if it's a change that can lock us out
+/// of the node.  Those wait for the user to confirm them.
+#[derive(Debug, Clone)]
+pub struct SecurityChange {
+    pub config: SecurityConfig,
+    pub description: String,
+    pub risk: Option<String>,
+    /// Whether the node will come up with new keys we should ask it for.
+    pub regenerates: bool,
+}
+
+/// The Security page: keys as base64, one row per admin key slot, then the plain flags.
+pub fn fields(security: &SecurityConfig) -> Vec<ConfigField> {
+    let mut fields = vec![
+        ConfigField {
+            path: PUBLIC_KEY_PATH.to_string(),
+            value: Value::String(BASE64_STANDARD.encode(&security.public_key)),
+            kind: FieldKind::ReadOnly,
+        },
+        ConfigField {
+            path: PRIVATE_KEY_PATH.to_string(),
+            value: Value::String(String::new()),
+            kind: FieldKind::Secret,
+        },
+    ];
+    for slot in 0..ADMIN_KEY_SLOTS {
+        let key = security
+            .admin_key
+            .get(slot)
+            .map(|k| BASE64_STANDARD.encode(k));
+        fields.push(ConfigField {
+            path: format!("{ADMIN_KEY_PREFIX}{}", slot + 1),
+            value: Value::String(key.unwrap_or_default()),
+            ..Default::default()
+        });
+    }
+    // the key fields serialize as byte lists, which we've already shown above
+    fields.extend(
+        config_fields::fields(security)
+            .into_iter()
+            .filter(|f| !f.value.is_array()),
+    );
+    fields
+}
+
+fn decode_key(input: &str) -> Result<Vec<u8>> {
+    let key = BASE64_STANDARD
+        .decode(input)
+        .context("keys are entered as base64")?;
+    if key.len() != 32 {
+        bail!("keys are 32 bytes, that's {}", key.len());
+    }
+    Ok(key)
+}
+
+/// Works out the new security config for an edit of `path`.  `our_key` is our own radio's
+/// public key, so we can tell when a change would shut us out of a remote node.
+pub fn change(
+    current: &SecurityConfig,
+    target: AdminTarget,
+    our_key: &[u8],
+    path: &str,
+    input: &str,
+) -> Result<SecurityChange> {
+    let input = input.trim();
+    let mut config = current.clone();
+    let mut regenerates = false;
+    let (description, mut risk) = if path == PRIVATE_KEY_PATH {
+        config.public_key.clear();
+        let description = match input {
+            "" => {
+                config.private_key.clear();
+                regenerates = true;
+                "Security keys regenerated".to_string()
+            }
+            key => {
+                config.private_key = decode_key(key)?;
+                "Security privateKey replaced".to_string()
+            }
+        };
+        let mut risk = format!(
+            "gives the {target} a new key pair; nodes that pinned its old public key will warn \
+             about the change, and direct messages in flight will fail"
+        );
+        if let AdminTarget::Remote(_) = target {
+            risk.push_str(", as will admin messages until our radio hears its new key");
+        }
+        (description, Some(risk))
+    } else if let Some(slot) = path.strip_prefix(ADMIN_KEY_PREFIX) {
+        let slot: usize = slot.parse()?;
+        let removed = current.admin_key.get(slot - 1).cloned();
+        let description = match input {
+            "" => {
+                if slot <= config.admin_key.len() {
+                    config.admin_key.remove(slot - 1);
+                }
+                format!("Security {path} removed")
+            }
+            key => {
+                let key = decode_key(key)?;
+                let description = format!("Security {path} = {}", pki::fingerprint(&key));
+                match config.admin_key.get_mut(slot - 1) {
+                    Some(existing) => *existing = key,
+                    None => config.admin_key.push(key),
+                }
+                description
+            }
+        };
+        if config.is_managed && config.admin_key.is_empty() {
+            bail!(
+                "the {target} is managed, so removing its last admin key would leave nothing \
+                 able to configure it; turn off isManaged first"
+            );
+        }
+        let risk = match removed {
+            Some(old) if matches!(target, AdminTarget::Remote(_)) && old == our_key => Some(
+                format!("removes our own radio's key from the {target}'s admin keys, so we may not be able to administer it again"),
+            ),
+            Some(_) if config.is_managed => Some(format!(
+                "changes the admin keys of the managed {target}; only the keys left will be able \
+                 to configure it"
+            )),
+            _ => None,
+        };
+        (description, risk)
+    } else {
+        config = config_fields::with_field(current, path, input)?;
+        let risk = match path {
+            "isManaged" if config.is_managed && !current.is_managed => {
+                if config.admin_key.is_empty() {
+                    bail!(
+                        "add an admin key before turning on managed mode, or nothing will be \
+                         able to configure the {target}"
+                    );
+                }
+                Some(format!(
+                    "puts the {target} in managed mode: it will refuse config changes from its \
+                     own clients and only take them from its admin keys"
+                ))
+            }
+            "serialEnabled" if !config.serial_enabled => Some(format!(
+                "turns off the {target}'s serial console; if we're connected to it over serial, \
+                 we'll lose the connection"
+            )),
+            "adminChannelEnabled"
+                if !config.admin_channel_enabled && matches!(target, AdminTarget::Remote(_)) =>
+            {
+                Some(format!(
+                    "stops the {target} taking admin messages over the legacy admin channel; only \
+                     admin keys will work"
+                ))
+            }
+            _ => None,
+        };
+        (format!("Security {path} = {input}"), risk)
+    };
+
+    // nodes don't send their private key over the mesh, and some firmware makes a new key pair
+    // when it's handed a security config without one
+    if config.private_key.is_empty() && !regenerates && risk.is_none() {
+        risk = Some(format!(
+            "sends a security config without the {target}'s private key, which we don't have; \
+             some firmware will generate a new key pair in response"
+        ));
+    }
+    Ok(SecurityChange {
+        config,
+        description,
+        risk,
+        regenerates,
+    })
+}
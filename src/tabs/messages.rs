@@ -1,8 +1,16 @@
+pub(crate) mod store_forward;
+
 use crate::app::Mode;
-use crate::packet_handler::MessageEnvelope;
+use crate::composer::SendOptions;
+use crate::ipc::IPCMessage;
+use crate::packet_handler::{MessageEnvelope, MessageKind};
 use crate::theme::THEME;
-use crate::{consts, PAGE_SIZE};
-use meshtastic::protobufs::User;
+use crate::{consts, mouse, util, PAGE_SIZE};
+use anyhow::Result;
+use meshtastic::packet::PacketDestination;
+use meshtastic::protobufs::{NodeInfo, StoreAndForward, User};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use store_forward::StoreForwardRouter;
 use circular_buffer::CircularBuffer;
 use itertools::Itertools;
 
@@ -16,12 +24,260 @@ pub struct MessagesTab {
     table_state: TableState,
     editing: bool,
     pub page_size: u16,
+    routers: BTreeMap<u32, StoreForwardRouter>,
+    selected_router: usize,
+    prompt: Option<Prompt>,
+}
+
+/// What the input popup is collecting for this tab, when it isn't a plain new message.
+#[derive(Debug, Clone)]
+enum Prompt {
+    History,
+    Reply(MessageEnvelope),
+    React(MessageEnvelope),
+}
+
+/// Replies nested deeper than this are shown at this depth.
+const MAX_THREAD_DEPTH: usize = 4;
+
+/// A message as laid out in the table: replies follow their parent, indented, and reactions
+/// are folded into counters on the message they react to.
+#[derive(Debug, Clone)]
+struct MessageRow {
+    envelope: MessageEnvelope,
+    depth: usize,
+    reactions: Vec<(String, usize)>,
+}
+
+/// A short name for whoever sent a message.
+fn sender_name(envelope: &MessageEnvelope) -> String {
+    match envelope.source.as_ref().and_then(|s| s.user.clone()) {
+        Some(user) if !user.short_name.is_empty() => user.short_name,
+        _ => format!(
+            "!{:x}",
+            envelope.source.as_ref().map(|s| s.num).unwrap_or(0)
+        ),
+    }
 }
 
 impl MessagesTab {
     pub async fn run(&mut self) {
         self.page_size = *PAGE_SIZE.read().await;
     }
+    /// Adds a message unless we already have it, which happens when a Store & Forward router
+    /// replays something we heard live.
+    pub fn push(&mut self, envelope: MessageEnvelope) {
+        if envelope.id != 0 && self.messages.iter().any(|m| m.id == envelope.id) {
+            debug!("Dropping duplicate of message {}", envelope.id);
+            return;
+        }
+        self.messages.push_back(envelope);
+    }
+
+    pub fn observe_store_forward(&mut self, from: u32, name: String, sf: &StoreAndForward) {
+        let router = self
+            .routers
+            .entry(from)
+            .or_insert_with(|| StoreForwardRouter::new(from, name.clone()));
+        router.name = name;
+        if !router.observe(sf) {
+            self.routers.remove(&from);
+        }
+    }
+
+    fn router(&self) -> Option<&StoreForwardRouter> {
+        self.routers.values().nth(self.selected_router)
+    }
+
+    /// Starts asking the selected router for history.  Returns the window to offer, in minutes.
+    pub fn begin_history_request(&mut self) -> Option<String> {
+        let Some(router) = self.router() else {
+            warn!("No Store & Forward routers heard yet");
+            return None;
+        };
+        let minutes = router
+            .return_window()
+            .unwrap_or(store_forward::DEFAULT_HISTORY_MINUTES);
+        self.prompt = Some(Prompt::History);
+        Some(minutes.to_string())
+    }
+
+    /// `r` replies to the selected message and `t` reacts to it.  Returns what to prefill the
+    /// input with.
+    pub fn char_key(&mut self, c: char) -> Option<String> {
+        let selected = self.selected_message()?;
+        if selected.id == 0 {
+            warn!("That message has no packet id, so it can't be replied or reacted to");
+            return None;
+        }
+        match c {
+            'r' => {
+                self.prompt = Some(Prompt::Reply(selected));
+                Some("".to_string())
+            }
+            't' => {
+                self.prompt = Some(Prompt::React(selected));
+                Some("👍".to_string())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn has_prompt(&self) -> bool {
+        self.prompt.is_some()
+    }
+
+    /// Whether the popup holds message text, as opposed to a history window or a reaction.
+    pub fn composing_text(&self) -> bool {
+        matches!(self.prompt, None | Some(Prompt::Reply(_)))
+    }
+
+    pub fn prompt_title(&self) -> String {
+        let quote = |e: &MessageEnvelope| {
+            format!(
+                "{}: {}",
+                sender_name(e),
+                e.message.chars().take(30).collect::<String>()
+            )
+        };
+        match &self.prompt {
+            Some(Prompt::History) => match self.router() {
+                Some(r) => format!("Minutes of history to ask {} for", r.name),
+                None => "Minutes of history".to_string(),
+            },
+            Some(Prompt::Reply(parent)) => format!("Reply to {}", quote(parent)),
+            Some(Prompt::React(parent)) => format!("React to {}", quote(parent)),
+            None => "Enter message".to_string(),
+        }
+    }
+
+    /// Acts on the input popup.  A reply goes out as `parts`, which is the input split to fit
+    /// if it had to be.  Replies and reactions we send are added to the list straight away, from
+    /// `me`, so they show up in their thread.
+    pub async fn finish_prompt(
+        &mut self,
+        input: &str,
+        parts: Vec<String>,
+        options: &SendOptions,
+        me: Option<NodeInfo>,
+    ) {
+        match self.prompt.take() {
+            Some(Prompt::History) => self.request_history(input).await,
+            Some(Prompt::Reply(parent)) => {
+                for part in parts.iter().map(|p| p.trim()).filter(|p| !p.is_empty()) {
+                    self.send_reply(&parent, part, false, options, me.clone())
+                        .await
+                }
+            }
+            Some(Prompt::React(parent)) if !input.trim().is_empty() => {
+                self.send_reply(&parent, input.trim(), true, options, me)
+                    .await
+            }
+            _ => {}
+        }
+    }
+
+    pub fn cancel_prompt(&mut self) {
+        self.prompt = None;
+    }
+
+    async fn request_history(&mut self, input: &str) {
+        let Some(router) = self.router().cloned() else {
+            return;
+        };
+        let minutes = match input.trim().parse::<u32>() {
+            Ok(m) if m > 0 => m,
+            _ => {
+                error!("{input} isn't a number of minutes");
+                return;
+            }
+        };
+        match store_forward::request_history(router.id, minutes).await {
+            Ok(()) => info!(
+                "Asked {} for the last {minutes} minute(s) of messages",
+                router.name
+            ),
+            Err(e) => error!("Unable to ask {} for history: {e}", router.name),
+        }
+    }
+
+    async fn send_reply(
+        &mut self,
+        parent: &MessageEnvelope,
+        text: &str,
+        emoji: bool,
+        options: &SendOptions,
+        me: Option<NodeInfo>,
+    ) {
+        match send_reply(parent, text, emoji, options).await {
+            Ok(mut envelope) => {
+                info!(
+                    "Sent {} to {}",
+                    if emoji { "reaction" } else { "reply" },
+                    sender_name(parent)
+                );
+                envelope.source = me;
+                if envelope.source.is_some() {
+                    self.push(envelope);
+                }
+            }
+            Err(e) => error!("Unable to send reply: {e}"),
+        }
+    }
+
+    /// Lays the messages out newest first, with each message's replies under it, oldest first.
+    fn rows(&self) -> Vec<MessageRow> {
+        let ids: HashSet<u32> = self
+            .messages
+            .iter()
+            .map(|m| m.id)
+            .filter(|id| *id != 0)
+            .collect();
+        let mut replies: HashMap<u32, Vec<&MessageEnvelope>> = HashMap::new();
+        let mut reactions: HashMap<u32, Vec<&str>> = HashMap::new();
+        let mut roots = vec![];
+        for m in self.messages.iter() {
+            match (ids.contains(&m.reply_id), m.emoji) {
+                (true, true) => reactions.entry(m.reply_id).or_default().push(&m.message),
+                (true, false) => replies.entry(m.reply_id).or_default().push(m),
+                (false, _) => roots.push(m),
+            }
+        }
+        roots.sort_by_key(|m| std::cmp::Reverse(m.timestamp));
+
+        let mut rows = vec![];
+        let mut stack: Vec<(&MessageEnvelope, usize)> =
+            roots.into_iter().rev().map(|m| (m, 0)).collect();
+        while let Some((m, depth)) = stack.pop() {
+            let counted = reactions
+                .get(&m.id)
+                .map(|r| {
+                    r.iter()
+                        .counts()
+                        .into_iter()
+                        .map(|(e, n)| (e.to_string(), n))
+                        .sorted_by_key(|(e, n)| (std::cmp::Reverse(*n), e.clone()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            rows.push(MessageRow {
+                envelope: m.clone(),
+                depth,
+                reactions: counted,
+            });
+            if let Some(children) = replies.get(&m.id) {
+                let children = children.iter().sorted_by_key(|c| c.timestamp).rev();
+                stack.extend(children.map(|c| (*c, (depth + 1).min(MAX_THREAD_DEPTH))));
+            }
+        }
+        rows
+    }
+
+    fn selected_message(&self) -> Option<MessageEnvelope> {
+        let i = self.table_state.selected()?;
+        self.rows().get(i).map(|r| r.envelope.clone())
+    }
+
     pub fn escape(&mut self) -> Mode {
         Mode::Exiting
     }
@@ -33,7 +289,7 @@ impl MessagesTab {
         let i = match self.table_state.selected() {
             Some(i) => {
                 if i == 0 {
-                    self.messages.len().saturating_sub(1)
+                    self.rows().len().saturating_sub(1)
                 } else {
                     i.saturating_sub(1)
                 }
@@ -59,7 +315,7 @@ impl MessagesTab {
     pub fn next_row(&mut self) {
         let i = match self.table_state.selected() {
             Some(i) => {
-                if i >= self.messages.len().saturating_sub(1) {
+                if i >= self.rows().len().saturating_sub(1) {
                     0
                 } else {
                     i.saturating_add(1)
@@ -69,11 +325,24 @@ impl MessagesTab {
         };
         self.table_state.select(Some(i));
     }
+    /// Jumps to a row, or the last one if `index` is past the end.
+    pub fn select_row(&mut self, index: usize) {
+        self.table_state
+            .select(Some(index.min(self.rows().len().saturating_sub(1))));
+    }
+    /// Moves one row up or down for the scroll wheel, stopping at the ends rather than wrapping.
+    pub fn scroll(&mut self, down: bool) {
+        match (self.table_state.selected(), down) {
+            (Some(i), true) => self.select_row(i.saturating_add(1)),
+            (Some(i), false) => self.select_row(i.saturating_sub(1)),
+            (None, _) => self.select_row(0),
+        }
+    }
     pub fn next_page(&mut self) {
         let i = match self.table_state.selected() {
             Some(i) => {
-                if i >= self.messages.len().saturating_sub(self.page_size as usize) {
-                    self.messages.len().saturating_sub(1)
+                if i >= self.rows().len().saturating_sub(self.page_size as usize) {
+                    self.rows().len().saturating_sub(1)
                 } else {
                     i.saturating_add(self.page_size as usize)
                 }
@@ -83,11 +352,102 @@ impl MessagesTab {
         debug!("i is {i}");
         self.table_state.select(Some(i));
     }
-    pub fn function_key(&mut self, _num: u8) {
-        {}
+    pub async fn function_key(&mut self, num: u8) {
+        match num {
+            3 if !self.routers.is_empty() => {
+                self.selected_router = (self.selected_router + 1) % self.routers.len();
+            }
+            4 => {
+                if let Some(router) = self.router().cloned() {
+                    match store_forward::request_stats(router.id).await {
+                        Ok(()) => info!("Asked {} for its statistics", router.name),
+                        Err(e) => error!("Unable to ask {} for statistics: {e}", router.name),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn render_routers(&self, area: Rect, buf: &mut Buffer) {
+        let lines = self
+            .routers
+            .values()
+            .enumerate()
+            .map(|(i, r)| match i == self.selected_router {
+                true => Line::styled(format!("> {}", r.summary()), THEME.tabs_selected),
+                false => Line::raw(format!("  {}", r.summary())),
+            })
+            .collect_vec();
+        Paragraph::new(lines)
+            .block(
+                Block::new()
+                    .borders(Borders::ALL)
+                    .title("Store & Forward routers (F2 history, F3 next router, F4 stats)")
+                    .title_alignment(Alignment::Center)
+                    .style(THEME.middle),
+            )
+            .render(area, buf);
     }
 }
 
+/// The message column: replies are indented under their parent and reactions are tallied after
+/// the message they react to.  A reaction to a message we never saw stands on its own.
+fn message_text(row: &MessageRow) -> String {
+    let indent = match row.depth {
+        0 => "".to_string(),
+        d => format!("{}↳ ", "  ".repeat(d - 1)),
+    };
+    let text = match row.envelope.emoji {
+        true => format!("reacted {}", row.envelope.message),
+        false => format!("{}{}", row.envelope.kind.marker(), row.envelope.message),
+    };
+    let reactions = row
+        .reactions
+        .iter()
+        .map(|(emoji, n)| format!("{emoji}{n}"))
+        .join(" ");
+    match reactions.is_empty() {
+        true => format!("{indent}{text}"),
+        false => format!("{indent}{text}  [{reactions}]"),
+    }
+}
+
+/// Sends `text` as a reply to `parent`, or as a reaction if `emoji` is set.  Direct messages are
+/// answered directly; anything else goes back to the channel it came from.  The hop limit,
+/// priority, ack and compression come from `options`, as for any other message.
+async fn send_reply(
+    parent: &MessageEnvelope,
+    text: &str,
+    emoji: bool,
+    options: &SendOptions,
+) -> Result<MessageEnvelope> {
+    let sender = parent.source.as_ref().map(|s| s.num).unwrap_or(0);
+    let destination = match parent.destination {
+        PacketDestination::Node(_) if sender != 0 => PacketDestination::Node(sender.into()),
+        _ => PacketDestination::Broadcast,
+    };
+    // the id is chosen here so our copy of the reply matches the packet that goes out
+    let envelope = MessageEnvelope {
+        timestamp: util::get_secs() as u32,
+        source: None,
+        destination,
+        channel: parent.channel,
+        message: text.to_string(),
+        rx_rssi: 0,
+        rx_snr: 0.0,
+        id: meshtastic::utils::generate_rand_id(),
+        via_store_forward: false,
+        reply_id: parent.id,
+        emoji,
+        kind: MessageKind::Text,
+        decrypted_with: None,
+        pki_encrypted: false,
+    };
+    util::send_to_radio(IPCMessage::SendMessage(envelope.clone(), options.clone())).await?;
+    Ok(envelope)
+}
+
 impl Widget for MessagesTab {
     fn render(mut self, area: Rect, buf: &mut Buffer) {
         // since this fn is operating on a copy of the messagestab struct, there
@@ -104,19 +464,34 @@ impl Widget for MessagesTab {
             }
         }
 
+        let area = match self.routers.is_empty() {
+            true => area,
+            false => {
+                let [routers, messages] = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([
+                        Constraint::Length(self.routers.len() as u16 + 2),
+                        Constraint::Min(0),
+                    ])
+                    .areas(area);
+                self.render_routers(routers, buf);
+                messages
+            }
+        };
+
         let message_table_constraints = vec![
             Constraint::Length(20),
             Constraint::Length(32),
             Constraint::Length(32),
+            Constraint::Length(14),
             Constraint::Min(50),
         ];
 
-        let mut message_list = self.messages.to_vec();
-        message_list.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
-        message_list.reverse();
-        let rows = message_list
-            .iter()
-            .map(|message| {
+        let rows = self
+            .rows()
+            .into_iter()
+            .map(|row| {
+                let message = &row.envelope;
                 let dt =
                     OffsetDateTime::from_unix_timestamp(message.clone().timestamp as i64).unwrap();
 
@@ -125,7 +500,19 @@ impl Widget for MessagesTab {
                     None => "".to_string(),
                 };
 
-                let destination_str = format!("{} (Ch. {})", channel_name, &message.channel);
+                let destination_str = match &message.decrypted_with {
+                    Some(key) => format!("{key} (decrypted locally)"),
+                    None if message.pki_encrypted => "🔒 Direct (PKI)".to_string(),
+                    None => format!("{} (Ch. {})", channel_name, &message.channel),
+                };
+                let rf_str = match (
+                    message.via_store_forward,
+                    message.rx_snr != 0.0 || message.rx_rssi != 0,
+                ) {
+                    (true, _) => "via S&F".to_string(),
+                    (false, true) => format!("{:.1}dB/{}", message.rx_snr, message.rx_rssi),
+                    (false, false) => "".to_string(),
+                };
 
                 Row::new(vec![
                     format!("{}", dt.format(consts::DATE_FORMAT).unwrap()),
@@ -138,7 +525,8 @@ impl Widget for MessagesTab {
                         .unwrap_or_else(User::default)
                         .long_name,
                     destination_str,
-                    message.clone().message,
+                    rf_str,
+                    message_text(&row),
                 ])
             })
             .collect_vec();
@@ -150,10 +538,11 @@ impl Widget for MessagesTab {
             .border_set(symbols::border::DOUBLE)
             .style(THEME.middle);
 
-        let header = Row::new(vec!["Time", "Source", "Destination", "Message"])
+        let header = Row::new(vec!["Time", "Source", "Destination", "SNR/RSSI", "Message"])
             .style(THEME.message_header)
             .bottom_margin(1);
 
+        mouse::table(area, true, 2, &self.table_state, rows.len());
         StatefulWidget::render(
             Table::new(rows, message_table_constraints)
                 .block(block)
@@ -0,0 +1,140 @@
+use crate::ipc::IPCMessage;
+use crate::tabs::nodes::requests::request_packet;
+use crate::util::{self, get_secs};
+use anyhow::Result;
+use meshtastic::protobufs::store_and_forward::{
+    Heartbeat, History, RequestResponse, Statistics, Variant,
+};
+use meshtastic::protobufs::to_radio::PayloadVariant::Packet;
+use meshtastic::protobufs::{PortNum, StoreAndForward, ToRadio};
+use meshtastic::Message;
+use pretty_duration::pretty_duration;
+use std::time::Duration;
+
+/// How many minutes of history we ask for when the router hasn't told us its limit.
+pub const DEFAULT_HISTORY_MINUTES: u32 = 120;
+
+/// A Store & Forward router we've heard from, and what it last told us about itself.
+#[derive(Debug, Clone, Default)]
+pub struct StoreForwardRouter {
+    pub id: u32,
+    pub name: String,
+    pub last_heard: u64,
+    pub heartbeat: Option<Heartbeat>,
+    pub stats: Option<Statistics>,
+    pub history: Option<History>,
+    pub busy: bool,
+}
+
+impl StoreForwardRouter {
+    pub fn new(id: u32, name: String) -> Self {
+        StoreForwardRouter {
+            id,
+            name,
+            ..Default::default()
+        }
+    }
+
+    /// Files a message from the router.  Returns false for client-side messages, which means
+    /// the sender isn't a router at all.
+    pub fn observe(&mut self, sf: &StoreAndForward) -> bool {
+        let rr = sf.rr();
+        if matches!(
+            rr,
+            RequestResponse::Unset
+                | RequestResponse::ClientError
+                | RequestResponse::ClientHistory
+                | RequestResponse::ClientStats
+                | RequestResponse::ClientPing
+                | RequestResponse::ClientPong
+                | RequestResponse::ClientAbort
+        ) {
+            return false;
+        }
+        self.last_heard = get_secs();
+        self.busy = rr == RequestResponse::RouterBusy;
+        match &sf.variant {
+            Some(Variant::Heartbeat(h)) => self.heartbeat = Some(*h),
+            Some(Variant::Stats(s)) => self.stats = Some(*s),
+            Some(Variant::History(h)) => {
+                info!(
+                    "S&F router {} is replaying {} message(s) from the last {} minute(s)",
+                    self.name,
+                    h.history_messages,
+                    h.window / 60_000
+                );
+                self.history = Some(*h);
+            }
+            _ => {}
+        }
+        true
+    }
+
+    /// The most history the router will hand out, in minutes, if it has told us.
+    pub fn return_window(&self) -> Option<u32> {
+        self.stats.map(|s| s.return_window).filter(|w| *w > 0)
+    }
+
+    /// A one-line description for the router list.
+    pub fn summary(&self) -> String {
+        let age = pretty_duration(
+            &Duration::from_secs(get_secs().saturating_sub(self.last_heard)),
+            None,
+        );
+        let mut parts = vec![format!("{} heard {age} ago", self.name)];
+        if self.busy {
+            parts.push("busy".to_string());
+        }
+        if let Some(s) = self.stats {
+            parts.push(format!("{}/{} msgs", s.messages_saved, s.messages_max));
+        }
+        if let Some(w) = self.return_window() {
+            parts.push(format!("{w} min window"));
+        }
+        if let Some(h) = self.history {
+            parts.push(format!("last replay {} msgs", h.history_messages));
+        }
+        if let Some(h) = self.heartbeat.filter(|h| h.secondary != 0) {
+            parts.push(format!("secondary, beats every {}s", h.period));
+        }
+        parts.join(", ")
+    }
+}
+
+async fn send_request(router: u32, sf: StoreAndForward) -> Result<()> {
+    let packet = request_packet(
+        router,
+        0,
+        meshtastic::utils::generate_rand_id(),
+        PortNum::StoreForwardApp,
+        sf.encode_to_vec(),
+    );
+    let payload_variant = Some(Packet(packet));
+    util::send_to_radio(IPCMessage::ToRadio(ToRadio { payload_variant })).await
+}
+
+/// Asks a router to replay what it stored over the last `minutes`.
+pub async fn request_history(router: u32, minutes: u32) -> Result<()> {
+    send_request(
+        router,
+        StoreAndForward {
+            rr: RequestResponse::ClientHistory.into(),
+            variant: Some(Variant::History(History {
+                window: minutes,
+                ..Default::default()
+            })),
+        },
+    )
+    .await
+}
+
+pub async fn request_stats(router: u32) -> Result<()> {
+    send_request(
+        router,
+        StoreAndForward {
+            rr: RequestResponse::ClientStats.into(),
+            variant: None,
+        },
+    )
+    .await
+}
@@ -1,14 +1,32 @@
+use crate::admin::{self, AdminTarget, ConfigChange, ConfigSection, RemoteSession};
 use crate::app::{DeviceConfiguration, Mode};
+use crate::mouse::{self, Target};
+use crate::tabs::config_fields::{self, ConfigField};
+use crate::tabs::device_config::REBOOT_CONFIRM_SECS;
 use crate::theme::THEME;
+use crate::util::get_secs;
 use crate::DEVICE_CONFIG;
+use anyhow::Result;
+use meshtastic::protobufs::admin_message::ModuleConfigType;
+use meshtastic::protobufs::module_config::PayloadVariant;
 use ratatui::{prelude::*, widgets::*};
+use serde_json::Value;
 use strum::{Display, EnumIter, FromRepr, IntoEnumIterator};
 
+/// The canned message list isn't part of the module config, so it gets a field of its own on
+/// the CannedMessage page, edited as the firmware stores it: one `|`-separated string.
+const CANNED_MESSAGES_PATH: &str = "messages";
+
 #[derive(Debug, Clone, Default)]
 pub struct ModulesConfigTab {
     row_index: usize,
     tab: ModuleTabs,
     device_config: DeviceConfiguration,
+    pub my_node_id: u32,
+    target: AdminTarget,
+    session: Option<RemoteSession>,
+    fields: Vec<ConfigField>,
+    reboot_armed_at: u64,
 }
 
 #[derive(Debug, Clone, Copy, Default, Display, EnumIter, FromRepr, PartialEq, Eq)]
@@ -44,13 +62,155 @@ impl ModuleTabs {
         let tab = self;
         format!(" {tab} ")
     }
+    fn section(self) -> ConfigSection {
+        ConfigSection::Module(match self {
+            ModuleTabs::Mqtt => ModuleConfigType::MqttConfig,
+            ModuleTabs::Serial => ModuleConfigType::SerialConfig,
+            ModuleTabs::ExternalNotification => ModuleConfigType::ExtnotifConfig,
+            ModuleTabs::StoreForward => ModuleConfigType::StoreforwardConfig,
+            ModuleTabs::RangeTest => ModuleConfigType::RangetestConfig,
+            ModuleTabs::Telemetry => ModuleConfigType::TelemetryConfig,
+            ModuleTabs::CannedMessage => ModuleConfigType::CannedmsgConfig,
+            ModuleTabs::Audio => ModuleConfigType::AudioConfig,
+            ModuleTabs::RemoteHardware => ModuleConfigType::RemotehardwareConfig,
+            ModuleTabs::NeighborInfo => ModuleConfigType::NeighborinfoConfig,
+            ModuleTabs::AmbientLighting => ModuleConfigType::AmbientlightingConfig,
+            ModuleTabs::DetectionSensor => ModuleConfigType::DetectionsensorConfig,
+            ModuleTabs::Paxcounter => ModuleConfigType::PaxcounterConfig,
+        })
+    }
 }
 
 impl ModulesConfigTab {
     pub async fn run(&mut self) {
-        let dc = DEVICE_CONFIG.read().await;
-        if let Some(config) = dc.clone() {
-            self.device_config = config.clone();
+        self.target = admin::target().await;
+        match self.target {
+            AdminTarget::Local => {
+                self.session = None;
+                let dc = DEVICE_CONFIG.read().await;
+                if let Some(config) = dc.clone() {
+                    self.device_config = config.clone();
+                }
+            }
+            AdminTarget::Remote(node_id) => {
+                admin::ensure_section(node_id, self.tab.section()).await;
+                if self.tab == ModuleTabs::CannedMessage {
+                    admin::ensure_section(node_id, ConfigSection::CannedMessages).await;
+                }
+                self.session = admin::session(node_id).await;
+                if let Some(session) = &self.session {
+                    self.device_config = session.config.clone();
+                }
+            }
+        }
+        self.fields = self.section_fields();
+        self.row_index = self.row_index.min(self.fields.len().saturating_sub(1));
+    }
+
+    fn section_fields(&self) -> Vec<ConfigField> {
+        let dc = &self.device_config;
+        match self.tab {
+            ModuleTabs::Mqtt => config_fields::fields(&dc.mqtt),
+            ModuleTabs::Serial => config_fields::fields(&dc.serial),
+            ModuleTabs::ExternalNotification => config_fields::fields(&dc.external_notification),
+            ModuleTabs::StoreForward => config_fields::fields(&dc.store_forward),
+            ModuleTabs::RangeTest => config_fields::fields(&dc.range_test),
+            ModuleTabs::Telemetry => config_fields::fields(&dc.telemetry),
+            ModuleTabs::CannedMessage => {
+                let mut fields = config_fields::fields(&dc.canned_message);
+                fields.push(ConfigField {
+                    path: CANNED_MESSAGES_PATH.to_string(),
+                    value: Value::String(dc.canned_messages.join("|")),
+                    ..Default::default()
+                });
+                fields
+            }
+            ModuleTabs::Audio => config_fields::fields(&dc.audio),
+            ModuleTabs::RemoteHardware => config_fields::fields(&dc.remote_hardware),
+            ModuleTabs::NeighborInfo => config_fields::fields(&dc.neighbor_info),
+            ModuleTabs::AmbientLighting => config_fields::fields(&dc.ambient_lighting),
+            ModuleTabs::DetectionSensor => config_fields::fields(&dc.detection_sensor),
+            ModuleTabs::Paxcounter => config_fields::fields(&dc.paxcounter),
+        }
+    }
+
+    fn updated_section(&self, path: &str, input: &str) -> Result<PayloadVariant> {
+        use config_fields::with_field;
+        let dc = &self.device_config;
+        Ok(match self.tab {
+            ModuleTabs::Mqtt => PayloadVariant::Mqtt(with_field(&dc.mqtt, path, input)?),
+            ModuleTabs::Serial => PayloadVariant::Serial(with_field(&dc.serial, path, input)?),
+            ModuleTabs::ExternalNotification => PayloadVariant::ExternalNotification(with_field(
+                &dc.external_notification,
+                path,
+                input,
+            )?),
+            ModuleTabs::StoreForward => {
+                PayloadVariant::StoreForward(with_field(&dc.store_forward, path, input)?)
+            }
+            ModuleTabs::RangeTest => {
+                PayloadVariant::RangeTest(with_field(&dc.range_test, path, input)?)
+            }
+            ModuleTabs::Telemetry => {
+                PayloadVariant::Telemetry(with_field(&dc.telemetry, path, input)?)
+            }
+            ModuleTabs::CannedMessage => {
+                PayloadVariant::CannedMessage(with_field(&dc.canned_message, path, input)?)
+            }
+            ModuleTabs::Audio => PayloadVariant::Audio(with_field(&dc.audio, path, input)?),
+            ModuleTabs::RemoteHardware => {
+                PayloadVariant::RemoteHardware(with_field(&dc.remote_hardware, path, input)?)
+            }
+            ModuleTabs::NeighborInfo => {
+                PayloadVariant::NeighborInfo(with_field(&dc.neighbor_info, path, input)?)
+            }
+            ModuleTabs::AmbientLighting => {
+                PayloadVariant::AmbientLighting(with_field(&dc.ambient_lighting, path, input)?)
+            }
+            ModuleTabs::DetectionSensor => {
+                PayloadVariant::DetectionSensor(with_field(&dc.detection_sensor, path, input)?)
+            }
+            ModuleTabs::Paxcounter => {
+                PayloadVariant::Paxcounter(with_field(&dc.paxcounter, path, input)?)
+            }
+        })
+    }
+
+    pub fn selected_field(&self) -> Option<&ConfigField> {
+        self.fields.get(self.row_index)
+    }
+
+    pub fn edit_title(&self) -> String {
+        let path = self.selected_field().map(|f| f.path.as_str()).unwrap_or("");
+        format!("Set {} {path} on {}", self.tab, self.target)
+    }
+
+    pub async fn apply_edit(&mut self, input: &str) {
+        let Some(field) = self.selected_field().cloned() else {
+            return;
+        };
+        if self.tab == ModuleTabs::CannedMessage && field.path == CANNED_MESSAGES_PATH {
+            let messages = admin::parse_canned_messages(input);
+            if let Err(e) = admin::set_canned_messages(self.target, self.my_node_id, messages).await
+            {
+                error!(
+                    "Unable to change the canned messages on {}: {e}",
+                    self.target
+                );
+            }
+            return;
+        }
+        let change = match self.updated_section(&field.path, input) {
+            Ok(m) => ConfigChange::Module(m),
+            Err(e) => {
+                error!("Couldn't set {} to {input}: {e}", field.path);
+                return;
+            }
+        };
+        let description = format!("{} {} = {}", self.tab, field.path, input.trim());
+        if let Err(e) = admin::apply_change(self.target, self.my_node_id, change, description).await
+        {
+            error!("Unable to change {} on {}: {e}", field.path, self.target);
         }
     }
 
@@ -63,12 +223,51 @@ impl ModulesConfigTab {
     }
 
     pub fn next_row(&mut self) {
-        self.row_index = self.row_index.saturating_add(1);
+        self.row_index = self
+            .row_index
+            .saturating_add(1)
+            .min(self.fields.len().saturating_sub(1));
     }
-    pub fn function_key(&mut self, _num: u8) {
-        {}
+    /// Jumps to a row, or the last one if `index` is past the end.
+    pub fn select_row(&mut self, index: usize) {
+        self.row_index = index.min(self.fields.len().saturating_sub(1));
+    }
+    pub async fn function_key(&mut self, num: u8) {
+        match num {
+            2 => {
+                admin::cycle_target().await;
+            }
+            3 => {
+                if let AdminTarget::Remote(node_id) = self.target {
+                    admin::request_section(node_id, self.tab.section()).await;
+                    if self.tab == ModuleTabs::CannedMessage {
+                        admin::request_section(node_id, ConfigSection::CannedMessages).await;
+                    }
+                }
+            }
+            4 => {
+                if get_secs().saturating_sub(self.reboot_armed_at) > REBOOT_CONFIRM_SECS {
+                    self.reboot_armed_at = get_secs();
+                    warn!(
+                        "Press F4 again within {REBOOT_CONFIRM_SECS} seconds to reboot the {}",
+                        self.target
+                    );
+                    return;
+                }
+                self.reboot_armed_at = 0;
+                if let Err(e) = admin::reboot(self.target, self.my_node_id).await {
+                    error!("Unable to reboot the {}: {e}", self.target);
+                }
+            }
+            _ => {}
+        }
     }
     pub fn render_tabs(&self, area: Rect, buf: &mut Buffer) {
+        mouse::tabs(
+            area,
+            ModuleTabs::iter().map(ModuleTabs::title),
+            Target::SubTab,
+        );
         let titles = ModuleTabs::iter().map(ModuleTabs::title);
         Tabs::new(titles)
             .style(THEME.tabs)
@@ -80,9 +279,18 @@ impl ModulesConfigTab {
     }
     pub fn left(&mut self) {
         self.tab = self.tab.prev();
+        self.row_index = 0;
     }
     pub fn right(&mut self) {
         self.tab = self.tab.next();
+        self.row_index = 0;
+    }
+    /// Switches to a sub-tab by its position in the bar.
+    pub fn select_tab(&mut self, index: usize) {
+        if let Some(tab) = ModuleTabs::from_repr(index) {
+            self.tab = tab;
+            self.row_index = 0;
+        }
     }
 }
 
@@ -102,50 +310,25 @@ impl Widget for ModulesConfigTab {
             .margin(1)
             .areas(area);
 
-        let device_block = default_inner_block.clone().title("Configuration");
+        let device_block = default_inner_block
+            .clone()
+            .title(config_fields::target_title(
+                self.tab.to_string(),
+                self.target,
+            ));
 
         self.render_tabs(bar, buf);
-        let pg = match self.tab {
-            ModuleTabs::Mqtt => {
-                format!("{:#?}", self.device_config.mqtt)
-            }
-            ModuleTabs::Serial => {
-                format!("{:#?}", self.device_config.serial)
-            }
-            ModuleTabs::ExternalNotification => {
-                format!("{:#?}", self.device_config.external_notification)
-            }
-            ModuleTabs::StoreForward => {
-                format!("{:#?}", self.device_config.store_forward)
-            }
-            ModuleTabs::RangeTest => {
-                format!("{:#?}", self.device_config.range_test)
-            }
-            ModuleTabs::Telemetry => {
-                format!("{:#?}", self.device_config.telemetry)
-            }
-            ModuleTabs::CannedMessage => {
-                format!("{:#?}", self.device_config.canned_message)
-            }
-            ModuleTabs::Audio => {
-                format!("{:#?}", self.device_config.audio)
-            }
-            ModuleTabs::RemoteHardware => {
-                format!("{:#?}", self.device_config.remote_hardware)
-            }
-            ModuleTabs::NeighborInfo => {
-                format!("{:#?}", self.device_config.neighbor_info)
-            }
-            ModuleTabs::AmbientLighting => {
-                format!("{:#?}", self.device_config.ambient_lighting)
-            }
-            ModuleTabs::DetectionSensor => {
-                format!("{:#?}", self.device_config.detection_sensor)
-            }
-            ModuleTabs::Paxcounter => {
-                format!("{:#?}", self.device_config.paxcounter)
-            }
-        };
-        Paragraph::new(pg).block(device_block).render(field, buf);
+        let session = self
+            .session
+            .as_ref()
+            .map(|s| (s, s.has(self.tab.section())));
+        config_fields::render_fields(
+            &self.fields,
+            self.row_index,
+            device_block,
+            session,
+            field,
+            buf,
+        );
     }
 }
@@ -3,29 +3,35 @@ use crate::app::{MenuTabs, Mode, Preferences};
 use crate::consts::GPS_PRECISION_FACTOR;
 use crate::theme::THEME;
 use crate::util::get_secs;
-use crate::{PAGE_SIZE, consts, util};
+use crate::{mouse, pki, KEY_PINS, PAGE_SIZE, consts, util};
 use geoutils::Location;
 use itertools::Itertools;
 
-use circular_buffer::CircularBuffer;
-use meshtastic::protobufs;
 use meshtastic::protobufs::PortNum::TracerouteApp;
 use meshtastic::protobufs::to_radio::PayloadVariant::Packet;
 use meshtastic::protobufs::*;
 use pretty_duration::pretty_duration;
 use ratatui::{prelude::*, widgets::*};
-use std::collections::HashMap;
-use std::ops::Div;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ops::{Deref, Div};
+use std::sync::Arc;
 use std::time::Duration;
-use strum::Display;
+use strum::{Display, EnumIter, FromRepr, IntoEnumIterator};
 
 use crate::ipc::IPCMessage;
 
+mod chart;
+pub(crate) mod requests;
+use crate::packet_handler::{LinkObservation, RequestReply};
+use chart::{ChartSeries, ChartWindow};
+use requests::{NodeRequest, NodeRequestKind, RequestState};
+
 #[derive(Default, Debug, Clone, PartialEq)]
 pub enum DisplayMode {
     #[default]
     List,
     Detail,
+    Chart,
     Help,
 }
 
@@ -42,50 +48,119 @@ pub struct NodesTab {
     pub selected_node_id: u32,
     pub page_size: u16,
     pub which_graph: DisplayedGraph,
+    pub overlay_graph: Option<DisplayedGraph>,
+    pub chart_window: ChartWindow,
+    pub compare_nodes: Vec<u32>,
+    chart_return_mode: DisplayMode,
+    requests: HashMap<u32, NodeRequest>,
 }
-#[derive(Default, Debug, Display, Clone)]
+#[derive(Default, Debug, Display, Clone, Copy, EnumIter, FromRepr, PartialEq, Eq)]
 pub enum DisplayedGraph {
     #[default]
     Battery,
     Voltage,
     AirUtilization,
     ChannelUtilization,
-    RSSI,
-    SNR,
+    Rssi,
+    Snr,
     Temperature,
     RelativeHumidity,
     BarometricPressure,
     GasResistance,
+    Pm10,
+    Pm25,
+    Pm100,
+    Particles03um,
+    Particles05um,
+    Particles10um,
+    Particles25um,
+    Particles50um,
+    Particles100um,
+    Co2,
+    Ch1Voltage,
+    Ch1Current,
+    Ch2Voltage,
+    Ch2Current,
+    Ch3Voltage,
+    Ch3Current,
 }
 impl DisplayedGraph {
     fn prev(&self) -> Self {
+        let current_index = *self as usize;
+        match current_index {
+            0 => Self::iter().next_back().unwrap_or(*self),
+            i => Self::from_repr(i - 1).unwrap_or(*self),
+        }
+    }
+    fn next(&self) -> Self {
+        let current_index = *self as usize;
+        Self::from_repr(current_index.saturating_add(1)).unwrap_or_default()
+    }
+    /// Returns the chart title and y-axis label for this graph.
+    fn describe(&self) -> (&'static str, &'static str) {
         use DisplayedGraph::*;
         match *self {
-            Battery => GasResistance,
-            Voltage => Battery,
-            AirUtilization => Voltage,
-            ChannelUtilization => AirUtilization,
-            RSSI => ChannelUtilization,
-            SNR => RSSI,
-            Temperature => SNR,
-            RelativeHumidity => Temperature,
-            BarometricPressure => RelativeHumidity,
-            GasResistance => BarometricPressure,
+            Battery => ("Battery", "Percent (%)"),
+            Voltage => ("Device Voltage", "Volts (V)"),
+            AirUtilization => ("Air Utilization", "Percent (%)"),
+            ChannelUtilization => ("Channel Utilization", "Percent (%)"),
+            Rssi => ("RSSI", "decibels (dB)"),
+            Snr => ("SNR", "decibels (dB)"),
+            Temperature => ("Temperature", "Celsius (C)"),
+            RelativeHumidity => ("Relative Humidity", "Percent (%)"),
+            BarometricPressure => ("Barometric Pressure", "millibars (mb)"),
+            GasResistance => ("Gas Resistance", "milliohms (mΩ)"),
+            Pm10 => ("PM1.0 (standard)", "µg/m³"),
+            Pm25 => ("PM2.5 (standard)", "µg/m³"),
+            Pm100 => ("PM10 (standard)", "µg/m³"),
+            Particles03um => ("Particles >0.3µm", "per 0.1L"),
+            Particles05um => ("Particles >0.5µm", "per 0.1L"),
+            Particles10um => ("Particles >1.0µm", "per 0.1L"),
+            Particles25um => ("Particles >2.5µm", "per 0.1L"),
+            Particles50um => ("Particles >5.0µm", "per 0.1L"),
+            Particles100um => ("Particles >10µm", "per 0.1L"),
+            Co2 => ("CO2", "ppm"),
+            Ch1Voltage => ("Ch1 Voltage", "Volts (V)"),
+            Ch1Current => ("Ch1 Current", "milliamps (mA)"),
+            Ch2Voltage => ("Ch2 Voltage", "Volts (V)"),
+            Ch2Current => ("Ch2 Current", "milliamps (mA)"),
+            Ch3Voltage => ("Ch3 Voltage", "Volts (V)"),
+            Ch3Current => ("Ch3 Current", "milliamps (mA)"),
         }
     }
-    fn next(&self) -> Self {
+    /// Pulls this graph's datapoint out of a timeseries sample, if the sample carries one.
+    fn value(&self, d: &TimeSeriesData) -> Option<f64> {
         use DisplayedGraph::*;
+        let aq = &d.air_quality;
+        let pm = &d.power;
         match *self {
-            Battery => Voltage,
-            Voltage => AirUtilization,
-            AirUtilization => ChannelUtilization,
-            ChannelUtilization => RSSI,
-            RSSI => SNR,
-            SNR => Temperature,
-            Temperature => RelativeHumidity,
-            RelativeHumidity => BarometricPressure,
-            BarometricPressure => GasResistance,
-            GasResistance => Battery,
+            Battery => d.device.battery_level.map(|v| v as f64),
+            Voltage => d.device.voltage.map(|v| v as f64),
+            AirUtilization => d.device.air_util_tx.map(|v| v as f64),
+            ChannelUtilization => d.device.channel_utilization.map(|v| v as f64),
+            // rssi and snr are recorded as 0.0 when the packet didn't come in over RF.
+            Rssi => Some(d.rssi).filter(|v| *v != 0.0),
+            Snr => Some(d.snr).filter(|v| *v != 0.0),
+            Temperature => d.environment.temperature.map(|v| v as f64),
+            RelativeHumidity => d.environment.relative_humidity.map(|v| v as f64),
+            BarometricPressure => d.environment.barometric_pressure.map(|v| v as f64),
+            GasResistance => d.environment.gas_resistance.map(|v| v as f64),
+            Pm10 => aq.pm10_standard.map(|v| v as f64),
+            Pm25 => aq.pm25_standard.map(|v| v as f64),
+            Pm100 => aq.pm100_standard.map(|v| v as f64),
+            Particles03um => aq.particles_03um.map(|v| v as f64),
+            Particles05um => aq.particles_05um.map(|v| v as f64),
+            Particles10um => aq.particles_10um.map(|v| v as f64),
+            Particles25um => aq.particles_25um.map(|v| v as f64),
+            Particles50um => aq.particles_50um.map(|v| v as f64),
+            Particles100um => aq.particles_100um.map(|v| v as f64),
+            Co2 => aq.co2.map(|v| v as f64),
+            Ch1Voltage => pm.ch1_voltage.map(|v| v as f64),
+            Ch1Current => pm.ch1_current.map(|v| v as f64),
+            Ch2Voltage => pm.ch2_voltage.map(|v| v as f64),
+            Ch2Current => pm.ch2_current.map(|v| v as f64),
+            Ch3Voltage => pm.ch3_voltage.map(|v| v as f64),
+            Ch3Current => pm.ch3_current.map(|v| v as f64),
         }
     }
 }
@@ -96,11 +171,88 @@ pub struct ComprehensiveNode {
     pub node_info: NodeInfo,
     pub last_seen: u64,
     pub neighbors: Vec<Neighbor>,
+    /// When the node last sent us its neighbor list.
+    pub neighbors_heard: u64,
     pub last_snr: f32,
     pub last_rssi: i32,
     pub route_list: HashMap<u32, Vec<u32>>,
-    pub timeseries: CircularBuffer<{ consts::MAX_MSG_RETENTION }, TimeSeriesData>,
+    /// The last traceroute between this node and each node in `route_list`, with hop SNRs.
+    pub traceroutes: HashMap<u32, TracedRoute>,
+    pub timeseries: History<TimeSeriesData>,
     pub timeseries_start: u64,
+    pub air_quality: Option<AirQualityMetrics>,
+    pub power: Option<PowerMetrics>,
+    /// How many packets we've heard from this node at each hop count.
+    pub hop_counts: BTreeMap<u32, u32>,
+    pub last_hops: Option<u32>,
+    /// The last byte of the node id that last relayed this node's traffic to us.
+    pub last_relay: Option<u32>,
+    /// Signal readings from every packet we've received from this node.
+    pub rf_history: History<RfSample>,
+    /// Packets received from this node, by port name.
+    pub packet_counts: BTreeMap<&'static str, u32>,
+    /// Where the node has reported being, oldest first.
+    pub track: History<TrackPoint>,
+}
+
+/// A node's history of some reading, oldest first, holding at most
+/// `consts::MAX_TIMESERIES_RETENTION` entries.  The node list is copied for every packet and
+/// every frame, so clones share the entries rather than copying them, until one of them adds to
+/// its history.
+#[derive(Debug, Clone)]
+pub struct History<T: Clone>(Arc<VecDeque<T>>);
+
+impl<T: Clone> Default for History<T> {
+    fn default() -> Self {
+        History(Arc::new(VecDeque::new()))
+    }
+}
+
+impl<T: Clone> History<T> {
+    /// Appends an entry, dropping the oldest once we hit our retention limit.
+    pub fn push(&mut self, entry: T) {
+        let entries = Arc::make_mut(&mut self.0);
+        entries.push_back(entry);
+        while entries.len() > consts::MAX_TIMESERIES_RETENTION {
+            entries.pop_front();
+        }
+    }
+}
+
+impl<T: Clone> Deref for History<T> {
+    type Target = VecDeque<T>;
+    fn deref(&self) -> &VecDeque<T> {
+        &self.0
+    }
+}
+
+impl<'a, T: Clone> IntoIterator for &'a History<T> {
+    type Item = &'a T;
+    type IntoIter = std::collections::vec_deque::Iter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TracedRoute {
+    pub discovery: RouteDiscovery,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrackPoint {
+    pub timestamp: u64,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: Option<i32>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RfSample {
+    pub timestamp: u64,
+    pub snr: f32,
+    pub rssi: i32,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -118,14 +270,89 @@ impl ComprehensiveNode {
     pub fn with_id(id: u32) -> Self {
         ComprehensiveNode {
             id,
+            node_info: NodeInfo {
+                num: id,
+                ..Default::default()
+            },
             ..Default::default()
         }
     }
+    /// Appends a telemetry sample, dropping the oldest once we hit our retention limit.
+    pub fn record(&mut self, sample: TimeSeriesData) {
+        if self.timeseries_start == 0 {
+            self.timeseries_start = sample.timestamp;
+        }
+        self.timeseries.push(sample);
+    }
+    /// Adds a reported position to the node's track, unless it hasn't moved since the last one.
+    pub fn record_position(&mut self, position: &meshtastic::protobufs::Position) {
+        let Some((latitude, longitude)) = util::position_degrees(position) else {
+            return;
+        };
+        if self
+            .track
+            .back()
+            .is_some_and(|p| p.latitude == latitude && p.longitude == longitude)
+        {
+            return;
+        }
+        self.track.push(TrackPoint {
+            timestamp: match position.time {
+                0 => get_secs(),
+                t => t as u64,
+            },
+            latitude,
+            longitude,
+            altitude: position.altitude,
+        });
+    }
+    /// Notes the route a packet from this node took to reach us.
+    pub fn observe(&mut self, link: &LinkObservation) {
+        if let Some(hops) = link.hops {
+            *self.hop_counts.entry(hops).or_default() += 1;
+            self.node_info.hops_away = Some(hops);
+        }
+        self.last_hops = link.hops;
+        self.last_relay = match link.hops {
+            Some(0) => None,
+            _ => link.relay_node,
+        };
+        self.node_info.via_mqtt = link.via_mqtt;
+        self.last_seen = get_secs();
+
+        let port = link.port.map_or("ENCRYPTED", |p| p.as_str_name());
+        *self.packet_counts.entry(port).or_default() += 1;
+        // packets bridged in from MQTT carry no signal readings of their own.
+        if !link.via_mqtt && (link.rx_snr != 0.0 || link.rx_rssi != 0) {
+            self.last_snr = link.rx_snr;
+            self.last_rssi = link.rx_rssi;
+            self.rf_history.push(RfSample {
+                timestamp: self.last_seen,
+                snr: link.rx_snr,
+                rssi: link.rx_rssi,
+            });
+        }
+    }
+    /// Mean and standard deviation of a reading across our RF history.
+    pub fn rf_spread(&self, reading: impl Fn(&RfSample) -> f64) -> Option<(f64, f64)> {
+        if self.rf_history.is_empty() {
+            return None;
+        }
+        let count = self.rf_history.len() as f64;
+        let mean = self.rf_history.iter().map(&reading).sum::<f64>() / count;
+        let variance = self
+            .rf_history
+            .iter()
+            .map(|s| (reading(s) - mean).powi(2))
+            .sum::<f64>()
+            / count;
+        Some((mean, variance.sqrt()))
+    }
 }
 
 impl NodesTab {
     pub(crate) fn prev_tab(&mut self, app_tab: MenuTabs) -> MenuTabs {
-        if self.display_mode == DisplayMode::Detail {
+        if matches!(self.display_mode, DisplayMode::Detail | DisplayMode::Chart) {
             self.which_graph = self.which_graph.prev();
             app_tab
         } else {
@@ -133,7 +360,7 @@ impl NodesTab {
         }
     }
     pub(crate) fn next_tab(&mut self, app_tab: MenuTabs) -> MenuTabs {
-        if self.display_mode == DisplayMode::Detail {
+        if matches!(self.display_mode, DisplayMode::Detail | DisplayMode::Chart) {
             self.which_graph = self.which_graph.next();
             app_tab
         } else {
@@ -221,7 +448,7 @@ impl NodesTab {
 
             //region User-struct display fields
             if cn.node_info.user.is_some() {
-                let user = cn.node_info.user.unwrap();
+                let user = cn.node_info.user.clone().unwrap();
 
                 rows.push(Row::new(vec![
                     "Id (According to User)".to_string(),
@@ -245,6 +472,7 @@ impl NodesTab {
                     "Device Role".to_string(),
                     format!("{:?}", user.role()),
                 ]));
+                rows.extend(public_key_rows(&cn, &user));
             } else {
                 rows.push(Row::new(vec![
                     "Id* (implied)".to_string(),
@@ -253,11 +481,74 @@ impl NodesTab {
             }
             //endregion
 
+            //region on-demand request status
+            for request in self
+                .requests
+                .values()
+                .filter(|r| r.node_id == cn.id)
+                .sorted_by_key(|r| r.kind.to_string())
+            {
+                rows.push(
+                    Row::new(vec![format!("{} request", request.kind), request.status()])
+                        .style(THEME.message_header),
+                );
+            }
+            //endregion
+
+            //region route display fields
+            let route = match (cn.last_hops, cn.last_relay) {
+                (Some(0), _) => "direct".to_string(),
+                (Some(hops), Some(relay)) => {
+                    format!("relayed via {} ({hops} hops)", self.relay_label(relay))
+                }
+                (Some(hops), None) => format!("{hops} hops"),
+                (None, _) => "unknown".to_string(),
+            };
+            rows.push(Row::new(vec!["Route".to_string(), route]));
+            if !cn.hop_counts.is_empty() {
+                let distribution = cn
+                    .hop_counts
+                    .iter()
+                    .map(|(hops, count)| format!("{hops}h:{count}"))
+                    .join(" ");
+                rows.push(Row::new(vec!["Hops heard".to_string(), distribution]));
+            }
+            //endregion
+
             rows.push(Row::new(vec![
                 "Last RF SNR/RSSI".to_string(),
                 format!("{:.2}dB/{:.2}db", cn.last_snr, cn.last_rssi),
             ]));
 
+            //region link quality display fields
+            if let (Some((snr_avg, snr_spread)), Some((rssi_avg, rssi_spread))) = (
+                cn.rf_spread(|s| s.snr as f64),
+                cn.rf_spread(|s| s.rssi as f64),
+            ) {
+                rows.push(Row::new(vec![
+                    "SNR avg ± spread".to_string(),
+                    format!(
+                        "{snr_avg:.2}dB ± {snr_spread:.2} ({} packets)",
+                        cn.rf_history.len()
+                    ),
+                ]));
+                rows.push(Row::new(vec![
+                    "RSSI avg ± spread".to_string(),
+                    format!("{rssi_avg:.1}dB ± {rssi_spread:.1}"),
+                ]));
+            }
+            for (port, count) in cn
+                .packet_counts
+                .iter()
+                .sorted_by_key(|(_, count)| std::cmp::Reverse(**count))
+            {
+                rows.push(Row::new(vec![
+                    format!("Packets: {port}"),
+                    count.to_string(),
+                ]));
+            }
+            //endregion
+
             //region DeviceMetrics-struct display fields
             if let Some(device_metrics) = cn.node_info.device_metrics {
                 if let Some(air_util_tx) = device_metrics.air_util_tx  {
@@ -305,6 +596,53 @@ impl NodesTab {
                 }
             }
             //endregion
+
+            //region AirQualityMetrics-struct display fields
+            if let Some(aq) = cn.air_quality {
+                if let (Some(pm10), Some(pm25), Some(pm100)) =
+                    (aq.pm10_standard, aq.pm25_standard, aq.pm100_standard)
+                {
+                    rows.push(Row::new(vec![
+                        "PM1.0/PM2.5/PM10".to_string(),
+                        format!("{pm10}/{pm25}/{pm100} µg/m³"),
+                    ]));
+                }
+                if let Some(p03) = aq.particles_03um {
+                    rows.push(Row::new(vec![
+                        "Particles >0.3µm".to_string(),
+                        format!("{p03}/0.1L"),
+                    ]));
+                }
+                if let Some(p25) = aq.particles_25um {
+                    rows.push(Row::new(vec![
+                        "Particles >2.5µm".to_string(),
+                        format!("{p25}/0.1L"),
+                    ]));
+                }
+                if let Some(co2) = aq.co2 {
+                    rows.push(Row::new(vec!["CO2".to_string(), format!("{co2}ppm")]));
+                }
+            }
+            //endregion
+
+            //region PowerMetrics-struct display fields
+            if let Some(power) = cn.power {
+                let channels = [
+                    ("Ch1", power.ch1_voltage, power.ch1_current),
+                    ("Ch2", power.ch2_voltage, power.ch2_current),
+                    ("Ch3", power.ch3_voltage, power.ch3_current),
+                ];
+                for (name, voltage, current) in channels {
+                    let reading = match (voltage, current) {
+                        (Some(v), Some(c)) => format!("{:.2}V / {:.1}mA", v, c),
+                        (Some(v), None) => format!("{:.2}V", v),
+                        (None, Some(c)) => format!("{:.1}mA", c),
+                        (None, None) => continue,
+                    };
+                    rows.push(Row::new(vec![format!("{name} Power"), reading]));
+                }
+            }
+            //endregion
             //endregion
 
             //region Position-struct display fields
@@ -407,215 +745,380 @@ impl NodesTab {
             //endregion
         }
     }
+    /// Collects one graph's points for a node, trimmed to the selected chart window.
+    fn series_for(&self, node_id: u32, graph: DisplayedGraph) -> Vec<(f64, f64)> {
+        let cutoff = self
+            .chart_window
+            .seconds()
+            .map_or(0, |secs| get_secs().saturating_sub(secs));
+        let Some(cn) = self.node_list.get(&node_id) else {
+            return vec![];
+        };
+        // link quality comes from every packet we hear, not just telemetry.
+        let rf_reading: Option<fn(&RfSample) -> f64> = match graph {
+            DisplayedGraph::Rssi => Some(|s: &RfSample| s.rssi as f64),
+            DisplayedGraph::Snr => Some(|s: &RfSample| s.snr as f64),
+            _ => None,
+        };
+        if let Some(reading) = rf_reading {
+            return cn
+                .rf_history
+                .iter()
+                .filter(|s| s.timestamp >= cutoff)
+                .map(|s| (s.timestamp as f64, reading(s)))
+                .collect();
+        }
+        cn.timeseries
+            .iter()
+            .filter(|d| d.timestamp >= cutoff)
+            .filter_map(|d| graph.value(d).map(|datum| (d.timestamp as f64, datum)))
+            .collect()
+    }
+
+    pub(crate) fn node_label(&self, node_id: u32) -> String {
+        match self
+            .node_list
+            .get(&node_id)
+            .and_then(|cn| cn.node_info.user.clone())
+        {
+            Some(user) if !user.short_name.is_empty() => user.short_name,
+            _ => format!("!{:x}", node_id),
+        }
+    }
+
     pub fn make_graph(&self, area: Rect, buf: &mut Buffer) {
         // chart time
-        use DisplayedGraph::*;
-        let cn = self.node_list.get(&self.selected_node_id).cloned().unwrap();
-        let mut data: Vec<(f64, f64)>;
-        let graph_name: String;
-        let y_axis_unit: String;
-        match self.which_graph {
-            Battery => {
-                graph_name = "Battery".to_string();
-                y_axis_unit = "Percent (%)".to_string();
-                data = cn
-                    .timeseries
-                    .iter()
-                    .map(|d| (d.timestamp as f64, d.device.battery_level() as f64))
-                    .collect();
-            }
-            Voltage => {
-                graph_name = "Device Voltage".to_string();
-                y_axis_unit = "Volts (V)".to_string();
-                data = cn
-                    .timeseries
-                    .iter()
-                    .map(|d| (d.timestamp as f64, d.device.voltage() as f64))
-                    .collect();
-            }
-            AirUtilization => {
-                graph_name = "Air Utilization".to_string();
-                y_axis_unit = "Percent (%)".to_string();
-                data = cn
-                    .timeseries
-                    .iter()
-                    .map(|d| (d.timestamp as f64, d.device.air_util_tx() as f64))
-                    .collect();
-            }
-            ChannelUtilization => {
-                graph_name = "Channel Utilization".to_string();
-                y_axis_unit = "Percent (%)".to_string();
-                data = cn
-                    .timeseries
-                    .iter()
-                    .map(|d| (d.timestamp as f64, d.device.channel_utilization() as f64))
-                    .collect()
-            }
-            RSSI => {
-                graph_name = "RSSI".to_string();
-                y_axis_unit = "decibels (dB)".to_string();
-                data = cn
-                    .timeseries
-                    .iter()
-                    .map(|d| (d.timestamp as f64, d.rssi))
-                    .collect()
-            }
-            SNR => {
-                graph_name = "SNR".to_string();
-                y_axis_unit = "decibels (dB)".to_string();
-                data = cn
-                    .timeseries
-                    .iter()
-                    .map(|d| (d.timestamp as f64, d.snr))
-                    .collect()
-            }
-            Temperature => {
-                graph_name = "Temperature".to_string();
-                y_axis_unit = "Celsius (C)".to_string();
-                data = cn
-                    .timeseries
-                    .iter()
-                    .map(|d| (d.timestamp as f64, d.environment.temperature() as f64))
-                    .collect()
+        let (graph_name, y_axis_unit) = self.which_graph.describe();
+
+        let mut series: Vec<ChartSeries> = vec![];
+        if self.display_mode == DisplayMode::Chart && !self.compare_nodes.is_empty() {
+            for node_id in self.compare_nodes.iter() {
+                let points = self.series_for(*node_id, self.which_graph);
+                series.push(ChartSeries::new(self.node_label(*node_id), points));
             }
-            RelativeHumidity => {
-                graph_name = "Relative Humidity".to_string();
-                y_axis_unit = "Percent (%)".to_string();
-                data = cn
-                    .timeseries
-                    .iter()
-                    .map(|d| (d.timestamp as f64, d.environment.relative_humidity() as f64))
-                    .collect()
+        } else {
+            let primary = ChartSeries::new(
+                graph_name.to_string(),
+                self.series_for(self.selected_node_id, self.which_graph),
+            );
+            let primary_stats = primary.stats;
+            series.push(primary);
+
+            if let Some(overlay) = self.overlay_graph {
+                let (overlay_name, overlay_unit) = overlay.describe();
+                let mut secondary = ChartSeries::new(
+                    overlay_name.to_string(),
+                    self.series_for(self.selected_node_id, overlay),
+                );
+                if overlay_unit != y_axis_unit {
+                    // different units can't share an axis, so stretch the overlay over the
+                    // primary series' range; the stats line still reports the real values.
+                    if let (Some(p), Some(o)) = (primary_stats, secondary.stats) {
+                        secondary.points =
+                            chart::rescale(&secondary.points, (o.min, o.max), (p.min, p.max));
+                        secondary.name = format!("{overlay_name} (scaled)");
+                    }
+                }
+                series.push(secondary);
             }
-            BarometricPressure => {
-                graph_name = "Barometric Pressure".to_string();
-                y_axis_unit = "millibars (mb)".to_string();
-                data = cn
-                    .timeseries
-                    .iter()
-                    .map(|d| (d.timestamp as f64, d.environment.barometric_pressure() as f64))
-                    .collect()
+        }
+
+        let datasets: Vec<Dataset> = series
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                Dataset::default()
+                    .marker(symbols::Marker::Braille)
+                    .name(line.name.clone())
+                    .graph_type(GraphType::Line)
+                    .style(Style::new().fg(THEME.chart_palette[i % THEME.chart_palette.len()]))
+                    .data(line.points.as_slice())
+            })
+            .collect();
+
+        let (x_low, x_high) = match self.chart_window.seconds() {
+            Some(secs) => {
+                let now = get_secs();
+                (now.saturating_sub(secs) as f64, now as f64)
             }
-            GasResistance => {
-                graph_name = "Gas Resistance".to_string();
-                y_axis_unit = "milliohms (mΩ)".to_string();
-                data = cn
-                    .timeseries
+            None => {
+                let timestamps = series
                     .iter()
-                    .map(|d| (d.timestamp as f64, d.environment.gas_resistance() as f64))
-                    .collect()
+                    .flat_map(|line| line.points.iter().map(|(ts, _)| *ts));
+                let (low, high) = timestamps.fold((f64::MAX, f64::MIN), |(low, high), ts| {
+                    (low.min(ts), high.max(ts))
+                });
+                if low > high {
+                    (0.0, 0.0)
+                } else {
+                    (low, high)
+                }
             }
         };
-        // if our dataset has exact 0.0 entries, the chances are astronomically high that the
-        // value was put there by Default::default() instead of an actual data read.
-        data.retain(|(_, datum)| datum > &0.0);
-
-        let dataset = Dataset::default()
-            .marker(symbols::Marker::Braille)
-            .name(graph_name)
-            .graph_type(GraphType::Line)
-            .style(THEME.tabs_selected)
-            .data(data.as_slice());
-
-        let x_bound: Vec<f64> = data.iter().map(|(ts, _)| *ts).collect();
-        let x_low = *x_bound
-            .iter()
-            .min_by(|a, b| a.partial_cmp(b).unwrap())
-            .unwrap_or(&0.0);
-        let x_high = *x_bound
-            .iter()
-            .max_by(|a, b| a.partial_cmp(b).unwrap())
-            .unwrap_or(&0.0);
-
-        let y_bound: Vec<f64> = data.iter().map(|(_, c)| *c).collect();
-        let y_low = *y_bound
-            .iter()
-            .min_by(|a, b| a.partial_cmp(b).unwrap())
-            .unwrap_or(&0.0);
-        let y_high = *y_bound
-            .iter()
-            .max_by(|a, b| a.partial_cmp(b).unwrap())
-            .unwrap_or(&0.0);
+        let (y_low, y_high) = chart::value_bounds(series.iter().map(|line| line.points.as_slice()));
 
+        let title = match self.display_mode {
+            DisplayMode::Chart if !self.compare_nodes.is_empty() => {
+                format!(
+                    "Telemetry: {graph_name} across {} nodes ({})",
+                    self.compare_nodes.len(),
+                    self.chart_window.label()
+                )
+            }
+            _ => format!("Telemetry ({})", self.chart_window.label()),
+        };
         let block = Block::default()
             .borders(Borders::ALL)
             .title_alignment(Alignment::Center)
             .border_set(symbols::border::ROUNDED)
-            .title("Telemetry")
+            .title(title)
             .style(THEME.middle);
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let [chart_area, stats_area] = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(series.len() as u16)])
+            .areas(inner);
 
+        let label_count = (chart_area.width / 16).clamp(2, 6) as usize;
         let x_axis = Axis::default()
-            .title("unixtime")
+            .title("time (UTC)")
             .style(THEME.tabs_selected)
             .bounds([x_low, x_high])
-            .labels(vec![
-                Span::raw(x_low.to_string()),
-                Span::raw(x_high.to_string()),
-            ]);
+            .labels(chart::time_labels(x_low, x_high, label_count));
         let y_axis = Axis::default()
             .title(y_axis_unit)
             .style(THEME.tabs_selected)
             .bounds([y_low, y_high])
             .labels(vec![
                 Span::raw(format!("{:.2}", y_low)),
+                Span::raw(format!("{:.2}", (y_low + y_high) / 2.0)),
                 Span::raw(format!("{:.2}", y_high)),
             ]);
         Widget::render(
-            Chart::new(vec![dataset])
+            Chart::new(datasets)
                 .style(THEME.middle)
-                .block(block)
                 .x_axis(x_axis)
                 .y_axis(y_axis),
-            area,
+            chart_area,
             buf,
         );
+
+        let stats_lines: Vec<Line> = series
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let readout = line
+                    .stats
+                    .map_or("no data in window".to_string(), |s| s.summary());
+                Line::from(vec![
+                    Span::styled(
+                        format!("{}: ", line.name),
+                        Style::new().fg(THEME.chart_palette[i % THEME.chart_palette.len()]),
+                    ),
+                    Span::raw(readout),
+                ])
+            })
+            .collect();
+        Paragraph::new(stats_lines).render(stats_area, buf);
+    }
+
+    /// Steps the overlay through every graph other than the primary, then back to none.
+    fn cycle_overlay(&mut self) {
+        let mut candidate = self.overlay_graph;
+        loop {
+            candidate = match candidate {
+                None => Some(DisplayedGraph::default()),
+                Some(g) if g.next() == DisplayedGraph::default() => None,
+                Some(g) => Some(g.next()),
+            };
+            if candidate != Some(self.which_graph) {
+                break;
+            }
+        }
+        self.overlay_graph = candidate;
+    }
+
+    fn cycle_chart_window(&mut self) {
+        let history_secs = self
+            .node_list
+            .get(&self.selected_node_id)
+            .and_then(|cn| cn.timeseries.front())
+            .map_or(0, |d| get_secs().saturating_sub(d.timestamp));
+        self.chart_window = self.chart_window.next_available(history_secs);
+    }
+
+    fn toggle_chart_mode(&mut self) {
+        match self.display_mode {
+            DisplayMode::Chart => self.display_mode = self.chart_return_mode.clone(),
+            DisplayMode::Detail => {
+                self.chart_return_mode = DisplayMode::Detail;
+                self.display_mode = DisplayMode::Chart;
+            }
+            DisplayMode::List if !self.compare_nodes.is_empty() => {
+                self.chart_return_mode = DisplayMode::List;
+                self.display_mode = DisplayMode::Chart;
+            }
+            _ => {}
+        }
+    }
+
+    /// Adds or removes a node from the set compared in the full-screen chart.
+    fn toggle_compare(&mut self) {
+        let node_id = match self.display_mode {
+            DisplayMode::List => match self
+                .table_state
+                .selected()
+                .and_then(|i| self.table_contents.get(i))
+            {
+                Some(cn) => cn.id,
+                None => return,
+            },
+            _ => self.selected_node_id,
+        };
+        if let Some(pos) = self.compare_nodes.iter().position(|id| *id == node_id) {
+            self.compare_nodes.remove(pos);
+            info!("Removed !{:x} from chart comparison", node_id);
+        } else {
+            self.compare_nodes.push(node_id);
+            info!("Added !{:x} to chart comparison", node_id);
+        }
     }
 
     pub async fn send_traceroute(&mut self) {
         if let Some(index) = self.table_state.selected() {
             self.selected_node_id = self.table_contents[index].clone().id;
+            self.traceroute(self.selected_node_id).await;
+        }
+    }
+    pub async fn traceroute(&self, node_id: u32) {
+        let mesh_packet = requests::request_packet(node_id, 0, 0, TracerouteApp, vec![]);
+        let payload_variant = Some(Packet(mesh_packet));
+        if let Err(e) = util::send_to_radio(IPCMessage::ToRadio(ToRadio { payload_variant })).await
+        {
+            error!("Tried sending traceroute but failed: {e}");
+        } else {
+            info!("Emitted Traceroute Request to !{:x}", node_id);
+        }
+    }
+
+    /// Asks the node being viewed to send us fresh data of the given kind.
+    pub async fn send_request(&mut self, kind: NodeRequestKind) {
+        if self.display_mode != DisplayMode::Detail {
+            return;
+        }
+        let node_id = self.selected_node_id;
+        let channel = self
+            .node_list
+            .get(&node_id)
+            .map_or(0, |cn| cn.node_info.channel);
+        let me = self
+            .node_list
+            .get(&self.my_node_id)
+            .and_then(|cn| cn.node_info.user.as_ref());
+        let packet_id: u32 = meshtastic::utils::generate_rand_id();
+        let mesh_packet = requests::request_packet(
+            node_id,
+            channel,
+            packet_id,
+            kind.portnum(),
+            kind.payload(me),
+        );
+        let payload_variant = Some(Packet(mesh_packet));
+        if let Err(e) = util::send_to_radio(IPCMessage::ToRadio(ToRadio { payload_variant })).await
+        {
+            error!(
+                "Tried requesting {kind} from !{:x} but failed: {e}",
+                node_id
+            );
+            return;
+        }
+        info!("Requested {kind} from !{:x}", node_id);
+        // only the newest request of each kind per node is interesting to show.
+        self.requests
+            .retain(|_, r| !(r.node_id == node_id && r.kind == kind));
+        self.requests
+            .insert(packet_id, NodeRequest::new(node_id, kind));
+    }
 
-            #[allow(deprecated)]
-            let mesh_packet = MeshPacket {
-                from: 0,
-                to: self.selected_node_id,
-                channel: 0,
-                id: 0,
-                rx_time: 0,
-                rx_snr: 0.0,
-                hop_limit: 0,
-                want_ack: true,
-                priority: 0,
-                rx_rssi: 0,
-                delayed: 0,
-                via_mqtt: true,
-                hop_start: 0,
-                public_key: vec![],
-                pki_encrypted: false,
-                next_hop: 0,
-                relay_node: 0,
-                tx_after: 0,
-                transport_mechanism: 0,
-                payload_variant: Some(protobufs::mesh_packet::PayloadVariant::Decoded(Data {
-                    portnum: i32::from(TracerouteApp),
-                    payload: vec![],
-                    want_response: true,
-                    dest: 0,
-                    source: 0,
-                    request_id: 0,
-                    reply_id: 0,
-                    emoji: 0,
-                    bitfield: None,
-                })),
+    /// Marks one of our outstanding requests as answered (or failed) once a packet referencing
+    /// its id comes back.
+    pub fn resolve_request(&mut self, request_id: u32, reply: RequestReply) {
+        if let Some(request) = self.requests.get_mut(&request_id) {
+            request.state = match reply {
+                RequestReply::Answered => RequestState::Answered(get_secs()),
+                RequestReply::Acknowledged => return,
+                RequestReply::Failed(reason) => RequestState::Failed(reason),
             };
-            let payload_variant = Some(Packet(mesh_packet));
-            if let Err(e) =
-                util::send_to_radio(IPCMessage::ToRadio(ToRadio { payload_variant })).await
-            {
-                error!("Tried sending traceroute but failed: {e}");
-            } else {
-                info!("Emitted Traceroute Request to !{:x}", self.selected_node_id);
+            info!(
+                "{} request to !{:x}: {}",
+                request.kind,
+                request.node_id,
+                request.status()
+            );
+        }
+    }
+
+    pub fn observe_link(&mut self, link: LinkObservation) {
+        if link.from == self.my_node_id {
+            return;
+        }
+        self.node_list
+            .entry(link.from)
+            .or_insert_with(|| ComprehensiveNode::with_id(link.from))
+            .observe(&link);
+    }
+
+    /// Firmware only reports the last byte of a relay's node id, so we name the relay if exactly
+    /// one node we hear directly matches it.
+    fn relay_label(&self, relay: u32) -> String {
+        let candidates = self
+            .node_list
+            .values()
+            .filter(|n| n.id & 0xff == relay & 0xff && n.last_hops == Some(0))
+            .collect_vec();
+        match candidates.as_slice() {
+            [only] => match &only.node_info.user {
+                Some(user) if !user.short_name.is_empty() => user.short_name.clone(),
+                _ => format!("!{:x}", only.id),
+            },
+            _ => format!("!..{:02x}", relay & 0xff),
+        }
+    }
+
+    /// A short description of how this node's packets reach us.
+    fn route_label(&self, cn: &ComprehensiveNode) -> String {
+        if cn.node_info.via_mqtt {
+            return "MQTT".to_string();
+        }
+        match (cn.last_hops, cn.last_relay) {
+            (Some(0), _) => "direct".to_string(),
+            (Some(hops), Some(relay)) => format!("{hops} via {}", self.relay_label(relay)),
+            (Some(hops), None) => hops.to_string(),
+            (None, _) => cn.node_info.hops_away().to_string(),
+        }
+    }
+
+    /// The node whose detail screen is open, if any.
+    pub fn detail_node(&self) -> Option<u32> {
+        match self.display_mode {
+            DisplayMode::Detail => Some(self.selected_node_id),
+            _ => None,
+        }
+    }
+
+    pub async fn char_key(&mut self, c: char) {
+        match c {
+            'p' => self.send_request(NodeRequestKind::Position).await,
+            'n' => self.send_request(NodeRequestKind::NodeInfo).await,
+            'd' => self.send_request(NodeRequestKind::DeviceTelemetry).await,
+            'e' => {
+                self.send_request(NodeRequestKind::EnvironmentTelemetry)
+                    .await
             }
+            'w' => self.send_request(NodeRequestKind::PowerTelemetry).await,
+            _ => {}
         }
     }
     pub fn escape(&mut self) -> Mode {
@@ -625,6 +1128,10 @@ impl NodesTab {
                 self.display_mode = DisplayMode::List;
                 Mode::Running
             }
+            DisplayMode::Chart => {
+                self.display_mode = self.chart_return_mode.clone();
+                Mode::Running
+            }
             DisplayMode::Help => {
                 self.display_mode = DisplayMode::List;
                 Mode::Running
@@ -642,6 +1149,7 @@ impl NodesTab {
                 }
             }
             DisplayMode::Detail => self.display_mode = DisplayMode::List,
+            DisplayMode::Chart => self.display_mode = self.chart_return_mode.clone(),
             DisplayMode::Help => self.display_mode = DisplayMode::List,
         }
     }
@@ -678,6 +1186,22 @@ impl NodesTab {
             self.scrollbar_state = self.scrollbar_state.position(i);
         }
     }
+    /// Jumps to a row of the list, or the last one if `index` is past the end.
+    pub fn select_row(&mut self, index: usize) {
+        if self.display_mode == DisplayMode::List {
+            let i = index.min(self.table_contents.len().saturating_sub(1));
+            self.table_state.select(Some(i));
+            self.scrollbar_state = self.scrollbar_state.position(i);
+        }
+    }
+    /// Moves one row up or down for the scroll wheel, stopping at the ends rather than wrapping.
+    pub fn scroll(&mut self, down: bool) {
+        match (self.table_state.selected(), down) {
+            (Some(i), true) => self.select_row(i.saturating_add(1)),
+            (Some(i), false) => self.select_row(i.saturating_sub(1)),
+            (None, _) => self.select_row(0),
+        }
+    }
     pub fn next_page(&mut self) {
         if self.display_mode == DisplayMode::List {
             let i = match self.table_state.selected() {
@@ -713,11 +1237,47 @@ impl NodesTab {
         match num {
             1 => self.display_mode = DisplayMode::Help,
             2 => self.send_traceroute().await,
+            3 => self.cycle_chart_window(),
+            4 => self.cycle_overlay(),
+            5 => self.toggle_chart_mode(),
+            6 => self.toggle_compare(),
             _ => {}
         }
     }
 }
 
+/// The node's public key fingerprint, and a warning if it isn't the one we pinned.
+fn public_key_rows<'a>(cn: &ComprehensiveNode, user: &User) -> Vec<Row<'a>> {
+    if user.public_key.is_empty() {
+        return vec![Row::new(vec![
+            "Public Key".to_string(),
+            "none (no PKI)".to_string(),
+        ])];
+    }
+    let verified = match cn.node_info.is_key_manually_verified {
+        true => " (verified)",
+        false => "",
+    };
+    let mut rows = vec![Row::new(vec![
+        "Public Key".to_string(),
+        format!("{}{verified}", pki::fingerprint(&user.public_key)),
+    ])];
+    let pinned = KEY_PINS
+        .try_read()
+        .ok()
+        .and_then(|pins| pins.pinned(cn.id).map(<[u8]>::to_vec));
+    if let Some(pinned) = pinned.filter(|p| *p != user.public_key) {
+        rows.push(
+            Row::new(vec![
+                "KEY CHANGED".to_string(),
+                format!("pinned {}; K trusts the new key", pki::fingerprint(&pinned)),
+            ])
+            .style(THEME.key_warning),
+        );
+    }
+    rows
+}
+
 impl Widget for NodesTab {
     fn render(mut self, area: Rect, buf: &mut Buffer) {
         // since this fn is operating on a copy of the messagestab struct, there
@@ -764,13 +1324,17 @@ impl Widget for NodesTab {
                 Widget::render(popup_block, area, buf);
                 self.get_details_for_node(area, buf);
             }
+            DisplayMode::Chart => {
+                Widget::render(Clear, area, buf);
+                self.make_graph(area, buf);
+            }
             DisplayMode::List => {
                 let node_list_constraints = vec![
                     Constraint::Max(10),    // ID
                     Constraint::Max(5),     // ShortName
                     Constraint::Max(25),    // LongName
                     Constraint::Max(25),    // RF Details
-                    Constraint::Max(5),     // Hops
+                    Constraint::Max(12),    // Hops
                     Constraint::Max(10),    // Neighbors
                     Constraint::Length(12), // Distance
                     Constraint::Length(10), // Latitude
@@ -824,10 +1388,7 @@ impl Widget for NodesTab {
                             }
                         }
 
-                        let hops: String = match cn.node_info.via_mqtt {
-                            true => "MQTT".to_string(),
-                            false => cn.node_info.hops_away().to_string(),
-                        };
+                        let hops = self.route_label(cn);
 
                         let now_secs = get_secs();
                         let mut ni_lastheard_since_string = "Unknown".to_string();
@@ -891,6 +1452,10 @@ impl Widget for NodesTab {
                         // I don't want to blocking read every loop iteration so we'll cheat and set
                         // self.prefs here, avoiding ::new(),::default() adjusting shenanigans.
 
+                        let row_style = match self.compare_nodes.contains(&cn.id) {
+                            true => THEME.nodes.compare_marked,
+                            false => THEME.nodes.list,
+                        };
                         Row::new(vec![
                             user_id_str,
                             user.short_name,
@@ -907,6 +1472,7 @@ impl Widget for NodesTab {
                             ni_lastheard_since_string,
                             lastupdate_since_string,
                         ])
+                        .style(row_style)
                     })
                     .collect_vec();
 
@@ -942,6 +1508,7 @@ impl Widget for NodesTab {
                     .style(THEME.tabs_selected)
                     .end_symbol(None);
 
+                mouse::table(area, true, 2, &self.table_state, rows.len());
                 StatefulWidget::render(
                     Table::new(rows, node_list_constraints)
                         .block(block)
@@ -0,0 +1,225 @@
+use crate::consts;
+use ratatui::prelude::*;
+use strum::{Display, EnumIter, FromRepr};
+use time::OffsetDateTime;
+
+#[derive(Debug, Clone, Copy, Default, Display, EnumIter, FromRepr, PartialEq, Eq)]
+pub enum ChartWindow {
+    #[default]
+    All,
+    Hour,
+    SixHours,
+    Day,
+    Week,
+}
+
+impl ChartWindow {
+    pub fn seconds(self) -> Option<u64> {
+        match self {
+            ChartWindow::All => None,
+            ChartWindow::Hour => Some(3600),
+            ChartWindow::SixHours => Some(6 * 3600),
+            ChartWindow::Day => Some(24 * 3600),
+            ChartWindow::Week => Some(7 * 24 * 3600),
+        }
+    }
+    pub fn label(self) -> &'static str {
+        match self {
+            ChartWindow::All => "all",
+            ChartWindow::Hour => "1h",
+            ChartWindow::SixHours => "6h",
+            ChartWindow::Day => "24h",
+            ChartWindow::Week => "7d",
+        }
+    }
+    /// Moves to the next window, skipping windows that our history is too short to fill.  A
+    /// window is only offered once we hold more history than the window before it covers.
+    pub fn next_available(self, history_secs: u64) -> Self {
+        let mut candidate = self;
+        loop {
+            candidate = Self::from_repr(candidate as usize + 1).unwrap_or_default();
+            let previous = Self::from_repr((candidate as usize).saturating_sub(1))
+                .and_then(|w| w.seconds())
+                .unwrap_or(0);
+            if candidate == ChartWindow::All || history_secs > previous {
+                return candidate;
+            }
+        }
+    }
+}
+
+/// One line on a chart, along with the readouts shown beneath it.
+pub struct ChartSeries {
+    pub name: String,
+    pub points: Vec<(f64, f64)>,
+    pub stats: Option<SeriesStats>,
+}
+
+impl ChartSeries {
+    pub fn new(name: String, points: Vec<(f64, f64)>) -> Self {
+        let stats = SeriesStats::from_points(&points);
+        ChartSeries {
+            name,
+            points,
+            stats,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SeriesStats {
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub last: f64,
+}
+
+impl SeriesStats {
+    /// Computes readouts for a series of (timestamp, value) points ordered oldest first.
+    pub fn from_points(points: &[(f64, f64)]) -> Option<Self> {
+        let last = points.last()?.1;
+        let (min, max, sum) = points
+            .iter()
+            .fold((f64::MAX, f64::MIN, 0.0_f64), |(min, max, sum), (_, v)| {
+                (min.min(*v), max.max(*v), sum + v)
+            });
+        Some(SeriesStats {
+            min,
+            max,
+            avg: sum / points.len() as f64,
+            last,
+        })
+    }
+    pub fn summary(&self) -> String {
+        format!(
+            "min {:.2} max {:.2} avg {:.2} last {:.2}",
+            self.min, self.max, self.avg, self.last
+        )
+    }
+}
+
+/// Produces `count` evenly spaced wall-clock (UTC) labels between two unix timestamps.
+pub fn time_labels(low: f64, high: f64, count: usize) -> Vec<Span<'static>> {
+    let format = if high - low > 24.0 * 3600.0 {
+        consts::CHART_DATE_FORMAT
+    } else {
+        consts::CHART_TIME_FORMAT
+    };
+    let steps = count.saturating_sub(1).max(1);
+    (0..=steps)
+        .map(|i| {
+            let ts = low + (high - low) * (i as f64 / steps as f64);
+            let label = OffsetDateTime::from_unix_timestamp(ts as i64)
+                .ok()
+                .and_then(|dt| dt.format(format).ok())
+                .unwrap_or_default();
+            Span::raw(label)
+        })
+        .collect()
+}
+
+/// Linearly maps points from one value range into another, so a series with different units
+/// can share the y-axis of the primary series.
+pub fn rescale(points: &[(f64, f64)], from: (f64, f64), to: (f64, f64)) -> Vec<(f64, f64)> {
+    let from_span = from.1 - from.0;
+    points
+        .iter()
+        .map(|(ts, v)| {
+            let ratio = if from_span == 0.0 {
+                0.5
+            } else {
+                (v - from.0) / from_span
+            };
+            (*ts, to.0 + ratio * (to.1 - to.0))
+        })
+        .collect()
+}
+
+/// Returns y-axis bounds covering every point in every series, widened if the data is flat.
+pub fn value_bounds<'a>(series: impl Iterator<Item = &'a [(f64, f64)]>) -> (f64, f64) {
+    let (low, high) = series
+        .flat_map(|s| s.iter())
+        .fold((f64::MAX, f64::MIN), |(low, high), (_, v)| {
+            (low.min(*v), high.max(*v))
+        });
+    if low > high {
+        return (0.0, 1.0);
+    }
+    if low == high {
+        return (low - 1.0, high + 1.0);
+    }
+    (low, high)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn series_stats() {
+        assert_eq!(SeriesStats::from_points(&[]), None);
+        let stats = SeriesStats::from_points(&[(0.0, 1.0), (1.0, 4.0), (2.0, 1.0)]).unwrap();
+        assert_eq!(
+            stats,
+            SeriesStats {
+                min: 1.0,
+                max: 4.0,
+                avg: 2.0,
+                last: 1.0
+            }
+        );
+        assert_eq!(stats.summary(), "min 1.00 max 4.00 avg 2.00 last 1.00");
+    }
+
+    #[test]
+    fn windows_need_the_history_to_fill_them() {
+        assert_eq!(ChartWindow::All.next_available(0), ChartWindow::All);
+        assert_eq!(ChartWindow::All.next_available(60), ChartWindow::Hour);
+        // an hour and a bit of history is worth a six hour window, but not a day
+        assert_eq!(
+            ChartWindow::Hour.next_available(4000),
+            ChartWindow::SixHours
+        );
+        assert_eq!(ChartWindow::SixHours.next_available(4000), ChartWindow::All);
+        assert_eq!(ChartWindow::Week.next_available(u64::MAX), ChartWindow::All);
+    }
+
+    #[test]
+    fn time_labels_span_the_range() {
+        let labels: Vec<String> = time_labels(0.0, 3600.0, 3)
+            .into_iter()
+            .map(|s| s.content.into_owned())
+            .collect();
+        assert_eq!(labels, ["00:00", "00:30", "01:00"]);
+        // more than a day shows dates
+        let labels = time_labels(0.0, 3.0 * 86400.0, 2);
+        assert_eq!(labels.len(), 2);
+        assert_ne!(labels[1].content, "00:00");
+        // a single label still gets both ends
+        assert_eq!(time_labels(0.0, 60.0, 1).len(), 2);
+    }
+
+    #[test]
+    fn value_bounds_cover_every_series() {
+        let a = [(0.0, 2.0), (1.0, 5.0)];
+        let b = [(0.0, -1.0)];
+        assert_eq!(value_bounds([&a[..], &b[..]].into_iter()), (-1.0, 5.0));
+        assert_eq!(value_bounds([&[(0.0, 3.0)][..]].into_iter()), (2.0, 4.0));
+        assert_eq!(value_bounds(std::iter::empty()), (0.0, 1.0));
+    }
+
+    #[test]
+    fn rescale_maps_ranges() {
+        let points = rescale(
+            &[(7.0, 0.0), (8.0, 5.0), (9.0, 10.0)],
+            (0.0, 10.0),
+            (100.0, 200.0),
+        );
+        assert_eq!(points, [(7.0, 100.0), (8.0, 150.0), (9.0, 200.0)]);
+        // a flat series sits in the middle
+        assert_eq!(
+            rescale(&[(1.0, 3.0)], (3.0, 3.0), (0.0, 10.0)),
+            [(1.0, 5.0)]
+        );
+    }
+}
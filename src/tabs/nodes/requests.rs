@@ -0,0 +1,148 @@
+use crate::util::{self, get_secs};
+use meshtastic::protobufs::telemetry::Variant;
+use meshtastic::protobufs::{
+    mesh_packet, routing, Data, DeviceMetrics, EnvironmentMetrics, MeshPacket, PortNum, Position,
+    PowerMetrics, Telemetry, User,
+};
+use meshtastic::Message;
+use strum::Display;
+
+/// How long we wait on a reply before we stop showing a request as pending.
+const REQUEST_TIMEOUT_SECS: u64 = 120;
+const SPINNER: [&str; 8] = ["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"];
+
+#[derive(Debug, Clone, Copy, Display, PartialEq, Eq, Hash)]
+pub enum NodeRequestKind {
+    Position,
+    NodeInfo,
+    DeviceTelemetry,
+    EnvironmentTelemetry,
+    PowerTelemetry,
+}
+
+impl NodeRequestKind {
+    pub fn portnum(self) -> PortNum {
+        match self {
+            NodeRequestKind::Position => PortNum::PositionApp,
+            NodeRequestKind::NodeInfo => PortNum::NodeinfoApp,
+            NodeRequestKind::DeviceTelemetry
+            | NodeRequestKind::EnvironmentTelemetry
+            | NodeRequestKind::PowerTelemetry => PortNum::TelemetryApp,
+        }
+    }
+
+    /// The firmware answers a request with the same kind of message it was sent, so each
+    /// request carries an (empty) example of what we want back.  Node info requests carry our
+    /// own user, which the remote side will also learn from.
+    pub fn payload(self, me: Option<&User>) -> Vec<u8> {
+        let telemetry = |variant| Telemetry {
+            time: get_secs() as u32,
+            variant: Some(variant),
+        };
+        match self {
+            NodeRequestKind::Position => Position::default().encode_to_vec(),
+            NodeRequestKind::NodeInfo => me.cloned().unwrap_or_default().encode_to_vec(),
+            NodeRequestKind::DeviceTelemetry => {
+                telemetry(Variant::DeviceMetrics(DeviceMetrics::default())).encode_to_vec()
+            }
+            NodeRequestKind::EnvironmentTelemetry => {
+                telemetry(Variant::EnvironmentMetrics(EnvironmentMetrics::default()))
+                    .encode_to_vec()
+            }
+            NodeRequestKind::PowerTelemetry => {
+                telemetry(Variant::PowerMetrics(PowerMetrics::default())).encode_to_vec()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RequestState {
+    Pending,
+    Answered(u64),
+    Failed(routing::Error),
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeRequest {
+    pub node_id: u32,
+    pub kind: NodeRequestKind,
+    pub sent_at: u64,
+    pub state: RequestState,
+}
+
+impl NodeRequest {
+    pub fn new(node_id: u32, kind: NodeRequestKind) -> Self {
+        NodeRequest {
+            node_id,
+            kind,
+            sent_at: get_secs(),
+            state: RequestState::Pending,
+        }
+    }
+
+    pub fn status(&self) -> String {
+        let now = get_secs();
+        match self.state {
+            RequestState::Pending if now.saturating_sub(self.sent_at) > REQUEST_TIMEOUT_SECS => {
+                "no reply".to_string()
+            }
+            RequestState::Pending => {
+                let millis = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis();
+                let frame = SPINNER[(millis / 250) as usize % SPINNER.len()];
+                format!("{frame} waiting ({}s)", now.saturating_sub(self.sent_at))
+            }
+            RequestState::Answered(at) => {
+                format!("refreshed {}s ago", now.saturating_sub(at))
+            }
+            RequestState::Failed(reason) => format!("failed: {}", reason.as_str_name()),
+        }
+    }
+}
+
+/// Builds a packet addressed to a single node with `want_response` set, the shape of every
+/// query we send out onto the mesh.
+pub fn request_packet(
+    to: u32,
+    channel: u32,
+    id: u32,
+    portnum: PortNum,
+    payload: Vec<u8>,
+) -> MeshPacket {
+    #[allow(deprecated)]
+    MeshPacket {
+        from: 0,
+        to,
+        channel,
+        id,
+        rx_time: 0,
+        rx_snr: 0.0,
+        hop_limit: util::configured_hop_limit(),
+        want_ack: true,
+        priority: 0,
+        rx_rssi: 0,
+        delayed: 0,
+        via_mqtt: false,
+        hop_start: 0,
+        public_key: vec![],
+        pki_encrypted: false,
+        next_hop: 0,
+        relay_node: 0,
+        tx_after: 0,
+        transport_mechanism: 0,
+        payload_variant: Some(mesh_packet::PayloadVariant::Decoded(Data {
+            portnum: i32::from(portnum),
+            payload,
+            want_response: true,
+            dest: 0,
+            source: 0,
+            request_id: 0,
+            reply_id: 0,
+            emoji: 0,
+            bitfield: None,
+        })),
+    }
+}
@@ -0,0 +1,335 @@
+use crate::app::Mode;
+use crate::export;
+use crate::mouse;
+use crate::theme::THEME;
+use crate::util::get_secs;
+use crate::PREFERENCES;
+use geoutils::Location;
+use itertools::Itertools;
+use meshtastic::protobufs::Position;
+use ratatui::{prelude::*, widgets::*};
+use std::collections::BTreeMap;
+
+/// One `seq N` packet from a node running the range test module.
+#[derive(Debug, Clone)]
+pub struct RangeTestHit {
+    pub timestamp: u64,
+    pub from: u32,
+    pub from_name: String,
+    pub seq: u32,
+    pub sender_position: Option<(f64, f64)>,
+    pub our_position: Option<(f64, f64)>,
+    pub distance_m: Option<f64>,
+    pub rx_snr: f32,
+    pub rx_rssi: i32,
+}
+
+/// Parses the payload the range test module sends, which is just `seq <number>`.
+pub fn parse_seq(payload: &str) -> Option<u32> {
+    payload.trim().strip_prefix("seq ")?.trim().parse().ok()
+}
+
+/// Running totals for one sender.  Loss is worked out from gaps in the sequence numbers; a
+/// sequence that goes backwards means the sender restarted, so it starts a new run.
+#[derive(Debug, Clone, Default)]
+pub struct SenderSummary {
+    pub name: String,
+    pub received: u32,
+    pub expected: u32,
+    pub last_seq: Option<u32>,
+    pub max_distance_m: Option<f64>,
+    pub last_snr: f32,
+    pub last_rssi: i32,
+}
+
+impl SenderSummary {
+    /// Returns false for a repeat of the last sequence number, which we've already counted.
+    fn count(&mut self, hit: &RangeTestHit) -> bool {
+        match self.last_seq {
+            Some(last) if hit.seq == last => return false,
+            Some(last) if hit.seq > last => self.expected += hit.seq - last,
+            _ => self.expected += 1,
+        }
+        self.received += 1;
+        self.last_seq = Some(hit.seq);
+        self.name = hit.from_name.clone();
+        self.last_snr = hit.rx_snr;
+        self.last_rssi = hit.rx_rssi;
+        if let Some(d) = hit.distance_m {
+            self.max_distance_m = Some(self.max_distance_m.map_or(d, |m| m.max(d)));
+        }
+        true
+    }
+
+    pub fn loss_percent(&self) -> f64 {
+        match self.expected {
+            0 => 0.0,
+            e => 100.0 * (e - self.received) as f64 / e as f64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RangeTestTab {
+    pub my_position: Option<Position>,
+    recording: bool,
+    started: u64,
+    hits: Vec<RangeTestHit>,
+    senders: BTreeMap<u32, SenderSummary>,
+    ignored: u32,
+    table_state: TableState,
+}
+
+impl RangeTestTab {
+    /// Files a range test packet.  Packets that arrive while we aren't recording are only counted.
+    pub fn record(&mut self, mut hit: RangeTestHit) {
+        if !self.recording {
+            debug!(
+                "Range test seq {} from {} (not recording)",
+                hit.seq, hit.from_name
+            );
+            self.ignored += 1;
+            return;
+        }
+        hit.our_position = self
+            .my_position
+            .as_ref()
+            .and_then(crate::util::position_degrees);
+        hit.distance_m = match (hit.our_position, hit.sender_position) {
+            (Some(us), Some(them)) => Some(
+                Location::new(us.0, us.1)
+                    .haversine_distance_to(&Location::new(them.0, them.1))
+                    .meters(),
+            ),
+            _ => None,
+        };
+        if self.senders.entry(hit.from).or_default().count(&hit) {
+            self.hits.push(hit);
+        }
+    }
+
+    fn toggle_recording(&mut self) {
+        self.recording = !self.recording;
+        if self.recording {
+            self.started = get_secs();
+            self.hits.clear();
+            self.senders.clear();
+            self.ignored = 0;
+            self.table_state.select(None);
+            info!("Range test session started");
+        } else {
+            info!(
+                "Range test session stopped after {} packet(s)",
+                self.hits.len()
+            );
+        }
+    }
+
+    async fn export(&self) {
+        if self.hits.is_empty() {
+            warn!("No range test packets to export");
+            return;
+        }
+        let export_dir = PREFERENCES.read().await.export_dir.clone();
+        match export::export_range_test(&export_dir, &self.hits) {
+            Ok(dir) => info!("Exported range test session to {}", dir.display()),
+            Err(e) => error!("Unable to export range test session: {e:#}"),
+        }
+    }
+
+    pub async fn function_key(&mut self, num: u8) {
+        match num {
+            2 => self.toggle_recording(),
+            3 => self.export().await,
+            _ => {}
+        }
+    }
+
+    pub fn escape(&self) -> Mode {
+        Mode::Exiting
+    }
+
+    pub fn prev_row(&mut self) {
+        let i = self.table_state.selected().unwrap_or(0).saturating_sub(1);
+        self.table_state.select(Some(i));
+    }
+
+    pub fn next_row(&mut self) {
+        let i = match self.table_state.selected() {
+            Some(i) => i.saturating_add(1).min(self.hits.len().saturating_sub(1)),
+            None => 0,
+        };
+        self.table_state.select(Some(i));
+    }
+
+    /// Jumps to a row, or the last one if `index` is past the end.
+    pub fn select_row(&mut self, index: usize) {
+        self.table_state
+            .select(Some(index.min(self.hits.len().saturating_sub(1))));
+    }
+
+    fn title(&self) -> String {
+        let status = match (self.recording, self.started) {
+            (true, _) => format!("recording since {}", export::format_timestamp(self.started)),
+            (false, 0) => "not recording".to_string(),
+            (false, _) => "stopped".to_string(),
+        };
+        let ignored = match self.ignored {
+            0 => "".to_string(),
+            n => format!(", {n} packet(s) heard while stopped"),
+        };
+        format!("Range test: {status}{ignored} (F2 start/stop, F3 export)")
+    }
+
+    fn render_summary(&self, area: Rect, buf: &mut Buffer) {
+        let rows = self.senders.iter().map(|(id, s)| {
+            Row::new(vec![
+                format!("{} (!{id:x})", s.name),
+                s.received.to_string(),
+                s.expected.to_string(),
+                format!("{:.1}%", s.loss_percent()),
+                s.last_seq.map(|q| q.to_string()).unwrap_or_default(),
+                s.max_distance_m.map(format_distance).unwrap_or_default(),
+                format!("{:.2}", s.last_snr),
+                s.last_rssi.to_string(),
+            ])
+        });
+        let header = Row::new(vec![
+            "Sender", "Received", "Expected", "Loss", "Last seq", "Max dist", "SNR", "RSSI",
+        ])
+        .style(THEME.message_header);
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Min(20),
+                Constraint::Length(9),
+                Constraint::Length(9),
+                Constraint::Length(7),
+                Constraint::Length(9),
+                Constraint::Length(10),
+                Constraint::Length(7),
+                Constraint::Length(5),
+            ],
+        )
+        .header(header)
+        .block(
+            Block::new()
+                .borders(Borders::ALL)
+                .title(self.title())
+                .title_alignment(Alignment::Center)
+                .border_set(symbols::border::DOUBLE)
+                .style(THEME.middle),
+        );
+        Widget::render(table, area, buf);
+    }
+
+    fn render_hits(&mut self, area: Rect, buf: &mut Buffer) {
+        let coordinates = |p: Option<(f64, f64)>| match p {
+            Some((lat, lon)) => format!("{lat:.5}, {lon:.5}"),
+            None => "".to_string(),
+        };
+        let rows = self
+            .hits
+            .iter()
+            .rev()
+            .map(|hit| {
+                Row::new(vec![
+                    export::format_timestamp(hit.timestamp),
+                    hit.from_name.clone(),
+                    hit.seq.to_string(),
+                    coordinates(hit.sender_position),
+                    coordinates(hit.our_position),
+                    hit.distance_m.map(format_distance).unwrap_or_default(),
+                    format!("{:.2}", hit.rx_snr),
+                    hit.rx_rssi.to_string(),
+                ])
+            })
+            .collect_vec();
+        let header = Row::new(vec![
+            "Time",
+            "Sender",
+            "Seq",
+            "Sender at",
+            "We were at",
+            "Distance",
+            "SNR",
+            "RSSI",
+        ])
+        .style(THEME.message_header);
+        mouse::table(area, true, 1, &self.table_state, rows.len());
+        StatefulWidget::render(
+            Table::new(
+                rows,
+                [
+                    Constraint::Length(20),
+                    Constraint::Max(10),
+                    Constraint::Length(6),
+                    Constraint::Length(22),
+                    Constraint::Length(22),
+                    Constraint::Length(10),
+                    Constraint::Length(7),
+                    Constraint::Length(5),
+                ],
+            )
+            .header(header)
+            .block(
+                Block::new()
+                    .borders(Borders::ALL)
+                    .title("Packets, newest first")
+                    .style(THEME.middle),
+            )
+            .highlight_style(THEME.tabs_selected),
+            area,
+            buf,
+            &mut self.table_state,
+        );
+    }
+}
+
+fn format_distance(meters: f64) -> String {
+    format!("{:.3}km", meters / 1000.0)
+}
+
+impl Widget for RangeTestTab {
+    fn render(mut self, area: Rect, buf: &mut Buffer) {
+        let summary_height = self.senders.len() as u16 + 3;
+        let [summary, hits] = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(summary_height.max(4)),
+                Constraint::Min(0),
+            ])
+            .areas(area);
+        self.render_summary(summary, buf);
+        self.render_hits(hits, buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_range_test_payloads() {
+        assert_eq!(parse_seq("seq 12"), Some(12));
+        assert_eq!(parse_seq("  seq 7\n"), Some(7));
+        assert_eq!(parse_seq("seq 4294967295"), Some(u32::MAX));
+    }
+
+    #[test]
+    fn seq_needs_a_number() {
+        assert_eq!(parse_seq("seq"), None);
+        assert_eq!(parse_seq("seq "), None);
+        assert_eq!(parse_seq("seq -1"), None);
+        assert_eq!(parse_seq("seq 4294967296"), None);
+    }
+
+    #[test]
+    fn ordinary_text_is_not_a_range_test() {
+        assert_eq!(parse_seq("sequence 5"), None);
+        assert_eq!(parse_seq("seq 12 is my favourite"), None);
+        assert_eq!(parse_seq("seq12"), None);
+        assert_eq!(parse_seq("Seq 12"), None);
+        assert_eq!(parse_seq("see you at 12"), None);
+    }
+}
@@ -0,0 +1,527 @@
+use crate::app::Mode;
+use crate::consts::GPS_PRECISION_FACTOR;
+use crate::ipc::IPCMessage;
+use crate::mouse;
+use crate::theme::THEME;
+use crate::util::{self, get_channel_from_id, get_secs};
+use anyhow::{anyhow, Result};
+use geoutils::Location;
+use itertools::Itertools;
+use meshtastic::protobufs::to_radio::PayloadVariant::Packet;
+use meshtastic::protobufs::{PortNum, Position, ToRadio, Waypoint};
+use meshtastic::Message;
+use pretty_duration::pretty_duration;
+use ratatui::{prelude::*, widgets::*};
+use std::collections::HashMap;
+use std::ops::Div;
+use std::time::Duration;
+use strum::{Display, EnumIter, FromRepr, IntoEnumIterator};
+
+const DEFAULT_ICON: char = '📍';
+/// Waypoints are deleted by re-broadcasting them with an expiry in the past.
+const DELETED_EXPIRY: u32 = 1;
+
+/// A waypoint as we heard it, along with who sent it and where.
+#[derive(Debug, Clone)]
+pub struct WaypointEntry {
+    pub waypoint: Waypoint,
+    pub owner: u32,
+    pub owner_name: String,
+    pub channel: u32,
+}
+
+impl WaypointEntry {
+    pub fn expired(&self) -> bool {
+        self.waypoint.expire != 0 && (self.waypoint.expire as u64) <= get_secs()
+    }
+    pub(crate) fn location(&self) -> Option<(f64, f64)> {
+        let lat = self.waypoint.latitude_i? as f64 * GPS_PRECISION_FACTOR as f64;
+        let lon = self.waypoint.longitude_i? as f64 * GPS_PRECISION_FACTOR as f64;
+        Some((lat, lon))
+    }
+    fn icon(&self) -> String {
+        char::from_u32(self.waypoint.icon)
+            .filter(|_| self.waypoint.icon != 0)
+            .unwrap_or(DEFAULT_ICON)
+            .to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+enum WaypointDisplayMode {
+    #[default]
+    List,
+    Form,
+}
+
+#[derive(Debug, Clone, Copy, Default, Display, EnumIter, FromRepr, PartialEq, Eq)]
+enum FormField {
+    #[default]
+    Name,
+    Description,
+    Latitude,
+    Longitude,
+    Icon,
+    #[strum(to_string = "Expires in (hours, 0 = never)")]
+    ExpiresInHours,
+    #[strum(to_string = "Locked to us")]
+    Locked,
+    Channel,
+}
+
+/// The waypoint being created or edited, kept in the units people type.
+#[derive(Debug, Clone, Default)]
+struct WaypointForm {
+    waypoint: Waypoint,
+    latitude: f64,
+    longitude: f64,
+    expires_in_hours: u32,
+    locked: bool,
+    channel: u32,
+}
+
+impl WaypointForm {
+    fn value(&self, field: FormField) -> String {
+        match field {
+            FormField::Name => self.waypoint.name.clone(),
+            FormField::Description => self.waypoint.description.clone(),
+            FormField::Latitude => format!("{:.7}", self.latitude),
+            FormField::Longitude => format!("{:.7}", self.longitude),
+            FormField::Icon => char::from_u32(self.waypoint.icon)
+                .unwrap_or(DEFAULT_ICON)
+                .to_string(),
+            FormField::ExpiresInHours => self.expires_in_hours.to_string(),
+            FormField::Locked => match self.locked {
+                true => "yes".to_string(),
+                false => "no".to_string(),
+            },
+            FormField::Channel => {
+                let name = get_channel_from_id(self.channel)
+                    .and_then(|c| c.settings)
+                    .map(|s| s.name)
+                    .unwrap_or_default();
+                format!("{} {name}", self.channel)
+            }
+        }
+    }
+
+    fn set(&mut self, field: FormField, input: &str) -> Result<()> {
+        let input = input.trim();
+        match field {
+            FormField::Name => self.waypoint.name = input.to_string(),
+            FormField::Description => self.waypoint.description = input.to_string(),
+            FormField::Latitude => self.latitude = input.parse()?,
+            FormField::Longitude => self.longitude = input.parse()?,
+            FormField::Icon => {
+                self.waypoint.icon = input.chars().next().unwrap_or(DEFAULT_ICON) as u32;
+            }
+            FormField::ExpiresInHours => self.expires_in_hours = input.parse()?,
+            FormField::Locked => {
+                self.locked = matches!(input.to_lowercase().as_str(), "y" | "yes" | "true" | "1")
+            }
+            FormField::Channel => {
+                let index = input
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or_default()
+                    .parse()?;
+                if get_channel_from_id(index).is_none() {
+                    return Err(anyhow!("there is no channel {index}"));
+                }
+                self.channel = index;
+            }
+        }
+        Ok(())
+    }
+
+    fn to_waypoint(&self, my_node_id: u32) -> Waypoint {
+        let mut waypoint = self.waypoint.clone();
+        if waypoint.id == 0 {
+            waypoint.id = meshtastic::utils::generate_rand_id();
+        }
+        waypoint.latitude_i = Some((self.latitude / GPS_PRECISION_FACTOR as f64).round() as i32);
+        waypoint.longitude_i = Some((self.longitude / GPS_PRECISION_FACTOR as f64).round() as i32);
+        waypoint.expire = match self.expires_in_hours {
+            0 => 0,
+            h => (get_secs() + h as u64 * 3600) as u32,
+        };
+        waypoint.locked_to = match self.locked {
+            true => my_node_id,
+            false => 0,
+        };
+        waypoint
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WaypointsTab {
+    pub waypoints: HashMap<u32, WaypointEntry>,
+    table_state: TableState,
+    table_contents: Vec<WaypointEntry>,
+    pub my_node_id: u32,
+    pub my_position: Option<Position>,
+    display_mode: WaypointDisplayMode,
+    form: WaypointForm,
+    form_row: usize,
+}
+
+impl WaypointsTab {
+    pub async fn run(&mut self) {
+        self.table_contents = self
+            .waypoints
+            .values()
+            .filter(|w| !w.expired())
+            .cloned()
+            .sorted_by_key(|w| w.waypoint.name.to_lowercase())
+            .collect();
+    }
+
+    /// Files a waypoint heard on the mesh.  A waypoint re-sent with an expiry in the past is
+    /// how other clients delete one.
+    pub fn upsert(&mut self, entry: WaypointEntry) {
+        if entry.expired() {
+            if self.waypoints.remove(&entry.waypoint.id).is_some() {
+                info!(
+                    "Waypoint {} was deleted by {}",
+                    entry.waypoint.name, entry.owner_name
+                );
+            }
+            return;
+        }
+        info!("Waypoint {} from {}", entry.waypoint.name, entry.owner_name);
+        self.waypoints.insert(entry.waypoint.id, entry);
+    }
+
+    pub fn escape(&mut self) -> Mode {
+        match self.display_mode {
+            WaypointDisplayMode::Form => {
+                self.display_mode = WaypointDisplayMode::List;
+                Mode::Running
+            }
+            WaypointDisplayMode::List => Mode::Exiting,
+        }
+    }
+
+    /// In the list, opens the highlighted waypoint for editing.  In the form, returns the value
+    /// of the highlighted field so it can be edited.
+    pub fn enter_key(&mut self) -> Option<String> {
+        match self.display_mode {
+            WaypointDisplayMode::List => {
+                if let Some(entry) = self.selected().cloned() {
+                    if self.locked_to_someone_else(&entry) {
+                        return None;
+                    }
+                    let (latitude, longitude) = entry.location().unwrap_or_default();
+                    self.form = WaypointForm {
+                        latitude,
+                        longitude,
+                        expires_in_hours: match entry.waypoint.expire {
+                            0 => 0,
+                            e => (e as u64).saturating_sub(get_secs()).div_ceil(3600) as u32,
+                        },
+                        locked: entry.waypoint.locked_to != 0,
+                        channel: entry.channel,
+                        waypoint: entry.waypoint,
+                    };
+                    self.form_row = 0;
+                    self.display_mode = WaypointDisplayMode::Form;
+                }
+                None
+            }
+            WaypointDisplayMode::Form => Some(self.form.value(self.form_field())),
+        }
+    }
+
+    pub fn edit_title(&self) -> String {
+        format!("{}", self.form_field())
+    }
+
+    pub fn apply_edit(&mut self, input: &str) {
+        let field = self.form_field();
+        if let Err(e) = self.form.set(field, input) {
+            error!("Couldn't set {field} to {input}: {e}");
+        }
+    }
+
+    fn form_field(&self) -> FormField {
+        FormField::from_repr(self.form_row).unwrap_or_default()
+    }
+
+    fn selected(&self) -> Option<&WaypointEntry> {
+        self.table_state
+            .selected()
+            .and_then(|i| self.table_contents.get(i))
+    }
+
+    fn locked_to_someone_else(&self, entry: &WaypointEntry) -> bool {
+        let locked_to = entry.waypoint.locked_to;
+        if locked_to != 0 && locked_to != self.my_node_id {
+            warn!(
+                "Waypoint {} is locked to !{:x}, so only it can change it",
+                entry.waypoint.name, locked_to
+            );
+            return true;
+        }
+        false
+    }
+
+    pub fn prev_row(&mut self) {
+        match self.display_mode {
+            WaypointDisplayMode::Form => self.form_row = self.form_row.saturating_sub(1),
+            WaypointDisplayMode::List => {
+                let i = self.table_state.selected().unwrap_or(0).saturating_sub(1);
+                self.table_state.select(Some(i));
+            }
+        }
+    }
+
+    pub fn next_row(&mut self) {
+        match self.display_mode {
+            WaypointDisplayMode::Form => {
+                self.form_row = self
+                    .form_row
+                    .saturating_add(1)
+                    .min(FormField::iter().count() - 1)
+            }
+            WaypointDisplayMode::List => {
+                let i = match self.table_state.selected() {
+                    Some(i) => i
+                        .saturating_add(1)
+                        .min(self.table_contents.len().saturating_sub(1)),
+                    None => 0,
+                };
+                self.table_state.select(Some(i));
+            }
+        }
+    }
+
+    /// Jumps to a row, or the last one if `index` is past the end.
+    pub fn select_row(&mut self, index: usize) {
+        match self.display_mode {
+            WaypointDisplayMode::Form => self.form_row = index.min(FormField::iter().count() - 1),
+            WaypointDisplayMode::List => self
+                .table_state
+                .select(Some(index.min(self.table_contents.len().saturating_sub(1)))),
+        }
+    }
+
+    pub async fn function_key(&mut self, num: u8) {
+        match (num, &self.display_mode) {
+            (2, WaypointDisplayMode::List) => self.new_waypoint(),
+            (2, WaypointDisplayMode::Form) => self.send_form().await,
+            (3, WaypointDisplayMode::List) => self.delete_selected().await,
+            _ => {}
+        }
+    }
+
+    fn new_waypoint(&mut self) {
+        let (latitude, longitude) = self.my_location().unwrap_or_default();
+        self.form = WaypointForm {
+            waypoint: Waypoint {
+                icon: DEFAULT_ICON as u32,
+                ..Default::default()
+            },
+            latitude,
+            longitude,
+            ..Default::default()
+        };
+        self.form_row = 0;
+        self.display_mode = WaypointDisplayMode::Form;
+    }
+
+    async fn send_form(&mut self) {
+        if self.form.waypoint.name.is_empty() {
+            warn!("Give the waypoint a name before sending it");
+            return;
+        }
+        let waypoint = self.form.to_waypoint(self.my_node_id);
+        if self.broadcast(&waypoint, self.form.channel).await {
+            self.upsert(WaypointEntry {
+                waypoint,
+                owner: self.my_node_id,
+                owner_name: "us".to_string(),
+                channel: self.form.channel,
+            });
+            self.display_mode = WaypointDisplayMode::List;
+        }
+    }
+
+    async fn delete_selected(&mut self) {
+        let Some(entry) = self.selected().cloned() else {
+            return;
+        };
+        if self.locked_to_someone_else(&entry) {
+            return;
+        }
+        let mut waypoint = entry.waypoint.clone();
+        waypoint.expire = DELETED_EXPIRY;
+        if self.broadcast(&waypoint, entry.channel).await {
+            self.waypoints.remove(&waypoint.id);
+            info!("Deleted waypoint {}", waypoint.name);
+        }
+    }
+
+    async fn broadcast(&self, waypoint: &Waypoint, channel: u32) -> bool {
+        let packet =
+            util::broadcast_packet(channel, PortNum::WaypointApp, waypoint.encode_to_vec());
+        let payload_variant = Some(Packet(packet));
+        match util::send_to_radio(IPCMessage::ToRadio(ToRadio { payload_variant })).await {
+            Ok(()) => {
+                info!("Sent waypoint {} to channel {channel}", waypoint.name);
+                true
+            }
+            Err(e) => {
+                error!("Unable to send waypoint {}: {e}", waypoint.name);
+                false
+            }
+        }
+    }
+
+    fn my_location(&self) -> Option<(f64, f64)> {
+        util::position_degrees(&self.my_position?)
+    }
+
+    fn render_form(&self, area: Rect, buf: &mut Buffer) {
+        let rows = FormField::iter().map(|f| Row::new(vec![f.to_string(), self.form.value(f)]));
+        let title = match self.form.waypoint.id {
+            0 => "New waypoint (Enter edits a field, F2 sends, Esc cancels)",
+            _ => "Edit waypoint (Enter edits a field, F2 sends, Esc cancels)",
+        };
+        let mut table_state = TableState::default().with_selected(Some(self.form_row));
+        mouse::table(area, true, 0, &table_state, FormField::iter().count());
+        StatefulWidget::render(
+            Table::new(rows, [Constraint::Length(32), Constraint::Min(20)])
+                .block(
+                    Block::new()
+                        .borders(Borders::ALL)
+                        .title(title)
+                        .title_alignment(Alignment::Center)
+                        .border_set(symbols::border::DOUBLE)
+                        .style(THEME.middle),
+                )
+                .highlight_style(THEME.tabs_selected),
+            area,
+            buf,
+            &mut table_state,
+        );
+    }
+}
+
+/// Initial bearing from one point to another, in degrees clockwise from north.
+fn bearing(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (from.0.to_radians(), to.0.to_radians());
+    let dlon = (to.1 - from.1).to_radians();
+    let y = dlon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlon.cos();
+    (y.atan2(x).to_degrees() + 360.0) % 360.0
+}
+
+fn compass_point(degrees: f64) -> &'static str {
+    const POINTS: [&str; 8] = ["N", "NE", "E", "SE", "S", "SW", "W", "NW"];
+    POINTS[((degrees + 22.5) / 45.0) as usize % POINTS.len()]
+}
+
+impl Widget for WaypointsTab {
+    fn render(mut self, area: Rect, buf: &mut Buffer) {
+        if self.display_mode == WaypointDisplayMode::Form {
+            self.render_form(area, buf);
+            return;
+        }
+        let me = self.my_location();
+        let now = get_secs();
+        let rows = self
+            .table_contents
+            .iter()
+            .map(|entry| {
+                let location = entry.location();
+                let (lat_str, lon_str) = match location {
+                    Some((lat, lon)) => (format!("{lat:.5}"), format!("{lon:.5}")),
+                    None => ("".to_string(), "".to_string()),
+                };
+                let (distance_str, bearing_str) = match (me, location) {
+                    (Some(me), Some(there)) => {
+                        let distance = Location::new(me.0, me.1)
+                            .haversine_distance_to(&Location::new(there.0, there.1));
+                        let degrees = bearing(me, there);
+                        (
+                            format!("{:.3}km", distance.meters().div(1000.0_f64)),
+                            format!("{degrees:.0}° {}", compass_point(degrees)),
+                        )
+                    }
+                    _ => ("".to_string(), "".to_string()),
+                };
+                let expires_str = match entry.waypoint.expire {
+                    0 => "never".to_string(),
+                    e => {
+                        pretty_duration(&Duration::from_secs((e as u64).saturating_sub(now)), None)
+                    }
+                };
+                let locked_str = match entry.waypoint.locked_to {
+                    0 => "".to_string(),
+                    id if id == self.my_node_id => "us".to_string(),
+                    id => format!("!{id:x}"),
+                };
+                Row::new(vec![
+                    entry.icon(),
+                    entry.waypoint.name.clone(),
+                    entry.waypoint.description.clone(),
+                    lat_str,
+                    lon_str,
+                    distance_str,
+                    bearing_str,
+                    expires_str,
+                    locked_str,
+                    entry.owner_name.clone(),
+                    entry.channel.to_string(),
+                ])
+            })
+            .collect_vec();
+
+        let constraints = vec![
+            Constraint::Length(4),
+            Constraint::Max(20),
+            Constraint::Min(20),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(8),
+            Constraint::Max(20),
+            Constraint::Length(10),
+            Constraint::Max(10),
+            Constraint::Length(7),
+        ];
+        let header = Row::new(vec![
+            "Icon",
+            "Name",
+            "Description",
+            "Latitude",
+            "Longitude",
+            "Distance",
+            "Bearing",
+            "Expires",
+            "Locked to",
+            "Owner",
+            "Channel",
+        ])
+        .style(THEME.message_header)
+        .bottom_margin(1);
+
+        let block = Block::new()
+            .borders(Borders::ALL)
+            .title("Waypoints (F2 new, F3 delete, Enter edit)")
+            .title_alignment(Alignment::Center)
+            .border_set(symbols::border::DOUBLE)
+            .style(THEME.middle);
+
+        mouse::table(area, true, 2, &self.table_state, rows.len());
+        StatefulWidget::render(
+            Table::new(rows, constraints)
+                .block(block)
+                .header(header)
+                .highlight_style(THEME.tabs_selected),
+            area,
+            buf,
+            &mut self.table_state,
+        );
+    }
+}
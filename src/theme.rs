@@ -1,6 +1,19 @@
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use ratatui::buffer::Buffer;
 use ratatui::prelude::*;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::Deref;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
 
+#[derive(Debug, Clone)]
 pub struct Theme {
+    pub name: String,
     pub root: Style,
     pub tabs: Style,
     pub tabs_selected: Style,
@@ -13,63 +26,361 @@ pub struct Theme {
     pub message_header: Style,
     pub message_selected: Style,
     pub warning_highlight: Style,
+    pub key_warning: Style,
     pub popup_window: Style,
+    pub remote_target: Style,
+    pub composer_cursor: Style,
+    pub composer_over_limit: Style,
+    pub chart_palette: [Color; 6],
 }
+#[derive(Debug, Clone, Default)]
 pub struct NodesTheme {
     pub list: Style,
     pub detail: Style,
+    pub compare_marked: Style,
 }
+#[derive(Debug, Clone, Default)]
 pub struct KeyBinding {
     pub key: Style,
     pub description: Style,
 }
-pub const THEME: Theme = Theme {
-    root: Style::new().bg(MENU_COLOR_BACKGROUND),
-    tabs: Style::new()
-        .fg(MENU_COLOR_FOREGROUND)
-        .bg(MENU_COLOR_BACKGROUND),
-    tabs_selected: Style::new()
-        .fg(MENU_COLOR_FOREGROUND)
-        .bg(MENU_COLOR_HIGHLIGHT),
-    warning_highlight: Style::new().fg(TV_WHITE).bg(TV_GREEN),
-    footer: Style::new()
-        .fg(MENU_COLOR_FOREGROUND)
-        .bg(MENU_COLOR_BACKGROUND),
-    borders: Style::new().fg(BORDER_MID_COLOR_FG).bg(BORDER_MID_COLOR_BG),
-    middle: Style::new().fg(MID_COLOR_FG).bg(MID_COLOR_BG),
-    nodes: NodesTheme {
-        list: Style::new(),
-        detail: Style::new().bg(MENU_COLOR_HIGHLIGHT),
-    },
-    key_binding: KeyBinding {
-        key: Style::new().fg(Color::Red).bg(MENU_COLOR_BACKGROUND),
-        description: Style::new()
-            .fg(MENU_COLOR_FOREGROUND)
-            .bg(MENU_COLOR_BACKGROUND),
-    },
-    date_display: Style::new()
-        .fg(MENU_COLOR_FOREGROUND)
-        .bg(MENU_COLOR_BACKGROUND),
-    message_header: Style::new().fg(Color::LightCyan),
-    message_selected: Style::new()
-        .fg(MENU_COLOR_FOREGROUND)
-        .bg(MENU_COLOR_BACKGROUND),
-    popup_window: Style::new().fg(TV_WHITE).bg(TV_GREY),
-};
-
-//  https://docs.rs/ratatui/latest/ratatui/style/enum.Color.html#
-pub const MENU_COLOR_HIGHLIGHT: Color = TV_GREEN;
-pub const MENU_COLOR_FOREGROUND: Color = Color::Black;
-pub const MENU_COLOR_BACKGROUND: Color = Color::Gray;
-
-pub const BORDER_MID_COLOR_FG: Color = TV_YELLOW;
-pub const BORDER_MID_COLOR_BG: Color = TV_BLUE;
-
-pub const MID_COLOR_FG: Color = TV_YELLOW;
-pub const MID_COLOR_BG: Color = TV_BLUE;
-
-pub const TV_BLUE: Color = Color::Rgb(0, 0, 178);
-pub const TV_YELLOW: Color = Color::Rgb(200, 200, 107);
-pub const TV_GREEN: Color = Color::Rgb(24, 178, 24);
-pub const TV_WHITE: Color = Color::Rgb(255, 255, 255);
-pub const TV_GREY: Color = Color::Rgb(178, 178, 178);
+
+/// The themes that ship with meshtui, in the order F10 cycles through them.
+const PRESETS: [&str; 5] = [
+    include_str!("../themes/dark.toml"),
+    include_str!("../themes/light.toml"),
+    include_str!("../themes/high-contrast.toml"),
+    include_str!("../themes/solarized.toml"),
+    include_str!("../themes/monochrome.toml"),
+];
+const DEFAULT_THEME: &str = "dark";
+const NO_COLOR_THEME: &str = "monochrome";
+
+/// The theme in use.  It dereferences to the selected entry of the theme list, so styles read
+/// the same as they did when the theme was a constant: `THEME.tabs`.
+pub struct CurrentTheme;
+pub static THEME: CurrentTheme = CurrentTheme;
+
+static THEMES: OnceLock<Vec<Theme>> = OnceLock::new();
+static SELECTED: AtomicUsize = AtomicUsize::new(0);
+static COLOR_DEPTH: OnceLock<ColorDepth> = OnceLock::new();
+
+impl Deref for CurrentTheme {
+    type Target = Theme;
+    fn deref(&self) -> &Theme {
+        let themes = THEMES.get_or_init(|| presets().expect("the preset themes parse"));
+        &themes[SELECTED.load(Ordering::Relaxed) % themes.len()]
+    }
+}
+
+/// How many colours the terminal can show.  Themes are written in whatever colours they like,
+/// and every frame is brought down to this before it's drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorDepth {
+    #[value(name = "none")]
+    NoColor,
+    #[value(name = "16")]
+    Ansi16,
+    #[value(name = "256")]
+    Ansi256,
+    #[value(name = "truecolor")]
+    TrueColor,
+}
+
+impl ColorDepth {
+    /// Works out the colour depth from the environment, honouring `NO_COLOR`.
+    pub fn detect() -> ColorDepth {
+        let var = |name| std::env::var(name).unwrap_or_default();
+        if !var("NO_COLOR").is_empty() {
+            return ColorDepth::NoColor;
+        }
+        let term = var("TERM");
+        match var("COLORTERM").as_str() {
+            "truecolor" | "24bit" => ColorDepth::TrueColor,
+            _ if term == "dumb" => ColorDepth::NoColor,
+            _ if term.contains("256") => ColorDepth::Ansi256,
+            _ => ColorDepth::Ansi16,
+        }
+    }
+}
+
+/// A theme file.  Styles name their colours as ratatui does (`lightcyan`, `#18b218`, or a
+/// 256-colour index like `236`), or by an entry in the file's palette.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeFile {
+    name: String,
+    /// A preset to start from; styles the file doesn't mention are taken from it.
+    extends: Option<String>,
+    #[serde(default)]
+    chart_palette: Vec<String>,
+    #[serde(default)]
+    palette: BTreeMap<String, String>,
+    #[serde(default)]
+    styles: BTreeMap<String, StyleSpec>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StyleSpec {
+    fg: Option<String>,
+    bg: Option<String>,
+    #[serde(default)]
+    modifiers: Vec<String>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            name: String::new(),
+            root: Style::new(),
+            tabs: Style::new(),
+            tabs_selected: Style::new().add_modifier(Modifier::REVERSED),
+            key_binding: KeyBinding::default(),
+            borders: Style::new(),
+            middle: Style::new(),
+            footer: Style::new(),
+            nodes: NodesTheme::default(),
+            date_display: Style::new(),
+            message_header: Style::new(),
+            message_selected: Style::new(),
+            warning_highlight: Style::new(),
+            key_warning: Style::new(),
+            popup_window: Style::new(),
+            remote_target: Style::new(),
+            composer_cursor: Style::new().add_modifier(Modifier::REVERSED),
+            composer_over_limit: Style::new(),
+            chart_palette: [
+                Color::LightGreen,
+                Color::LightMagenta,
+                Color::LightCyan,
+                Color::LightRed,
+                Color::White,
+                Color::LightYellow,
+            ],
+        }
+    }
+}
+
+impl Theme {
+    /// The style a theme file sets with `key`.
+    fn style_mut(&mut self, key: &str) -> Option<&mut Style> {
+        Some(match key {
+            "root" => &mut self.root,
+            "tabs" => &mut self.tabs,
+            "tabs_selected" => &mut self.tabs_selected,
+            "key_binding_key" => &mut self.key_binding.key,
+            "key_binding_description" => &mut self.key_binding.description,
+            "borders" => &mut self.borders,
+            "middle" => &mut self.middle,
+            "footer" => &mut self.footer,
+            "nodes_list" => &mut self.nodes.list,
+            "nodes_detail" => &mut self.nodes.detail,
+            "nodes_compare_marked" => &mut self.nodes.compare_marked,
+            "date_display" => &mut self.date_display,
+            "message_header" => &mut self.message_header,
+            "message_selected" => &mut self.message_selected,
+            "warning_highlight" => &mut self.warning_highlight,
+            "key_warning" => &mut self.key_warning,
+            "popup_window" => &mut self.popup_window,
+            "remote_target" => &mut self.remote_target,
+            "composer_cursor" => &mut self.composer_cursor,
+            "composer_over_limit" => &mut self.composer_over_limit,
+            _ => return None,
+        })
+    }
+
+    /// Parses a theme file, starting from the theme it extends, if any, among `known`.
+    fn parse(contents: &str, known: &[Theme]) -> Result<Theme> {
+        let file: ThemeFile = toml::from_str(contents)?;
+        let mut theme = match &file.extends {
+            Some(base) => known
+                .iter()
+                .find(|t| t.name == *base)
+                .cloned()
+                .with_context(|| format!("{} extends {base}, which isn't a theme", file.name))?,
+            None => Theme::default(),
+        };
+        theme.name = file.name;
+        let color = |name: &str| -> Result<Color> {
+            let name = file.palette.get(name).map_or(name, String::as_str);
+            Color::from_str(name).map_err(|_| anyhow::anyhow!("{name} isn't a colour"))
+        };
+        for (key, spec) in &file.styles {
+            let mut style = Style::new();
+            if let Some(fg) = &spec.fg {
+                style = style.fg(color(fg)?);
+            }
+            if let Some(bg) = &spec.bg {
+                style = style.bg(color(bg)?);
+            }
+            for name in &spec.modifiers {
+                match Modifier::from_name(&name.to_uppercase()) {
+                    Some(modifier) => style = style.add_modifier(modifier),
+                    None => bail!("{name} isn't a modifier"),
+                }
+            }
+            match theme.style_mut(key) {
+                Some(s) => *s = style,
+                None => bail!("{key} isn't a style a theme can set"),
+            }
+        }
+        for (slot, name) in theme
+            .chart_palette
+            .iter_mut()
+            .zip(file.chart_palette.iter().cycle())
+        {
+            *slot = color(name)?;
+        }
+        Ok(theme)
+    }
+}
+
+fn presets() -> Result<Vec<Theme>> {
+    let mut themes = vec![];
+    for contents in PRESETS {
+        let theme = Theme::parse(contents, &themes)?;
+        themes.push(theme);
+    }
+    Ok(themes)
+}
+
+/// Sets up the theme list: the presets, then every `.toml` file in `themes_dir`, where a file
+/// with a preset's name replaces it.  `name` picks the theme to start with; by default that's
+/// the dark preset, or the monochrome one when there's no colour to be had.
+pub fn init(themes_dir: Option<&Path>, name: Option<&str>, depth: ColorDepth) -> Result<()> {
+    let mut themes = presets()?;
+    if let Some(dir) = themes_dir {
+        let entries = fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))?;
+        let mut paths: Vec<_> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|e| e == "toml"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let contents =
+                fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+            let theme = Theme::parse(&contents, &themes)
+                .with_context(|| format!("in {}", path.display()))?;
+            match themes.iter_mut().find(|t| t.name == theme.name) {
+                Some(existing) => *existing = theme,
+                None => themes.push(theme),
+            }
+        }
+    }
+    let name = name.unwrap_or(match depth {
+        ColorDepth::NoColor => NO_COLOR_THEME,
+        _ => DEFAULT_THEME,
+    });
+    let Some(selected) = themes.iter().position(|t| t.name == name) else {
+        bail!(
+            "there's no theme called {name}; try one of {}",
+            themes
+                .iter()
+                .map(|t| t.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    };
+    SELECTED.store(selected, Ordering::Relaxed);
+    let _ = THEMES.set(themes);
+    let _ = COLOR_DEPTH.set(depth);
+    Ok(())
+}
+
+/// Switches to the next theme in the list, returning its name.
+pub fn next_theme() -> &'static str {
+    let themes = THEMES.get_or_init(|| presets().expect("the preset themes parse"));
+    let selected = (SELECTED.load(Ordering::Relaxed) + 1) % themes.len();
+    SELECTED.store(selected, Ordering::Relaxed);
+    &themes[selected].name
+}
+
+/// The sixteen ANSI colours, as xterm draws them by default.
+const ANSI: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+/// The levels of each channel in the 6x6x6 colour cube of the 256-colour palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+/// What a 256-colour palette entry looks like.
+fn indexed_rgb(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..=15 => ANSI[index as usize].1,
+        16..=231 => {
+            let i = index - 16;
+            let level = |n: u8| CUBE_LEVELS[n as usize];
+            (level(i / 36), level(i / 6 % 6), level(i % 6))
+        }
+        _ => {
+            let grey = 8 + (index - 232) * 10;
+            (grey, grey, grey)
+        }
+    }
+}
+
+fn nearest_indexed(rgb: (u8, u8, u8)) -> Color {
+    let nearest_level = |c: u8| {
+        (0..6u8)
+            .min_by_key(|i| (CUBE_LEVELS[*i as usize] as i32 - c as i32).abs())
+            .unwrap_or(0)
+    };
+    let cube = 16 + 36 * nearest_level(rgb.0) + 6 * nearest_level(rgb.1) + nearest_level(rgb.2);
+    let average = (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+    let grey = 232 + (average.saturating_sub(3) / 10).min(23) as u8;
+    match distance(indexed_rgb(grey), rgb) < distance(indexed_rgb(cube), rgb) {
+        true => Color::Indexed(grey),
+        false => Color::Indexed(cube),
+    }
+}
+
+fn nearest_ansi(rgb: (u8, u8, u8)) -> Color {
+    ANSI.iter()
+        .min_by_key(|(_, ansi)| distance(*ansi, rgb))
+        .map_or(Color::Reset, |(color, _)| *color)
+}
+
+fn downgrade(color: Color, depth: ColorDepth) -> Color {
+    match (depth, color) {
+        (ColorDepth::TrueColor, c) => c,
+        (ColorDepth::NoColor, _) => Color::Reset,
+        (ColorDepth::Ansi256, Color::Rgb(r, g, b)) => nearest_indexed((r, g, b)),
+        (ColorDepth::Ansi16, Color::Rgb(r, g, b)) => nearest_ansi((r, g, b)),
+        (ColorDepth::Ansi16, Color::Indexed(i)) => nearest_ansi(indexed_rgb(i)),
+        (_, c) => c,
+    }
+}
+
+/// Brings a drawn frame down to the colours the terminal can show.  This runs over the whole
+/// buffer so that it catches colours set outside the theme too.
+pub fn adapt(buf: &mut Buffer) {
+    let depth = *COLOR_DEPTH.get().unwrap_or(&ColorDepth::TrueColor);
+    if depth == ColorDepth::TrueColor {
+        return;
+    }
+    for cell in buf.content.iter_mut() {
+        cell.fg = downgrade(cell.fg, depth);
+        cell.bg = downgrade(cell.bg, depth);
+        cell.underline_color = downgrade(cell.underline_color, depth);
+    }
+}
@@ -0,0 +1,226 @@
+use crate::tabs::nodes::ComprehensiveNode;
+use crate::util::get_secs;
+use anyhow::{Context, Result};
+use pretty_duration::pretty_duration;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// What the firmware puts in a traceroute for a hop whose SNR it doesn't know, which is how hops
+/// that crossed an MQTT broker show up.
+const UNKNOWN_SNR: i32 = i8::MIN as i32;
+
+/// Which links make it into the topology export.
+#[derive(Debug, Clone, Default)]
+pub struct TopologyFilter {
+    /// Leave out links that went through an MQTT broker rather than over the air.
+    pub skip_mqtt: bool,
+    /// Leave out links we've had no word of for longer than this many seconds.
+    pub max_age: Option<u64>,
+}
+
+/// Where we learned about a link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    /// We heard the node ourselves, with no hops in between.
+    Direct,
+    NeighborInfo,
+    Traceroute,
+}
+
+impl Source {
+    fn describe(&self) -> &'static str {
+        match self {
+            Source::Direct => "heard directly",
+            Source::NeighborInfo => "neighbor info",
+            Source::Traceroute => "traceroute",
+        }
+    }
+}
+
+/// One node hearing another, keyed by (transmitter, receiver).
+#[derive(Debug, Clone)]
+struct Link {
+    snr: Option<f32>,
+    /// When we learned of the link, or 0 if we can't tell.
+    heard: u64,
+    mqtt: bool,
+    source: Source,
+}
+
+/// Keeps the freshest report of each link.
+fn add_link(links: &mut BTreeMap<(u32, u32), Link>, from: u32, to: u32, link: Link) {
+    if from == to {
+        return;
+    }
+    match links.get(&(from, to)) {
+        Some(existing) if existing.heard >= link.heard => {}
+        _ => {
+            links.insert((from, to), link);
+        }
+    }
+}
+
+/// Adds the hops along one direction of a traceroute.  SNRs are reported in quarter dB, one per
+/// hop, by firmware new enough to report them at all.
+fn add_route(links: &mut BTreeMap<(u32, u32), Link>, path: &[u32], snrs: &[i32], heard: u64) {
+    for (hop, pair) in path.windows(2).enumerate() {
+        let snr = snrs.get(hop).copied();
+        let link = Link {
+            snr: snr.filter(|s| *s != UNKNOWN_SNR).map(|s| s as f32 / 4.0),
+            heard,
+            mqtt: snr == Some(UNKNOWN_SNR),
+            source: Source::Traceroute,
+        };
+        add_link(links, pair[0], pair[1], link);
+    }
+}
+
+/// Every link we know of: nodes we hear directly, the neighbors nodes report, and the hops of
+/// the traceroutes we've seen.
+fn links(
+    node_list: &HashMap<u32, ComprehensiveNode>,
+    my_node_id: u32,
+) -> BTreeMap<(u32, u32), Link> {
+    let mut links = BTreeMap::new();
+    for cn in node_list.values() {
+        if cn.id != my_node_id && cn.last_hops == Some(0) {
+            let link = Link {
+                snr: (cn.last_snr != 0.0 || cn.last_rssi != 0).then_some(cn.last_snr),
+                heard: cn.last_seen,
+                mqtt: cn.node_info.via_mqtt,
+                source: Source::Direct,
+            };
+            add_link(&mut links, cn.id, my_node_id, link);
+        }
+        for neighbor in &cn.neighbors {
+            let link = Link {
+                snr: Some(neighbor.snr),
+                heard: match neighbor.last_rx_time {
+                    0 => cn.neighbors_heard,
+                    t => t as u64,
+                },
+                mqtt: false,
+                source: Source::NeighborInfo,
+            };
+            add_link(&mut links, neighbor.node_id, cn.id, link);
+        }
+        // a traceroute reply comes from the traced node, so `origin` is who asked for it
+        for (origin, traced) in &cn.traceroutes {
+            let discovery = &traced.discovery;
+            let towards: Vec<u32> = std::iter::once(*origin)
+                .chain(discovery.route.iter().copied())
+                .chain(std::iter::once(cn.id))
+                .collect();
+            add_route(
+                &mut links,
+                &towards,
+                &discovery.snr_towards,
+                traced.timestamp,
+            );
+            // older firmware only reports the way there
+            if !discovery.snr_back.is_empty() {
+                let back: Vec<u32> = std::iter::once(cn.id)
+                    .chain(discovery.route_back.iter().copied())
+                    .chain(std::iter::once(*origin))
+                    .collect();
+                add_route(&mut links, &back, &discovery.snr_back, traced.timestamp);
+            }
+        }
+    }
+    links
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn node_label(node_list: &HashMap<u32, ComprehensiveNode>, id: u32) -> String {
+    let mut lines = vec![];
+    if let Some(user) = node_list.get(&id).and_then(|cn| cn.node_info.user.as_ref()) {
+        lines.push(user.short_name.clone());
+        lines.push(user.long_name.clone());
+        lines.push(format!("{:?}", user.role()));
+    }
+    lines.retain(|l| !l.is_empty());
+    lines.push(format!("!{id:08x}"));
+    lines
+        .iter()
+        .map(|l| escape(l))
+        .collect::<Vec<_>>()
+        .join("\\n")
+}
+
+/// The mesh as a Graphviz digraph.  An edge runs from the node that transmitted to the node that
+/// heard it, labelled with the SNR it was heard at and how long ago we learned of it.  Links
+/// through MQTT are dashed, and our own node is drawn in bold.
+pub fn render_dot(
+    node_list: &HashMap<u32, ComprehensiveNode>,
+    my_node_id: u32,
+    filter: &TopologyFilter,
+) -> String {
+    let now = get_secs();
+    let links: Vec<_> = links(node_list, my_node_id)
+        .into_iter()
+        .filter(|(_, link)| !(filter.skip_mqtt && link.mqtt))
+        .filter(|(_, link)| match filter.max_age {
+            Some(max_age) => link.heard > 0 && now.saturating_sub(link.heard) <= max_age,
+            None => true,
+        })
+        .collect();
+    let mut nodes: BTreeSet<u32> = links.iter().flat_map(|((a, b), _)| [*a, *b]).collect();
+    if my_node_id != 0 {
+        nodes.insert(my_node_id);
+    }
+
+    let mut dot = String::from("digraph mesh {\n");
+    dot.push_str("  graph [overlap=false, splines=true];\n");
+    dot.push_str("  node [shape=box, style=rounded, fontname=\"Helvetica\"];\n");
+    dot.push_str("  edge [fontname=\"Helvetica\", fontsize=10];\n");
+    for id in nodes {
+        let style = match id == my_node_id {
+            true => "rounded,bold",
+            false => "rounded",
+        };
+        let _ = writeln!(
+            dot,
+            "  \"!{id:08x}\" [label=\"{}\", style=\"{style}\"];",
+            node_label(node_list, id)
+        );
+    }
+    for ((from, to), link) in links {
+        let mut label = vec![match link.snr {
+            Some(snr) => format!("{snr:.2} dB"),
+            None if link.mqtt => "MQTT".to_string(),
+            None => "? dB".to_string(),
+        }];
+        if link.heard > 0 {
+            let age = Duration::from_secs(now.saturating_sub(link.heard));
+            label.push(format!("{} ago", pretty_duration(&age, None)));
+        }
+        let style = match link.mqtt {
+            true => "dashed",
+            false => "solid",
+        };
+        let _ = writeln!(
+            dot,
+            "  \"!{from:08x}\" -> \"!{to:08x}\" [label=\"{}\", style={style}, tooltip=\"{}\"];",
+            label.join("\\n"),
+            link.source.describe()
+        );
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+pub fn write_dot(
+    path: &Path,
+    node_list: &HashMap<u32, ComprehensiveNode>,
+    my_node_id: u32,
+    filter: &TopologyFilter,
+) -> Result<()> {
+    fs::write(path, render_dot(node_list, my_node_id, filter))
+        .with_context(|| format!("creating {}", path.display()))
+}
@@ -0,0 +1,582 @@
+// Unishox2 with its default preset, which is what the firmware uses for
+// TextMessageCompressedApp.  The decoder understands everything the firmware's encoder emits
+// (dictionary repeats, runs, hex and template codes included).  The encoder only uses the three
+// character sets and one-off Unicode code points, which every Unishox2 decoder accepts and which
+// is where nearly all of the savings on chat text come from.
+
+const ALPHA: usize = 0;
+const SYM: usize = 1;
+const NUM: usize = 2;
+const DICT: usize = 3;
+const DELTA: usize = 4;
+
+/// The first bit of every Unishox2 stream, which tells it apart from the original Unishox.
+const MAGIC_BIT_LEN: usize = 1;
+/// Dictionary repeats are never shorter than this, so lengths and distances are sent less it.
+const NICE_LEN: usize = 5;
+/// The vertical code that ends a stream which doesn't finish on a byte boundary.
+const TERM_CODE: usize = 27;
+
+/// Codes that switch between the sets, MSB first, with their length in bits.
+const HCODES: [(u8, u8); 5] = [(0x00, 2), (0x40, 2), (0x80, 2), (0xC0, 3), (0xE0, 3)];
+
+/// Codes for a position within a set, MSB first, with their length in bits.
+const VCODES: [(u8, u8); 28] = [
+    (0x00, 2),
+    (0x40, 3),
+    (0x60, 3),
+    (0x80, 4),
+    (0x90, 4),
+    (0xA0, 4),
+    (0xB0, 4),
+    (0xC0, 4),
+    (0xD0, 5),
+    (0xD8, 5),
+    (0xE0, 6),
+    (0xE4, 6),
+    (0xE8, 6),
+    (0xEC, 7),
+    (0xEE, 7),
+    (0xF0, 7),
+    (0xF2, 7),
+    (0xF4, 7),
+    (0xF6, 8),
+    (0xF7, 8),
+    (0xF8, 8),
+    (0xF9, 8),
+    (0xFA, 8),
+    (0xFB, 8),
+    (0xFC, 8),
+    (0xFD, 8),
+    (0xFE, 8),
+    (0xFF, 8),
+];
+
+/// The alphabet, symbol and number sets, most frequent first.  Zeroes are control codes.
+const SETS: [[u8; 28]; 3] = [
+    [
+        0, b' ', b'e', b't', b'a', b'o', b'i', b'n', b's', b'r', b'l', b'c', b'd', b'h', b'u',
+        b'p', b'm', b'b', b'g', b'w', b'f', b'y', b'v', b'k', b'q', b'j', b'x', b'z',
+    ],
+    [
+        b'"', b'{', b'}', b'_', b'<', b'>', b':', b'\n', 0, b'[', b']', b'\\', b';', b'\'', b'\t',
+        b'@', b'*', b'&', b'?', b'!', b'^', b'|', b'\r', b'~', b'`', 0, 0, 0,
+    ],
+    [
+        0, b',', b'.', b'0', b'1', b'9', b'2', b'5', b'-', b'/', b'3', b'4', b'6', b'7', b'8',
+        b'(', b')', b' ', b'=', b'+', b'$', b'%', b'#', 0, 0, 0, 0, 0,
+    ],
+];
+
+const FREQ_SEQ: [&str; 6] = ["\": \"", "\": ", "</", "=\"", "\":\"", "://"];
+const TEMPLATES: [&str; 4] = [
+    "tfff-of-tfTtf:rf:rf.fffZ",
+    "tfff-of-tf",
+    "(fff) fff-ffff",
+    "tf:rf:rf",
+];
+
+const COUNT_BIT_LENS: [usize; 5] = [2, 4, 7, 11, 16];
+const COUNT_ADDER: [usize; 5] = [0, 4, 20, 148, 2196];
+const UNI_BIT_LENS: [usize; 5] = [6, 12, 14, 16, 21];
+const UNI_ADDER: [i32; 5] = [0, 64, 4160, 20544, 86080];
+
+/// What a Unicode code may carry: a difference from the last code point, or a special code.
+enum Delta {
+    CodePoint(i32),
+    Special(usize),
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    len: usize,
+}
+
+impl BitReader<'_> {
+    fn bit(&self, at: usize) -> u8 {
+        match at < self.len {
+            true => (self.data[at / 8] >> (7 - at % 8)) & 1,
+            false => 0,
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.len
+    }
+
+    /// The next eight bits, zero-padded past the end, without consuming them.
+    fn peek8(&self) -> u8 {
+        (0..8).fold(0, |acc, i| (acc << 1) | self.bit(self.pos + i))
+    }
+
+    fn number(&mut self, bits: usize) -> Option<usize> {
+        if self.pos + bits > self.len {
+            return None;
+        }
+        let n = (0..bits).fold(0, |acc, i| (acc << 1) | self.bit(self.pos + i) as usize);
+        self.pos += bits;
+        Some(n)
+    }
+
+    /// Counts ones up to `limit`, consuming the zero that ends a shorter run.
+    fn step_code(&mut self, limit: usize) -> Option<usize> {
+        let mut idx = 0;
+        while !self.at_end() && self.bit(self.pos) == 1 {
+            idx += 1;
+            self.pos += 1;
+            if idx == limit {
+                return Some(idx);
+            }
+        }
+        if self.at_end() {
+            return None;
+        }
+        self.pos += 1;
+        Some(idx)
+    }
+
+    fn prefix_code(&mut self, codes: &[(u8, u8)]) -> Option<usize> {
+        if self.at_end() {
+            return None;
+        }
+        let next = self.peek8();
+        let idx = codes
+            .iter()
+            .position(|(code, len)| next & (0xFF << (8 - len)) == *code)?;
+        self.pos += codes[idx].1 as usize;
+        Some(idx)
+    }
+
+    fn hcode(&mut self) -> Option<usize> {
+        self.prefix_code(&HCODES)
+    }
+
+    fn vcode(&mut self) -> Option<usize> {
+        let v = self.prefix_code(&VCODES)?;
+        match self.pos > self.len {
+            true => None,
+            false => Some(v),
+        }
+    }
+
+    fn count(&mut self) -> Option<usize> {
+        let idx = self.step_code(4)?;
+        Some(self.number(COUNT_BIT_LENS[idx])? + COUNT_ADDER[idx])
+    }
+
+    fn unicode(&mut self) -> Option<Delta> {
+        let idx = self.step_code(5)?;
+        if idx == 5 {
+            return Some(Delta::Special(self.step_code(4)?));
+        }
+        let negative = self.bit(self.pos) == 1;
+        self.pos += 1;
+        let value = self.number(UNI_BIT_LENS[idx])? as i32 + UNI_ADDER[idx];
+        Some(Delta::CodePoint(if negative { -value } else { value }))
+    }
+}
+
+fn hex_digit(nibble: usize, upper: bool) -> u8 {
+    match (nibble, upper) {
+        (0..=9, _) => b'0' + nibble as u8,
+        (_, true) => b'A' + nibble as u8 - 10,
+        (_, false) => b'a' + nibble as u8 - 10,
+    }
+}
+
+fn push_code_point(out: &mut Vec<u8>, code_point: i32) {
+    if let Some(c) = u32::try_from(code_point).ok().and_then(char::from_u32) {
+        let mut buf = [0; 4];
+        out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+}
+
+/// Copies an earlier stretch of the output back onto its end.
+fn copy_repeat(bits: &mut BitReader, out: &mut Vec<u8>) -> Option<()> {
+    let len = bits.count()? + NICE_LEN;
+    let dist = bits.count()? + NICE_LEN - 1;
+    let start = out.len().checked_sub(dist)?;
+    for i in 0..len {
+        out.push(out[start + i]);
+    }
+    Some(())
+}
+
+/// Templates (dates, times, phone numbers), hex strings, UUIDs and raw bytes, which all hide
+/// behind the empty first slot of the number set.
+fn special_number(bits: &mut BitReader, out: &mut Vec<u8>) -> Option<()> {
+    match bits.step_code(5)? {
+        0 => {
+            let template = TEMPLATES.get(bits.step_code(4)?)?;
+            let rem = template.len().checked_sub(bits.count()?)?;
+            for t in template.bytes().take(rem) {
+                let width = match t {
+                    b'f' | b'F' => 4,
+                    b'r' => 3,
+                    b't' => 2,
+                    b'o' => 1,
+                    _ => {
+                        out.push(t);
+                        continue;
+                    }
+                };
+                out.push(hex_digit(bits.number(width)?, t != b'f'));
+            }
+        }
+        5 => {
+            let count = bits.count().filter(|c| *c > 0)?;
+            for _ in 0..count {
+                out.push(bits.number(8)? as u8);
+            }
+        }
+        idx => {
+            let uuid = idx == 2 || idx == 4;
+            let count = match uuid {
+                true => 32,
+                false => bits.count().filter(|c| *c > 0)?,
+            };
+            for remaining in (1..=count).rev() {
+                out.push(hex_digit(bits.number(4)?, idx >= 3));
+                if uuid && matches!(remaining, 25 | 21 | 17 | 13) {
+                    out.push(b'-');
+                }
+            }
+        }
+    }
+    Some(())
+}
+
+/// Expands a Unishox2 stream.  The result is usually UTF-8, but that's up to whoever sent it.
+pub fn decompress(input: &[u8]) -> Vec<u8> {
+    let mut bits = BitReader {
+        data: input,
+        pos: MAGIC_BIT_LEN,
+        len: input.len() * 8,
+    };
+    let mut out = vec![];
+    let mut dstate = ALPHA;
+    let mut h = ALPHA;
+    let mut all_upper = false;
+    let mut prev_uni = 0;
+    while !bits.at_end() {
+        if dstate == DELTA || h == DELTA {
+            if dstate != DELTA {
+                h = dstate;
+            }
+            match bits.unicode() {
+                None => break,
+                Some(Delta::CodePoint(delta)) => {
+                    prev_uni += delta;
+                    push_code_point(&mut out, prev_uni);
+                }
+                Some(Delta::Special(1)) => {
+                    let Some(next) = bits.hcode() else { break };
+                    h = next;
+                    match h {
+                        DELTA | ALPHA => {
+                            dstate = h;
+                            continue;
+                        }
+                        DICT => {
+                            if copy_repeat(&mut bits, &mut out).is_none() {
+                                break;
+                            }
+                            h = dstate;
+                            continue;
+                        }
+                        // a single symbol or number follows
+                        _ => {}
+                    }
+                }
+                Some(Delta::Special(idx)) => {
+                    out.push(match idx {
+                        0 => b' ',
+                        2 => b',',
+                        3 => b'.',
+                        _ => b'\n',
+                    });
+                    continue;
+                }
+            }
+            if dstate == DELTA && h == DELTA {
+                continue;
+            }
+        } else {
+            h = dstate;
+        }
+
+        let mut upper = all_upper;
+        let Some(mut v) = bits.vcode() else { break };
+        if v == 0 && h != SYM {
+            if bits.at_end() {
+                break;
+            }
+            if h != NUM || dstate != DELTA {
+                match bits.hcode() {
+                    Some(next) if !bits.at_end() => h = next,
+                    _ => break,
+                }
+            }
+            match h {
+                ALPHA if dstate == ALPHA => {
+                    if all_upper {
+                        all_upper = false;
+                        continue;
+                    }
+                    let Some(next) = bits.vcode() else { break };
+                    v = next;
+                    if v == 0 {
+                        let Some(next) = bits.vcode() else { break };
+                        v = next;
+                        if v == 0 {
+                            all_upper = true;
+                            continue;
+                        }
+                    }
+                    upper = true;
+                }
+                ALPHA => {
+                    dstate = ALPHA;
+                    continue;
+                }
+                DICT => {
+                    if copy_repeat(&mut bits, &mut out).is_none() {
+                        break;
+                    }
+                    continue;
+                }
+                DELTA => continue,
+                _ => {
+                    if h != NUM || dstate != DELTA {
+                        let Some(next) = bits.vcode() else { break };
+                        v = next;
+                    }
+                    if h == NUM && v == 0 {
+                        if special_number(&mut bits, &mut out).is_none() {
+                            break;
+                        }
+                        if dstate == DELTA {
+                            h = DELTA;
+                        }
+                        continue;
+                    }
+                }
+            }
+        }
+        // an upper case space switches to a run of Unicode code points
+        if upper && v == 1 {
+            h = DELTA;
+            dstate = DELTA;
+            continue;
+        }
+        let c = SETS.get(h).map_or(0, |set| set[v]);
+        if c.is_ascii_lowercase() {
+            dstate = ALPHA;
+            out.push(if upper { c.to_ascii_uppercase() } else { c });
+            continue;
+        }
+        match (c, h, v) {
+            (0, _, 8) => out.extend_from_slice(b"\r\n"),
+            (0, NUM, 26) => {
+                let (Some(count), Some(last)) = (bits.count(), out.last().copied()) else {
+                    break;
+                };
+                out.extend(std::iter::repeat_n(last, count + 4));
+            }
+            (0, SYM, 25..) => out.extend_from_slice(FREQ_SEQ[v - 25].as_bytes()),
+            (0, NUM, 23..=25) => out.extend_from_slice(FREQ_SEQ[v - 20].as_bytes()),
+            // the terminator, or a code no encoder emits
+            (0, _, _) => break,
+            (c, _, _) => {
+                if c.is_ascii_digit() {
+                    dstate = NUM;
+                }
+                out.push(c);
+            }
+        }
+        if dstate == DELTA {
+            h = DELTA;
+        }
+    }
+    out
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    /// Appends the top `bits` bits of `code`.
+    fn push(&mut self, code: u8, bits: u8) {
+        for i in 0..bits {
+            self.push_bit((code >> (7 - i)) & 1 == 1);
+        }
+    }
+
+    fn push_bit(&mut self, bit: bool) {
+        if self.len.is_multiple_of(8) {
+            self.bytes.push(0);
+        }
+        if bit {
+            self.bytes[self.len / 8] |= 0x80 >> (self.len % 8);
+        }
+        self.len += 1;
+    }
+
+    fn push_number(&mut self, value: u32, bits: usize) {
+        for i in (0..bits).rev() {
+            self.push_bit((value >> i) & 1 == 1);
+        }
+    }
+
+    fn vcode(&mut self, v: usize) {
+        let (code, len) = VCODES[v];
+        self.push(code, len);
+    }
+
+    /// The switch code followed by the code for `set`.
+    fn switch(&mut self, set: usize) {
+        self.vcode(0);
+        let (code, len) = HCODES[set];
+        self.push(code, len);
+    }
+
+    fn unicode(&mut self, delta: i32) {
+        let diff = delta.unsigned_abs();
+        let idx = (0..UNI_BIT_LENS.len())
+            .find(|i| diff < UNI_ADDER[*i] as u32 + (1 << UNI_BIT_LENS[*i]))
+            .unwrap_or(UNI_BIT_LENS.len() - 1);
+        for _ in 0..idx {
+            self.push_bit(true);
+        }
+        self.push_bit(false);
+        self.push_bit(delta < 0);
+        self.push_number(diff - UNI_ADDER[idx] as u32, UNI_BIT_LENS[idx]);
+    }
+}
+
+fn position(set: usize, c: u8) -> Option<usize> {
+    SETS[set].iter().position(|s| *s == c && c != 0)
+}
+
+/// Compresses `text` into a Unishox2 stream the firmware can expand.
+pub fn compress(text: &str) -> Vec<u8> {
+    let mut w = BitWriter::default();
+    w.push(0x80, MAGIC_BIT_LEN as u8);
+    let mut state = ALPHA;
+    let mut prev_uni = 0;
+    for ch in text.chars() {
+        if ch.is_ascii_uppercase() {
+            if state != ALPHA {
+                w.switch(ALPHA);
+                state = ALPHA;
+            }
+            // switching to the set we're already in shifts the next letter
+            w.switch(ALPHA);
+            w.vcode(position(ALPHA, ch.to_ascii_lowercase() as u8).unwrap_or(1));
+            continue;
+        }
+        if ch.is_ascii() {
+            let c = ch as u8;
+            match (position(state, c), position(ALPHA, c), position(NUM, c)) {
+                (Some(v), _, _) => {
+                    w.vcode(v);
+                    if c.is_ascii_digit() {
+                        state = NUM;
+                    }
+                    continue;
+                }
+                (None, Some(v), _) => {
+                    w.switch(ALPHA);
+                    w.vcode(v);
+                    state = ALPHA;
+                    continue;
+                }
+                (None, None, Some(v)) => {
+                    w.switch(NUM);
+                    w.vcode(v);
+                    if c.is_ascii_digit() {
+                        state = NUM;
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            if let Some(v) = position(SYM, c) {
+                w.switch(SYM);
+                w.vcode(v);
+                continue;
+            }
+        }
+        let code_point = ch as i32;
+        w.switch(DELTA);
+        w.unicode(code_point - prev_uni);
+        prev_uni = code_point;
+    }
+    if !w.len.is_multiple_of(8) {
+        if state != NUM {
+            w.switch(NUM);
+        }
+        w.vcode(TERM_CODE);
+        while !w.len.is_multiple_of(8) {
+            w.push_bit(true);
+        }
+    }
+    w.bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(text: &str) {
+        assert_eq!(decompress(&compress(text)), text.as_bytes(), "{text:?}");
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip("");
+        round_trip("hello world, how are you doing today");
+        round_trip("Hello World");
+        round_trip("ALL CAPS AND Mixed Case");
+        round_trip("0123456789 -1.5 +44 (555) 123-4567");
+        round_trip("\"quoted\" {braces} [brackets] <a> @home #tag 100% $5 & more!? ~`^|\\");
+        round_trip("tabs\tand\r\nnew\nlines");
+        round_trip("café, naïve, Ελληνικά, русский, 日本語");
+        round_trip("emoji 👍🏽 and 🎉 and 😀😀");
+        round_trip("\u{1}control\u{7f}");
+    }
+
+    // These are assembled by hand from the Unishox2 code tables, most significant bit first, and
+    // pin the bit layout: the magic bit, the set switches and the terminator padded with ones.
+
+    #[test]
+    fn lower_case_letter() {
+        // 1 | 1001 'a' | 00 10 to numbers | 11111111 terminator | 1111111 padding
+        assert_eq!(compress("a"), [0xC9, 0x7F, 0xFF]);
+        assert_eq!(decompress(&[0xC9, 0x7F, 0xFF]), b"a");
+    }
+
+    #[test]
+    fn upper_case_letter() {
+        // 1 | 00 00 shift | 1001 'a' | 00 10 to numbers | 11111111 terminator | 111 padding
+        assert_eq!(compress("A"), [0x84, 0x97, 0xFF]);
+        assert_eq!(decompress(&[0x84, 0x97, 0xFF]), b"A");
+    }
+
+    #[test]
+    fn digit() {
+        // 1 | 00 10 to numbers | 1001 '1' | 11111111 terminator | 1111111 padding
+        assert_eq!(compress("1"), [0x94, 0xFF, 0xFF]);
+        assert_eq!(decompress(&[0x94, 0xFF, 0xFF]), b"1");
+    }
+
+    #[test]
+    fn compresses_chat_text() {
+        let text = "meet at the usual place at seven";
+        assert!(compress(text).len() < text.len());
+    }
+}
@@ -1,7 +1,8 @@
 use crate::ipc::IPCMessage;
-use crate::DEVICE_CONFIG;
+use crate::consts::GPS_PRECISION_FACTOR;
+use crate::{metrics, DEVICE_CONFIG};
 use anyhow::{bail, Result};
-use meshtastic::protobufs::Channel;
+use meshtastic::protobufs::{channel, mesh_packet, Channel, Data, MeshPacket, PortNum, Position};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub fn get_secs() -> u64 {
@@ -28,10 +29,65 @@ pub fn get_channel_from_id(id: u32) -> Option<Channel> {
     }
 }
 
+/// The channels our radio has enabled, by index.
+pub fn active_channels() -> Vec<u32> {
+    (0..8)
+        .filter(|i| get_channel_from_id(*i).is_some_and(|c| c.role() != channel::Role::Disabled))
+        .collect()
+}
+
+/// The hop limit our radio is configured with, or the firmware default if we don't know it.
+pub fn configured_hop_limit() -> u32 {
+    let configured = DEVICE_CONFIG
+        .try_read()
+        .ok()
+        .and_then(|c| c.as_ref().map(|c| c.lora.hop_limit));
+    match configured {
+        Some(h) if h > 0 => h,
+        _ => 3,
+    }
+}
+
+/// Our radio's canned messages, as last fetched from it.
+pub fn canned_messages() -> Vec<String> {
+    DEVICE_CONFIG
+        .try_read()
+        .ok()
+        .and_then(|c| c.as_ref().map(|c| c.canned_messages.clone()))
+        .unwrap_or_default()
+}
+
 pub async fn send_to_radio(ipc: IPCMessage) -> Result<()> {
+    metrics::count_sent(&ipc).await;
     let trm = crate::TO_RADIO_MPSC.write().await.clone().unwrap();
     if let Err(e) = trm.clone().send(ipc).await {
         bail!(e);
     }
     Ok(())
 }
+
+/// Builds a packet for everyone listening on a channel.  Broadcasts aren't acked, so we don't ask.
+pub fn broadcast_packet(channel: u32, portnum: PortNum, payload: Vec<u8>) -> MeshPacket {
+    MeshPacket {
+        to: u32::MAX,
+        channel,
+        id: meshtastic::utils::generate_rand_id(),
+        payload_variant: Some(mesh_packet::PayloadVariant::Decoded(Data {
+            portnum: i32::from(portnum),
+            payload,
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+}
+
+/// A position as (latitude, longitude) in degrees, or None if the node hasn't got a fix.
+pub fn position_degrees(position: &Position) -> Option<(f64, f64)> {
+    if position.latitude_i() == 0 && position.longitude_i() == 0 {
+        return None;
+    }
+    Some((
+        position.latitude_i() as f64 * GPS_PRECISION_FACTOR as f64,
+        position.longitude_i() as f64 * GPS_PRECISION_FACTOR as f64,
+    ))
+}